@@ -0,0 +1,265 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use anyhow::Context;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use time::Date;
+
+use crate::{
+    date::date_serde,
+    product::{price_serde, Price, ProductHistory},
+    storage::load_history,
+    stores::Store,
+};
+
+mod sinks;
+
+use sinks::SinkConfig;
+
+/// Condition on a watched product's price
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(tag = "rule", rename_all = "camelCase")]
+enum Rule {
+    /// Current price is at or below `price`
+    PriceAtMost { price: f64 },
+    /// Price dropped by at least `percent` percent on the evaluated day
+    DropAtLeast { percent: f64 },
+    /// Unit price fell below the lowest unit price ever seen on the evaluated day
+    UnitPriceBelowAllTimeLow,
+}
+
+impl Rule {
+    /// Description of why the rule fired, or `None` if it didn't. Only price changes on `day`
+    /// trigger the drop and all-time low rules so they don't fire again on every run.
+    fn check(&self, product: &ProductHistory, day: Date) -> Option<String> {
+        let history = product.price_history();
+        let current = history.first();
+        let changed_today = current.date() == day;
+        match *self {
+            Rule::PriceAtMost { price } => (current.price() <= Price::from(price))
+                .then(|| format!("price {} is at most {price:.2}", current.price())),
+            Rule::DropAtLeast { percent } => {
+                let previous = history.get(1)?;
+                let change = current.price().change_from(previous.price()) * 100.0;
+                (changed_today && -change >= percent).then(|| {
+                    format!(
+                        "price dropped {:.0}% from {} to {}",
+                        -change,
+                        previous.price(),
+                        current.price()
+                    )
+                })
+            }
+            Rule::UnitPriceBelowAllTimeLow => {
+                let info = product.product_info();
+                let unit_price = |price: Price| price.per_unit(info.quantity(), info.unit());
+                let current_unit_price = unit_price(current.price())?;
+                let low = history
+                    .iter()
+                    .skip(1)
+                    .filter_map(|s| unit_price(s.price()))
+                    .min()?;
+                (changed_today && current_unit_price < low).then(|| {
+                    format!("unit price {current_unit_price} is below the all-time low of {low}")
+                })
+            }
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct WatchedProduct {
+    store: Store,
+    id: i64,
+    rules: Vec<Rule>,
+}
+
+/// Products to watch, the rules to check for them and where to send alerts
+#[derive(Debug, Deserialize)]
+pub(crate) struct Watchlist {
+    products: Vec<WatchedProduct>,
+    #[serde(default)]
+    sinks: Vec<SinkConfig>,
+}
+
+impl Watchlist {
+    pub(crate) fn load(path: &Path) -> anyhow::Result<Self> {
+        let fpath = path.to_string_lossy();
+        let file = File::open(path).with_context(|| format!("Failed to open watchlist {fpath}"))?;
+        serde_json::from_reader(BufReader::new(file))
+            .with_context(|| format!("Failed to load watchlist from {fpath}"))
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct Alert {
+    store: Store,
+    id: i64,
+    name: String,
+    #[serde(with = "price_serde")]
+    price: Price,
+    #[serde(with = "date_serde")]
+    date: Date,
+    reason: String,
+}
+
+impl std::fmt::Display for Alert {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} ({}): {}",
+            self.store, self.id, self.name, self.reason
+        )
+    }
+}
+
+/// Checks all rules of the watchlist against the latest prices. The evaluated day is the most
+/// recent price change in the history, i.e. the day of the last analysis.
+pub(crate) fn evaluate(watchlist: &Watchlist, products: &[ProductHistory]) -> Vec<Alert> {
+    let Some(day) = products
+        .iter()
+        .map(|p| p.price_history().first().date())
+        .max()
+    else {
+        return Vec::new();
+    };
+    let lookup: HashMap<(Store, i64), &ProductHistory> =
+        products.iter().map(|p| ((p.store(), p.id()), p)).collect();
+
+    let mut alerts = Vec::new();
+    for watched in watchlist.products.iter() {
+        let Some(product) = lookup.get(&(watched.store, watched.id)) else {
+            warn!(
+                "Watched product {} of {} is not in the history",
+                watched.id, watched.store
+            );
+            continue;
+        };
+        for rule in watched.rules.iter() {
+            if let Some(reason) = rule.check(product, day) {
+                alerts.push(Alert {
+                    store: product.store(),
+                    id: product.id(),
+                    name: product.product_info().name().to_string(),
+                    price: product.price_history().first().price(),
+                    date: day,
+                    reason,
+                });
+            }
+        }
+    }
+    alerts
+}
+
+/// Evaluates the watchlist at `watchlist_path` and sends any alerts to its sinks
+pub fn do_alerts(output_dir: &Path, watchlist_path: &Path) -> anyhow::Result<()> {
+    let watchlist = Watchlist::load(watchlist_path)?;
+    let products = load_history(output_dir)?;
+    let alerts = evaluate(&watchlist, &products);
+    info!(
+        "{} alerts for {} watched products",
+        alerts.len(),
+        watchlist.products.len()
+    );
+    if alerts.is_empty() {
+        return Ok(());
+    }
+
+    // Stdout is the default so alerts never disappear silently
+    let default_sinks = [SinkConfig::Stdout];
+    let sinks = if watchlist.sinks.is_empty() {
+        &default_sinks[..]
+    } else {
+        &watchlist.sinks[..]
+    };
+    for sink in sinks {
+        sink.build()
+            .send(&alerts)
+            .with_context(|| format!("Failed to send alerts to {sink:?}"))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+    use time::Month;
+
+    use super::*;
+    use crate::product::ProductInfo;
+    use crate::unit::Unit;
+
+    fn day(day: u8) -> Date {
+        Date::from_calendar_date(2024, Month::January, day).unwrap()
+    }
+
+    fn product(id: i64, prices: &[(Date, f64)]) -> ProductHistory {
+        let info = ProductInfo::new(
+            id,
+            String::from("Milk"),
+            String::new(),
+            None,
+            None,
+            None,
+            Unit::Millilitre,
+            1000.0,
+            Store::Coles,
+            None,
+        );
+        ProductHistory::with_prices(info, prices)
+    }
+
+    fn watchlist(rules: serde_json::Value) -> Watchlist {
+        serde_json::from_value(json!({
+            "products": [{"store": "coles", "id": 1, "rules": rules}],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn price_at_most() {
+        let products = vec![product(1, &[(day(2), 2.0)])];
+        let alerts = evaluate(
+            &watchlist(json!([{"rule": "priceAtMost", "price": 2.0}])),
+            &products,
+        );
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].reason, "price 2.00 is at most 2.00");
+
+        let alerts = evaluate(
+            &watchlist(json!([{"rule": "priceAtMost", "price": 1.99}])),
+            &products,
+        );
+        assert!(alerts.is_empty());
+    }
+
+    #[test]
+    fn drop_at_least() {
+        let rules = json!([{"rule": "dropAtLeast", "percent": 20}]);
+        let products = vec![product(1, &[(day(2), 1.5), (day(1), 2.0)])];
+        let alerts = evaluate(&watchlist(rules.clone()), &products);
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].reason, "price dropped 25% from 2.00 to 1.50");
+
+        // Old drops don't fire again
+        let products = vec![
+            product(1, &[(day(2), 1.5), (day(1), 2.0)]),
+            product(2, &[(day(3), 1.0)]),
+        ];
+        assert!(evaluate(&watchlist(rules), &products).is_empty());
+    }
+
+    #[test]
+    fn unit_price_below_all_time_low() {
+        let rules = json!([{"rule": "unitPriceBelowAllTimeLow"}]);
+        let products = vec![product(1, &[(day(3), 1.0), (day(2), 1.5), (day(1), 1.2)])];
+        let alerts = evaluate(&watchlist(rules.clone()), &products);
+        assert_eq!(alerts.len(), 1);
+
+        let products = vec![product(1, &[(day(3), 1.2), (day(2), 1.5), (day(1), 1.0)])];
+        assert!(evaluate(&watchlist(rules), &products).is_empty());
+    }
+}
@@ -0,0 +1,350 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context};
+use serde::Deserialize;
+
+use super::Alert;
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Destination for alerts
+pub(crate) trait AlertSink {
+    fn send(&self, alerts: &[Alert]) -> anyhow::Result<()>;
+}
+
+/// Sink as configured in the watchlist
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub(crate) enum SinkConfig {
+    Stdout,
+    JsonFile {
+        path: PathBuf,
+    },
+    Webhook {
+        url: String,
+    },
+    /// Plain SMTP without authentication, meant for a local relay
+    Smtp {
+        server: String,
+        from: String,
+        to: Vec<String>,
+        #[serde(default = "default_subject")]
+        subject: String,
+    },
+}
+
+fn default_subject() -> String {
+    String::from("Price alerts")
+}
+
+impl SinkConfig {
+    pub(crate) fn build(&self) -> Box<dyn AlertSink> {
+        match self {
+            SinkConfig::Stdout => Box::new(WriterSink::new(std::io::stdout)),
+            SinkConfig::JsonFile { path } => Box::new(JsonFileSink { path: path.clone() }),
+            SinkConfig::Webhook { url } => Box::new(WebhookSink { url: url.clone() }),
+            SinkConfig::Smtp {
+                server,
+                from,
+                to,
+                subject,
+            } => Box::new(SmtpSink {
+                server: server.clone(),
+                from: from.clone(),
+                to: to.clone(),
+                subject: subject.clone(),
+            }),
+        }
+    }
+}
+
+/// Writes one line per alert
+pub(crate) struct WriterSink<F> {
+    writer: F,
+}
+
+impl<F: Fn() -> W, W: Write> WriterSink<F> {
+    pub(crate) fn new(writer: F) -> Self {
+        Self { writer }
+    }
+}
+
+impl<F: Fn() -> W, W: Write> AlertSink for WriterSink<F> {
+    fn send(&self, alerts: &[Alert]) -> anyhow::Result<()> {
+        let mut out = (self.writer)();
+        for alert in alerts {
+            writeln!(out, "{alert}")?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+}
+
+/// Replaces the file with a JSON array of the alerts
+pub(crate) struct JsonFileSink {
+    path: PathBuf,
+}
+
+impl AlertSink for JsonFileSink {
+    fn send(&self, alerts: &[Alert]) -> anyhow::Result<()> {
+        let file = File::create(&self.path)
+            .with_context(|| format!("Failed to create {}", self.path.to_string_lossy()))?;
+        serde_json::to_writer_pretty(BufWriter::new(file), alerts)?;
+        Ok(())
+    }
+}
+
+/// POSTs the alerts as a JSON array
+pub(crate) struct WebhookSink {
+    url: String,
+}
+
+impl AlertSink for WebhookSink {
+    fn send(&self, alerts: &[Alert]) -> anyhow::Result<()> {
+        let body = serde_json::to_value(alerts)?;
+        ureq::post(&self.url)
+            .timeout(TIMEOUT)
+            .send_json(body)
+            .with_context(|| format!("Webhook {} failed", self.url))?;
+        Ok(())
+    }
+}
+
+/// Sends one email listing all alerts
+pub(crate) struct SmtpSink {
+    server: String,
+    from: String,
+    to: Vec<String>,
+    subject: String,
+}
+
+struct SmtpConnection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl SmtpConnection {
+    fn connect(server: &str) -> anyhow::Result<Self> {
+        let stream = TcpStream::connect(server)
+            .with_context(|| format!("Failed to connect to SMTP server {server}"))?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        let mut connection = Self {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+        };
+        connection.expect(220)?;
+        Ok(connection)
+    }
+
+    /// Reads a possibly multi-line reply and checks its status code
+    fn expect(&mut self, code: u16) -> anyhow::Result<()> {
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                bail!("SMTP server closed the connection");
+            }
+            let status: u16 = line
+                .get(..3)
+                .and_then(|s| s.parse().ok())
+                .ok_or_else(|| anyhow!("Invalid SMTP reply {line:?}"))?;
+            if status != code {
+                bail!("Expected SMTP status {code}, got {}", line.trim_end());
+            }
+            // "250-" continues a multi-line reply, "250 " ends it
+            if line.as_bytes().get(3) != Some(&b'-') {
+                return Ok(());
+            }
+        }
+    }
+
+    fn command(&mut self, command: &str, code: u16) -> anyhow::Result<()> {
+        write!(self.writer, "{command}\r\n")?;
+        self.expect(code)
+    }
+}
+
+impl AlertSink for SmtpSink {
+    fn send(&self, alerts: &[Alert]) -> anyhow::Result<()> {
+        let mut smtp = SmtpConnection::connect(&self.server)?;
+        smtp.command("HELO localhost", 250)?;
+        smtp.command(&format!("MAIL FROM:<{}>", self.from), 250)?;
+        for to in self.to.iter() {
+            smtp.command(&format!("RCPT TO:<{to}>"), 250)?;
+        }
+        smtp.command("DATA", 354)?;
+
+        let mut message = format!(
+            "From: {}\r\nTo: {}\r\nSubject: {}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n",
+            self.from,
+            self.to.join(", "),
+            self.subject
+        );
+        for alert in alerts {
+            let line = alert.to_string();
+            // Lines starting with a dot would end the message early, so they are escaped
+            if line.starts_with('.') {
+                message.push('.');
+            }
+            message.push_str(&line);
+            message.push_str("\r\n");
+        }
+        message.push('.');
+        smtp.command(&message, 250)?;
+        smtp.command("QUIT", 221)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    use tempfile::tempdir;
+    use time::{Date, Month};
+
+    use super::*;
+    use crate::stores::Store;
+
+    fn alerts() -> Vec<Alert> {
+        vec![Alert {
+            store: Store::Coles,
+            id: 1,
+            name: String::from("Milk"),
+            price: 1.5.into(),
+            date: Date::from_calendar_date(2024, Month::January, 1).unwrap(),
+            reason: String::from("price dropped"),
+        }]
+    }
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn writer() {
+        let buffer = SharedBuffer::default();
+        let sink = WriterSink::new(|| buffer.clone());
+        sink.send(&alerts()).unwrap();
+        let out = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(out, "coles 1 (Milk): price dropped\n");
+    }
+
+    #[test]
+    fn json_file() {
+        let tmpdir = tempdir().unwrap();
+        let path = tmpdir.path().join("alerts.json");
+        let sink = SinkConfig::JsonFile { path: path.clone() }.build();
+        sink.send(&alerts()).unwrap();
+        let written: serde_json::Value =
+            serde_json::from_reader(File::open(path).unwrap()).unwrap();
+        assert_eq!(written[0]["reason"], "price dropped");
+        assert_eq!(written[0]["date"], "2024-01-01");
+    }
+
+    #[test]
+    fn webhook() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                if let Some(value) = line.to_lowercase().strip_prefix("content-length:") {
+                    content_length = value.trim().parse().unwrap();
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            stream
+                .write_all(b"HTTP/1.1 204 No Content\r\nContent-Length: 0\r\n\r\n")
+                .unwrap();
+            (request_line, body)
+        });
+
+        SinkConfig::Webhook { url }.build().send(&alerts()).unwrap();
+        let (request_line, body) = server.join().unwrap();
+        assert!(request_line.starts_with("POST /hook "));
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body[0]["id"], 1);
+    }
+
+    #[test]
+    fn smtp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = listener.local_addr().unwrap().to_string();
+        let stand_in = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut received = Vec::new();
+            stream.write_all(b"220 localhost ready\r\n").unwrap();
+            let mut in_data = false;
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap() == 0 {
+                    break;
+                }
+                let line = line.trim_end().to_string();
+                received.push(line.clone());
+                let reply: &[u8] = if in_data {
+                    if line != "." {
+                        continue;
+                    }
+                    in_data = false;
+                    b"250 queued\r\n"
+                } else if line == "DATA" {
+                    in_data = true;
+                    b"354 go ahead\r\n"
+                } else if line == "QUIT" {
+                    stream.write_all(b"221 bye\r\n").unwrap();
+                    break;
+                } else if line.starts_with("HELO") {
+                    b"250-localhost\r\n250 ok\r\n"
+                } else {
+                    b"250 ok\r\n"
+                };
+                stream.write_all(reply).unwrap();
+            }
+            received
+        });
+
+        let sink = SinkConfig::Smtp {
+            server,
+            from: String::from("alerts@example.com"),
+            to: vec![String::from("me@example.com")],
+            subject: default_subject(),
+        }
+        .build();
+        sink.send(&alerts()).unwrap();
+        let received = stand_in.join().unwrap();
+        assert!(received.contains(&String::from("MAIL FROM:<alerts@example.com>")));
+        assert!(received.contains(&String::from("RCPT TO:<me@example.com>")));
+        assert!(received.contains(&String::from("Subject: Price alerts")));
+        assert!(received.contains(&String::from("coles 1 (Milk): price dropped")));
+        assert_eq!(received.last().unwrap(), "QUIT");
+    }
+}
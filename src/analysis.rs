@@ -1,19 +1,35 @@
-use std::{fs, path::Path};
+use std::{fs, io::Write, path::Path};
 
-use anyhow::Context;
-use log::debug;
+use anyhow::{bail, Context};
+use log::{debug, info};
 use strum::IntoEnumIterator;
 use time::{macros::format_description, Date};
 
 use crate::{
+    config::Config,
+    conversion::ConversionMetrics,
+    feeds::{update_feeds, FeedEvent, Listing},
+    matching::{apply_match_groups, match_products},
+    price_changes::{price_changes, PriceChangeReport},
     product::{deduplicate_products, merge_price_history},
-    storage::{load_daily_snapshot, load_history, save_result, save_to_site},
+    reprocess::ReprocessReport,
+    search::SearchIndex,
+    stats::price_stats,
+    storage::{
+        get_history_backup_path, get_history_path, history_modified, load_daily_snapshot,
+        load_history, load_match_overrides, partial_stores, read_history, rotate_history_backups,
+        save_matches, save_price_changes, save_price_stats, save_result, save_search_index,
+        save_to_site, write_atomic,
+    },
     stores::Store,
 };
 
 pub enum AnalysisType {
     Day(Date),
     History,
+    /// Rebuilds the history from all snapshots, so products get the quantities and categories of
+    /// the current conversion rules
+    Reprocess,
 }
 
 impl AnalysisType {
@@ -23,7 +39,9 @@ impl AnalysisType {
         store_filter: Option<Store>,
     ) -> anyhow::Result<Vec<Date>> {
         match self {
-            AnalysisType::History => history_days(output_dir, store_filter),
+            AnalysisType::History | AnalysisType::Reprocess => {
+                history_days(output_dir, store_filter)
+            }
             AnalysisType::Day(day) => Ok(vec![day]),
         }
     }
@@ -52,13 +70,12 @@ fn history_days(output_dir: &Path, store_filter: Option<Store>) -> anyhow::Resul
                 }
             };
             let file_name = file_name.to_string_lossy();
-            let mut splits = file_name.split('.');
-            let basename = match splits.next() {
-                Some(b) => b,
-                None => {
-                    return Err(anyhow::Error::msg(format!(
-                        "File {file_name:?} can't be split"
-                    )));
+            // Sync manifests and temporary files left by an interrupted write aren't snapshots
+            let basename = match file_name.strip_suffix(".json.gz") {
+                Some(basename) if !file_name.starts_with('.') => basename,
+                _ => {
+                    debug!("Skipping path {path:?} since it is not a snapshot");
+                    continue;
                 }
             };
 
@@ -78,31 +95,111 @@ fn history_days(output_dir: &Path, store_filter: Option<Store>) -> anyhow::Resul
     Ok(entries)
 }
 
+/// What an analysis run did, for the run summary
+pub struct AnalysisSummary {
+    pub(crate) conversions: Vec<ConversionMetrics>,
+    pub price_changes: usize,
+}
+
 pub fn do_analysis(
     analysis_type: AnalysisType,
     store: Option<Store>,
     compress: bool,
-    output_dir: &Path,
-    data_dir: &Path,
-) -> anyhow::Result<()> {
+    config: &Config,
+    feeds_base_url: Option<&str>,
+) -> anyhow::Result<AnalysisSummary> {
+    let output_dir = config.output_dir.as_path();
     let previous_products = match load_history(output_dir) {
         Ok(products) => products,
         Err(e) => match analysis_type {
-            AnalysisType::History => Vec::new(),
+            AnalysisType::History | AnalysisType::Reprocess => Vec::new(),
             AnalysisType::Day(_) => return Err(e),
         },
     };
 
-    let mut products = previous_products;
+    let reprocess = matches!(analysis_type, AnalysisType::Reprocess);
+    // Only the products of the reprocessed stores are rebuilt, the others are kept as they are
+    let (mut products, previous_products): (Vec<_>, Vec<_>) = match reprocess {
+        true => previous_products
+            .into_iter()
+            .partition(|p| store.is_some_and(|s| s != p.store())),
+        false => (previous_products, Vec::new()),
+    };
+    // Every product would be new or delisted on some day of the rebuilt history
+    let feeds_base_url = feeds_base_url.filter(|_| !reprocess);
     // todo: make this return files instead of dates
-    for day in analysis_type.days(output_dir, store)? {
-        let new_products = load_daily_snapshot(output_dir, day, store)
+    let days = analysis_type.days(output_dir, store)?;
+    let mut feed_events = Vec::new();
+    let mut conversions = Vec::new();
+    for day in days.iter().copied() {
+        let (new_products, metrics) = load_daily_snapshot(config, day, store)
             .context(format!("Failed to load snapshot for day {day}"))?;
+        conversions.extend(metrics);
         let new_products = deduplicate_products(new_products);
-        products = merge_price_history(products, new_products, store);
+        // Delisted products are dropped by the merge, so they are found by comparing listings
+        let before = feeds_base_url.is_some().then(|| Listing::new(&products));
+        let partial = partial_stores(output_dir, day)?;
+        products = merge_price_history(products, new_products, store, &partial);
+        if let Some(before) = before {
+            feed_events.extend(before.events(&Listing::new(&products), day));
+        }
+    }
+    if reprocess {
+        // The merge doesn't keep an order, but a rebuilt history should be the same every time
+        products.sort_by_key(|p| (p.store(), p.id()));
+        let report = ReprocessReport::new(&previous_products, &products);
+        report.write_table(std::io::stdout().lock())?;
+    }
+
+    let overrides = load_match_overrides(output_dir)?;
+    let groups = match_products(&products, &overrides);
+    apply_match_groups(&mut products, &groups);
+    save_matches(&groups, output_dir)?;
+
+    let changes = price_changes(&products, &days);
+    info!("{} price changes in this run", changes.len());
+    save_price_changes(&PriceChangeReport::new(&days, &changes), output_dir)?;
+    if let Some(base_url) = feeds_base_url {
+        let drops = changes.iter().filter(|c| c.percent() < 0.0);
+        feed_events.extend(drops.map(FeedEvent::from));
+        update_feeds(&products, feed_events, &days, store, base_url, output_dir)?;
+    }
+    if let Some(as_of) = days.last() {
+        save_price_stats(&price_stats(&products, *as_of), output_dir)?;
+    }
+
+    save_result(&products, output_dir, config.history_backups)?;
+    let (index, entries) = SearchIndex::build(&products, history_modified(output_dir)?);
+    save_search_index(&index, &entries, output_dir)?;
+    save_to_site(&products, &config.data_dir, compress)?;
+    Ok(AnalysisSummary {
+        conversions,
+        price_changes: changes.len(),
+    })
+}
+
+/// Replaces the canonical history with its `backup`th most recent backup, e.g. after an analysis
+/// saved a broken history. The replaced history is kept as backup 1, shifting the others along
+/// the same way saving a new history does.
+pub fn do_restore(output_dir: &Path, backup: usize, backups: usize) -> anyhow::Result<()> {
+    let source = get_history_backup_path(output_dir, backup);
+    if !source.exists() {
+        bail!("There is no backup {}", source.to_string_lossy());
     }
-    save_result(&products, output_dir)?;
-    save_to_site(&products, data_dir, compress)?;
+    // Make sure the backup is usable before it replaces anything
+    let products = read_history(&source)?;
+    let restored = fs::read(&source)?;
+    // The replaced history becomes backup 1 so restoring it again undoes the restore
+    rotate_history_backups(output_dir, backups.max(1))?;
+    write_atomic(&get_history_path(output_dir), |file| {
+        file.write_all(&restored)?;
+        Ok(())
+    })?;
+    info!(
+        "Restored history with {} products from {}",
+        products.len(),
+        source.to_string_lossy()
+    );
     Ok(())
 }
 
@@ -119,9 +216,15 @@ mod test_do_analysis {
     use tempfile::tempdir;
     use time::{Date, Month};
 
-    use crate::{analysis::AnalysisType, storage::load_history, stores::Store};
+    use crate::{
+        analysis::AnalysisType,
+        config::Config,
+        product::ProductHistory,
+        storage::{load_history, save_result},
+        stores::Store,
+    };
 
-    use super::{do_analysis, history_days};
+    use super::{do_analysis, do_restore, history_days};
 
     fn init() {
         let _ = env_logger::builder()
@@ -147,8 +250,12 @@ mod test_do_analysis {
             AnalysisType::Day(day),
             store,
             compress,
-            output_dir.path(),
-            data_dir.path(),
+            &Config {
+                output_dir: output_dir.path().to_path_buf(),
+                data_dir: data_dir.path().to_path_buf(),
+                ..Config::default()
+            },
+            None,
         );
         assert!(result.is_err());
     }
@@ -225,12 +332,26 @@ mod test_do_analysis {
             AnalysisType::Day(day),
             Some(store),
             compress,
-            output_dir.path(),
-            data_dir.path(),
+            &Config {
+                output_dir: output_dir.path().to_path_buf(),
+                data_dir: data_dir.path().to_path_buf(),
+                ..Config::default()
+            },
+            Some("https://example.com"),
         )
         .expect("analysis should succeed");
 
         // validate result
+        let failures = output_dir
+            .path()
+            .join("conversion-failures/coles/2024-01-02.jsonl");
+        assert!(failures.exists(), "should write conversion failure report");
+        assert!(output_dir.path().join("price-changes.json").exists());
+        assert!(output_dir.path().join("price-stats.json.gz").exists());
+        let drops =
+            std::fs::read_to_string(output_dir.path().join("feeds/coles/drops.xml")).unwrap();
+        assert!(drops.contains("<link href=\"https://example.com/products/coles/1.html\"/>"));
+        assert!(drops.contains("Brand name Product name: 12.00 → 6.70 (-44%)"));
         let products = load_history(output_dir.path()).expect("should contain history");
         let products = serde_json::to_value(products).unwrap();
         assert_eq!(
@@ -240,6 +361,7 @@ mod test_do_analysis {
                     "id": 1,
                     "name": "Brand name Product name",
                     "description": "BRAND NAME PRODUCT NAME 150G",
+                    "brand": "Brand name",
                     "isWeighted": false,
                     "unit": "g",
                     "quantity": 150.0,
@@ -249,6 +371,7 @@ mod test_do_analysis {
                         { "date": "2024-01-01", "price": 12.0 },
                     ],
                     "category": "00",
+                    "unitPrice": 44.67,
                 }
             ]),
         );
@@ -336,8 +459,12 @@ mod test_do_analysis {
             AnalysisType::History,
             Some(store),
             compress,
-            output_dir.path(),
-            data_dir.path(),
+            &Config {
+                output_dir: output_dir.path().to_path_buf(),
+                data_dir: data_dir.path().to_path_buf(),
+                ..Config::default()
+            },
+            None,
         )
         .expect("analysis should succeed");
 
@@ -353,6 +480,149 @@ mod test_do_analysis {
         );
     }
 
+    #[test]
+    fn reprocess() {
+        init();
+        let output_dir = tempdir().unwrap();
+        let data_dir = tempdir().unwrap();
+
+        // history converted by older rules, with a product no snapshot has
+        let latest_canoncial = json!([
+            {
+                "id": 1,
+                "name": "Brand name Product name",
+                "description": "BRAND NAME PRODUCT NAME 150G",
+                "priceHistory": [
+                  { "date": "2024-01-02", "price": 7.0 },
+                  { "date": "2024-01-01", "price": 6.7 }
+                ],
+                "isWeighted": false,
+                "unit": "ea",
+                "quantity": 1.0,
+                "store": "coles"
+            },
+            {
+                "id": 2,
+                "name": "Other product",
+                "description": "OTHER PRODUCT",
+                "priceHistory": [{ "date": "2024-01-01", "price": 1.0 }],
+                "isWeighted": false,
+                "unit": "ea",
+                "quantity": 1.0,
+                "store": "coles"
+            }
+        ]);
+        write_compressed(
+            latest_canoncial.to_string().as_bytes(),
+            &output_dir.path().join("latest-canonical.json.gz"),
+        );
+
+        let day = Date::from_calendar_date(2024, Month::January, 1).unwrap();
+        let snapshot = json!(
+            [
+              {
+                "seoToken": "category-slug",
+                "Products": [
+                  {
+                    "_type": "PRODUCT",
+                    "id": 1,
+                    "adId": null,
+                    "name": "Product name",
+                    "brand": "Brand name",
+                    "description": "BRAND NAME PRODUCT NAME 150G",
+                    "size": "150g",
+                    "pricing": {
+                      "now": 6.7,
+                      "unit": {
+                        "isWeighted": false
+                      }
+                    },
+                    "onlineHeirs": [
+                      {
+                        "category": "",
+                      },
+                    ],
+                  }
+                ]
+              }
+            ]
+        );
+        let store = Store::Coles;
+        let dst_dir = output_dir.path().join(store.to_string());
+        create_dir_all(&dst_dir).unwrap();
+        write_compressed(
+            snapshot.to_string().as_bytes(),
+            &dst_dir.join(format!("{day}.json.gz")),
+        );
+
+        do_analysis(
+            AnalysisType::Reprocess,
+            Some(store),
+            false,
+            &Config {
+                output_dir: output_dir.path().to_path_buf(),
+                data_dir: data_dir.path().to_path_buf(),
+                ..Config::default()
+            },
+            None,
+        )
+        .expect("reprocessing should succeed");
+
+        // Rebuilt only from the snapshot, with the quantity of the current parser
+        let products = load_history(output_dir.path()).expect("should contain history");
+        let products = serde_json::to_value(products).unwrap();
+        assert_eq!(products.as_array().unwrap().len(), 1);
+        assert_eq!(products[0]["unit"], "g");
+        assert_eq!(products[0]["quantity"], 150.0);
+        assert_eq!(
+            products[0]["priceHistory"],
+            json!([{"date": "2024-01-01", "price": 6.7}])
+        );
+    }
+
+    #[test]
+    fn restore() {
+        let output_dir = tempdir().unwrap();
+        let output_dir = output_dir.path();
+        save_result(&[ProductHistory::default()], output_dir, 1).unwrap();
+        save_result(&[], output_dir, 1).unwrap();
+        assert!(
+            do_restore(output_dir, 2, 1).is_err(),
+            "there is only one backup"
+        );
+
+        // A truncated history is replaced by the last good one
+        std::fs::write(output_dir.join("latest-canonical.json.gz"), b"").unwrap();
+        do_restore(output_dir, 1, 1).expect("restore should succeed");
+        assert_eq!(load_history(output_dir).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn restore_twice_can_be_undone() {
+        let output_dir = tempdir().unwrap();
+        let output_dir = output_dir.path();
+        let history = |len| {
+            (0..len)
+                .map(|_| ProductHistory::default())
+                .collect::<Vec<_>>()
+        };
+        save_result(&history(1), output_dir, 3).unwrap();
+        save_result(&history(2), output_dir, 3).unwrap();
+        save_result(&history(3), output_dir, 3).unwrap();
+
+        do_restore(output_dir, 1, 3).unwrap();
+        assert_eq!(load_history(output_dir).unwrap().len(), 2);
+        // The oldest backup moved along by one, from 2 to 3
+        do_restore(output_dir, 3, 3).unwrap();
+        assert_eq!(load_history(output_dir).unwrap().len(), 1);
+
+        // Each restore kept what it replaced, so both can be walked back
+        do_restore(output_dir, 1, 3).unwrap();
+        assert_eq!(load_history(output_dir).unwrap().len(), 2);
+        do_restore(output_dir, 3, 3).unwrap();
+        assert_eq!(load_history(output_dir).unwrap().len(), 3);
+    }
+
     #[test]
     fn history_days_skips_folder() {
         init();
@@ -369,4 +639,20 @@ mod test_do_analysis {
             "Should have skipped folders and returned empty result but got {days:?}"
         );
     }
+
+    #[test]
+    fn history_days_skips_other_files() {
+        let output_dir = tempdir().unwrap();
+        let store_dir = output_dir.path().join(Store::Coles.to_string());
+        create_dir_all(&store_dir).unwrap();
+        File::create(store_dir.join("2024-01-01.json.gz")).unwrap();
+        File::create(store_dir.join("2024-01-01.manifest.json")).unwrap();
+        // Left behind by a crash while saving a snapshot
+        File::create(store_dir.join(".2024-01-02.json.gz.tmp")).unwrap();
+        let days = history_days(output_dir.path(), Some(Store::Coles)).unwrap();
+        assert_eq!(
+            days,
+            vec![Date::from_calendar_date(2024, Month::January, 1).unwrap()]
+        );
+    }
 }
@@ -1,3 +1,4 @@
+use std::cell::Cell;
 use std::fs::{create_dir_all, read_to_string, File};
 use std::io::prelude::*;
 use std::path::PathBuf;
@@ -6,11 +7,26 @@ pub(crate) type FetchCallback<'a> = &'a dyn Fn() -> anyhow::Result<String>;
 
 pub(crate) struct FsCache {
     path: PathBuf,
+    // Pages loaded from the backend and from the cache, for the run summary
+    fetched: Cell<usize>,
+    cached: Cell<usize>,
 }
 
 impl FsCache {
     pub(crate) fn new(path: PathBuf) -> FsCache {
-        FsCache { path }
+        FsCache {
+            path,
+            fetched: Cell::new(0),
+            cached: Cell::new(0),
+        }
+    }
+
+    pub(crate) fn fetched(&self) -> usize {
+        self.fetched.get()
+    }
+
+    pub(crate) fn cached(&self) -> usize {
+        self.cached.get()
     }
 
     fn store(&self, path: &PathBuf, resp: &str) -> std::io::Result<()> {
@@ -37,11 +53,13 @@ impl FsCache {
         match path.exists() {
             true => {
                 log::debug!("get_or_fetch: Loading file \"{file}\" from cache");
+                self.cached.set(self.cached.get() + 1);
                 Ok(self.load(&path)?)
             }
             false => {
                 log::debug!("get_or_fetch: Loading file \"{file}\" from backend");
                 let resp = fetch()?;
+                self.fetched.set(self.fetched.get() + 1);
                 self.store(&path, &resp)?;
                 Ok(resp)
             }
@@ -92,6 +110,7 @@ pub(crate) mod test {
             .get_or_fetch(String::from("test"), &|| Ok(String::from("2")))
             .unwrap();
         assert_eq!(res, "1");
+        assert_eq!((cache.fetched(), cache.cached()), (1, 1));
     }
 
     #[test]
@@ -1,3 +1,4 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -6,6 +7,45 @@ pub enum Category {
     MeatAndSeafood(MeatAndSeafood),
 }
 
+impl Category {
+    /// Two digit code of the category as used in the canonical output
+    pub(crate) fn code(&self) -> &'static str {
+        match self {
+            // Fruit & Veg
+            Category::FruitAndVeg(sub) => match sub {
+                FruitAndVeg::Fruit => "00",
+                FruitAndVeg::Veg => "01",
+                FruitAndVeg::SaladAndHerbs => "02",
+                FruitAndVeg::NutsAndDriedFruits => "03",
+            },
+
+            // Meat & Seafood
+            Category::MeatAndSeafood(sub) => match sub {
+                MeatAndSeafood::Poultry => "30",
+                MeatAndSeafood::Meat => "31",
+                MeatAndSeafood::Seafood => "32",
+            },
+        }
+    }
+
+    /// Name of the category as shown to people
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Category::FruitAndVeg(sub) => match sub {
+                FruitAndVeg::Fruit => "Fruit",
+                FruitAndVeg::Veg => "Vegetables",
+                FruitAndVeg::SaladAndHerbs => "Salad & Herbs",
+                FruitAndVeg::NutsAndDriedFruits => "Nuts & Dried Fruits",
+            },
+            Category::MeatAndSeafood(sub) => match sub {
+                MeatAndSeafood::Poultry => "Poultry",
+                MeatAndSeafood::Meat => "Meat",
+                MeatAndSeafood::Seafood => "Seafood",
+            },
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum FruitAndVeg {
     Fruit,
@@ -21,9 +61,11 @@ pub enum MeatAndSeafood {
     Seafood,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
 pub(crate) struct CategoryCode {
+    /// Two digit code, e.g. "31" for meat
     #[serde(with = "cat_code_serde")]
+    #[schemars(with = "String")]
     pub category: Category,
 }
 
@@ -42,23 +84,7 @@ mod cat_code_serde {
     where
         S: Serializer,
     {
-        let s = match category {
-            // Fruit & Veg
-            Category::FruitAndVeg(sub) => match sub {
-                FruitAndVeg::Fruit => "00",
-                FruitAndVeg::Veg => "01",
-                FruitAndVeg::SaladAndHerbs => "02",
-                FruitAndVeg::NutsAndDriedFruits => "03",
-            },
-
-            // Meat & Seafood
-            Category::MeatAndSeafood(sub) => match sub {
-                MeatAndSeafood::Poultry => "30",
-                MeatAndSeafood::Meat => "31",
-                MeatAndSeafood::Seafood => "32",
-            },
-        };
-        serializer.serialize_str(s)
+        serializer.serialize_str(category.code())
     }
 
     pub fn deserialize<'de, D>(deserializer: D) -> Result<Category, D::Error>
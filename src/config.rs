@@ -0,0 +1,474 @@
+//! Runtime settings, layered from built-in defaults, a TOML file, environment variables and
+//! command line flags, each overriding the previous one.
+use std::env;
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+
+use time::{Date, OffsetDateTime};
+
+use anyhow::{anyhow, bail, Context};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::conversion::{CategoryFilter, ConversionThresholds};
+use crate::date::{date_in, timezone};
+use crate::stores::Store;
+
+/// File that is read from the working directory if no other config file is given
+pub const DEFAULT_CONFIG_FILE: &str = "hotprices.toml";
+/// Prefix of environment variables overriding settings. Sections are separated with a double
+/// underscore, e.g. `HOTPRICES_COLES__STORE_ID`.
+const ENV_PREFIX: &str = "HOTPRICES_";
+/// Environment variable with the path of the config file
+const ENV_CONFIG_FILE: &str = "HOTPRICES_CONFIG";
+
+const USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/87.0.4280.88 Safari/537.36";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub output_dir: PathBuf,
+    pub cache_path: PathBuf,
+    pub data_dir: PathBuf,
+    /// Timezone whose date is used to name snapshots and cache directories
+    pub timezone: String,
+    /// Maximum share of failed product conversions for stores without their own threshold
+    pub conversion_threshold: f64,
+    /// Number of previous canonical histories kept as `latest-canonical.json.gz.1` and so on
+    pub history_backups: usize,
+    pub validation: ValidationConfig,
+    pub coles: ColesConfig,
+    pub woolies: WooliesConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            output_dir: PathBuf::from("output"),
+            cache_path: PathBuf::from("cache"),
+            data_dir: PathBuf::from("static/data"),
+            timezone: String::from("Australia/Sydney"),
+            // If more than 5% of conversions fail then it should be an error
+            conversion_threshold: 0.05,
+            history_backups: 3,
+            validation: ValidationConfig::default(),
+            coles: ColesConfig::default(),
+            woolies: WooliesConfig::default(),
+        }
+    }
+}
+
+/// What happens to a snapshot that fails validation after it was fetched
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ValidationAction {
+    /// Only log the issues, a snapshot with failed pages is still marked as partial
+    Warn,
+    /// Save the snapshot but mark it as partial, so missing products aren't taken as delisted
+    Partial,
+    /// Don't save the snapshot
+    Block,
+}
+
+/// Checks of the product counts of a fetched snapshot, per category
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ValidationConfig {
+    /// Maximum share of products the store reported for a category that may be missing
+    pub max_missing: f64,
+    /// Maximum share by which a category may shrink compared to the previous snapshot
+    pub max_drop: f64,
+    pub action: ValidationAction,
+}
+
+impl Default for ValidationConfig {
+    fn default() -> Self {
+        Self {
+            max_missing: 0.05,
+            max_drop: 0.2,
+            action: ValidationAction::Partial,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ColesConfig {
+    pub base_url: String,
+    pub user_agent: String,
+    pub store_id: String,
+    /// How many pages of a category may fail before the sync fails. It's okay if *some*
+    /// categories don't return 100% of products.
+    pub error_count_max: i32,
+    /// SEO tokens of categories that are scraped and converted even if they are excluded
+    pub include_categories: Vec<String>,
+    /// SEO tokens of categories that aren't scraped or converted unless they are included
+    pub exclude_categories: Vec<String>,
+    /// SEO tokens of the only categories that are scraped and converted, all if empty
+    pub only_categories: Vec<String>,
+    /// Load the details of every product for its barcode, which search results almost never
+    /// have. Takes a request per product, which are cached like category pages.
+    pub fetch_barcodes: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub conversion_threshold: Option<f64>,
+}
+
+impl Default for ColesConfig {
+    fn default() -> Self {
+        Self {
+            base_url: String::from("https://www.coles.com.au"),
+            user_agent: String::from(USER_AGENT),
+            store_id: String::from("0584"),
+            error_count_max: 2,
+            include_categories: Vec::new(),
+            exclude_categories: vec![String::from("down-down"), String::from("back-to-school")],
+            only_categories: Vec::new(),
+            fetch_barcodes: false,
+            conversion_threshold: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WooliesConfig {
+    pub base_url: String,
+    pub user_agent: String,
+    /// Products requested per page of a category
+    pub page_size: u32,
+    /// Node ids or descriptions of categories that are scraped and converted even if they are
+    /// excluded
+    pub include_categories: Vec<String>,
+    /// Node ids or descriptions of categories that aren't scraped or converted unless they are
+    /// included
+    pub exclude_categories: Vec<String>,
+    /// Node ids or descriptions of the only categories that are scraped and converted, all if
+    /// empty
+    pub only_categories: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub conversion_threshold: Option<f64>,
+}
+
+impl Default for WooliesConfig {
+    fn default() -> Self {
+        Self {
+            base_url: String::from("https://www.woolworths.com.au"),
+            user_agent: String::from(USER_AGENT),
+            page_size: 36,
+            include_categories: Vec::new(),
+            exclude_categories: vec![
+                // Ads
+                String::from("specialsgroup"),
+                // Expect duplicates
+                String::from("Front of Store"),
+                // Skip alcohol because it has weird sizing and isn't that important
+                String::from("1_8E4DA6F"),
+                String::from("Beer, Wine & Spirits"),
+            ],
+            only_categories: Vec::new(),
+            conversion_threshold: None,
+        }
+    }
+}
+
+impl ColesConfig {
+    pub(crate) fn category_filter(&self) -> CategoryFilter {
+        CategoryFilter::new(
+            self.include_categories.clone(),
+            self.exclude_categories.clone(),
+        )
+        .only(self.only_categories.clone())
+    }
+}
+
+impl WooliesConfig {
+    pub(crate) fn category_filter(&self) -> CategoryFilter {
+        CategoryFilter::new(
+            self.include_categories.clone(),
+            self.exclude_categories.clone(),
+        )
+        .only(self.only_categories.clone())
+    }
+}
+
+impl Config {
+    /// Loads the defaults, overridden by the config file and then by `HOTPRICES_*` environment
+    /// variables. Without an explicit `path` the file named by `HOTPRICES_CONFIG` is used, or
+    /// `hotprices.toml` if it exists.
+    pub fn load(path: Option<&Path>) -> anyhow::Result<Self> {
+        let path = path
+            .map(PathBuf::from)
+            .or_else(|| env::var_os(ENV_CONFIG_FILE).map(PathBuf::from));
+        let file = match path {
+            Some(path) => Some(read_config_file(&path)?),
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                Some(read_config_file(Path::new(DEFAULT_CONFIG_FILE))?)
+            }
+            None => None,
+        };
+        Self::from_layers(file, env::vars())
+    }
+
+    fn from_layers(
+        file: Option<toml::Table>,
+        vars: impl Iterator<Item = (String, String)>,
+    ) -> anyhow::Result<Self> {
+        let mut table = toml::Table::try_from(Self::default())?;
+        if let Some(file) = file {
+            merge(&mut table, file);
+        }
+        apply_env(&mut table, vars)?;
+        let config: Self = table.try_into().context("Invalid configuration")?;
+        timezone(&config.timezone)?;
+        check_share("conversion_threshold", Some(config.conversion_threshold))?;
+        check_share(
+            "coles.conversion_threshold",
+            config.coles.conversion_threshold,
+        )?;
+        check_share(
+            "woolies.conversion_threshold",
+            config.woolies.conversion_threshold,
+        )?;
+        check_share(
+            "validation.max_missing",
+            Some(config.validation.max_missing),
+        )?;
+        check_share("validation.max_drop", Some(config.validation.max_drop))?;
+        Ok(config)
+    }
+
+    /// Current date in the configured timezone
+    pub fn today(&self) -> anyhow::Result<Date> {
+        Ok(date_in(
+            OffsetDateTime::now_utc(),
+            timezone(&self.timezone)?,
+        ))
+    }
+
+    pub fn conversion_thresholds(&self) -> ConversionThresholds {
+        let mut thresholds = ConversionThresholds::new(self.conversion_threshold);
+        if let Some(threshold) = self.coles.conversion_threshold {
+            thresholds.set_store(Store::Coles, threshold);
+        }
+        if let Some(threshold) = self.woolies.conversion_threshold {
+            thresholds.set_store(Store::Woolies, threshold);
+        }
+        thresholds
+    }
+
+    /// Sets the conversion threshold of a single store, or the default if `store` is `None`
+    pub fn set_conversion_threshold(&mut self, store: Option<Store>, threshold: f64) {
+        match store {
+            None => self.conversion_threshold = threshold,
+            Some(Store::Coles) => self.coles.conversion_threshold = Some(threshold),
+            Some(Store::Woolies) => self.woolies.conversion_threshold = Some(threshold),
+        }
+    }
+
+    pub(crate) fn category_filter(&self, store: Store) -> CategoryFilter {
+        match store {
+            Store::Coles => self.coles.category_filter(),
+            Store::Woolies => self.woolies.category_filter(),
+        }
+    }
+
+    /// Adds categories of a store to its include, exclude and only lists
+    pub fn add_category_filters(
+        &mut self,
+        store: Store,
+        include: Vec<String>,
+        exclude: Vec<String>,
+        only: Vec<String>,
+    ) {
+        let (includes, excludes, onlys) = match store {
+            Store::Coles => (
+                &mut self.coles.include_categories,
+                &mut self.coles.exclude_categories,
+                &mut self.coles.only_categories,
+            ),
+            Store::Woolies => (
+                &mut self.woolies.include_categories,
+                &mut self.woolies.exclude_categories,
+                &mut self.woolies.only_categories,
+            ),
+        };
+        includes.extend(include);
+        excludes.extend(exclude);
+        onlys.extend(only);
+    }
+
+    /// Effective configuration as TOML, e.g. for `config show`
+    pub fn to_toml(&self) -> anyhow::Result<String> {
+        Ok(toml::to_string_pretty(self)?)
+    }
+}
+
+fn read_config_file(path: &Path) -> anyhow::Result<toml::Table> {
+    let fpath = path.to_string_lossy();
+    let content =
+        read_to_string(path).with_context(|| format!("Failed to read config file {fpath}"))?;
+    content
+        .parse()
+        .with_context(|| format!("Failed to parse config file {fpath}"))
+}
+
+/// Overrides values in `base` with the ones from `other`, merging sections key by key
+fn merge(base: &mut toml::Table, other: toml::Table) {
+    for (key, value) in other {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(other)) => merge(base, other),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Applies `HOTPRICES_*` variables to `table`. Values of string settings are taken as they are,
+/// everything else is parsed as a TOML value, e.g. `0.1` or `["a", "b"]`.
+fn apply_env(
+    table: &mut toml::Table,
+    vars: impl Iterator<Item = (String, String)>,
+) -> anyhow::Result<()> {
+    for (name, value) in vars {
+        let Some(key) = name.strip_prefix(ENV_PREFIX) else {
+            continue;
+        };
+        if name == ENV_CONFIG_FILE {
+            continue;
+        }
+        let path: Vec<String> = key.split("__").map(|s| s.to_lowercase()).collect();
+        let (last, sections) = path.split_last().expect("split returns at least one part");
+        let mut section = &mut *table;
+        for part in sections {
+            section = section
+                .entry(part.clone())
+                .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+                .as_table_mut()
+                .ok_or_else(|| anyhow!("{part} in {name} is not a config section"))?;
+        }
+        let value = match section.get(last) {
+            Some(toml::Value::String(_)) => toml::Value::String(value),
+            // Invalid values end up as strings and are reported when deserializing the config
+            _ => parse_value(&value).unwrap_or(toml::Value::String(value)),
+        };
+        section.insert(last.clone(), value);
+    }
+    Ok(())
+}
+
+/// Settings that are a share of products have to be between 0 and 1, like on the command line
+fn check_share(name: &str, value: Option<f64>) -> anyhow::Result<()> {
+    match value {
+        Some(value) if !(0.0..=1.0).contains(&value) => {
+            bail!("Setting {name} is {value} but must be between 0 and 1")
+        }
+        _ => Ok(()),
+    }
+}
+
+fn parse_value(value: &str) -> anyhow::Result<toml::Value> {
+    let table: toml::Table = format!("value = {value}").parse()?;
+    Ok(table
+        .get("value")
+        .cloned()
+        .expect("table was parsed with this key"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn vars(vars: &[(&str, &str)]) -> impl Iterator<Item = (String, String)> {
+        vars.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn defaults() {
+        let config = Config::from_layers(None, vars(&[])).unwrap();
+        assert_eq!(config.output_dir, PathBuf::from("output"));
+        assert_eq!(config.coles.store_id, "0584");
+        assert_eq!(config.woolies.page_size, 36);
+        assert_eq!(config.conversion_thresholds().for_store(Store::Coles), 0.05);
+    }
+
+    #[test]
+    fn layers() {
+        let file: toml::Table = r#"
+            output_dir = "/data/output"
+            conversion_threshold = 0.1
+
+            [coles]
+            store_id = "0123"
+            conversion_threshold = 0.2
+        "#
+        .parse()
+        .unwrap();
+        let config = Config::from_layers(
+            Some(file),
+            vars(&[
+                ("HOTPRICES_OUTPUT_DIR", "/env/output"),
+                ("HOTPRICES_COLES__ERROR_COUNT_MAX", "5"),
+                ("HOTPRICES_VALIDATION__ACTION", "block"),
+                (
+                    "HOTPRICES_WOOLIES__EXCLUDE_CATEGORIES",
+                    r#"["specialsgroup"]"#,
+                ),
+                ("HOTPRICES_CONFIG", "ignored.toml"),
+                ("PATH", "/usr/bin"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(config.output_dir, PathBuf::from("/env/output"));
+        assert_eq!(config.coles.store_id, "0123");
+        assert_eq!(config.coles.error_count_max, 5);
+        assert_eq!(config.validation.action, ValidationAction::Block);
+        // Unset settings of a section keep their defaults
+        assert_eq!(config.coles.base_url, "https://www.coles.com.au");
+        assert_eq!(config.woolies.exclude_categories, vec!["specialsgroup"]);
+        let thresholds = config.conversion_thresholds();
+        assert_eq!(thresholds.for_store(Store::Coles), 0.2);
+        assert_eq!(thresholds.for_store(Store::Woolies), 0.1);
+    }
+
+    #[test]
+    fn rejects_unknown_settings() {
+        let file: toml::Table = "output_directory = \"x\"".parse().unwrap();
+        assert!(Config::from_layers(Some(file), vars(&[])).is_err());
+        assert!(Config::from_layers(None, vars(&[("HOTPRICES_COLES__STORE", "1")])).is_err());
+        assert!(Config::from_layers(None, vars(&[("HOTPRICES_TIMEZONE", "Mars/Base")])).is_err());
+    }
+
+    #[test]
+    fn rejects_shares_out_of_range() {
+        let file: toml::Table = "conversion_threshold = 5.0".parse().unwrap();
+        assert!(Config::from_layers(Some(file), vars(&[])).is_err());
+        for (var, value) in [
+            ("HOTPRICES_WOOLIES__CONVERSION_THRESHOLD", "-0.1"),
+            ("HOTPRICES_VALIDATION__MAX_MISSING", "1.5"),
+            ("HOTPRICES_VALIDATION__MAX_DROP", "20"),
+        ] {
+            let err = Config::from_layers(None, vars(&[(var, value)])).unwrap_err();
+            assert!(err.to_string().contains("must be between 0 and 1"), "{err}");
+        }
+        let config =
+            Config::from_layers(None, vars(&[("HOTPRICES_VALIDATION__MAX_DROP", "1")])).unwrap();
+        assert_eq!(config.validation.max_drop, 1.0);
+    }
+
+    #[test]
+    fn show() {
+        let config = Config::default();
+        let shown = config.to_toml().unwrap();
+        assert!(shown.contains("output_dir = \"output\""));
+        assert!(shown.contains("[coles]"));
+        let reparsed = Config::from_layers(Some(shown.parse().unwrap()), vars(&[])).unwrap();
+        assert_eq!(
+            reparsed.woolies.exclude_categories,
+            config.woolies.exclude_categories
+        );
+    }
+}
@@ -1,13 +1,14 @@
 use anyhow::Context;
-use itertools::{Either, Itertools};
+use itertools::Itertools;
 use log::{error, info};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::Display;
 use std::io::Read;
-use std::result::Result as StdResult;
 use time::Date;
 
 use crate::{
+    date::date_serde,
     errors::{Error, Result},
     product::ProductSnapshot,
     stores::Store,
@@ -16,12 +17,100 @@ use crate::{
 // If more than 5% of conversions fail then it should be an error
 const CONVERSION_SUCCESS_THRESHOLD: f64 = 0.05;
 
-struct ConversionMetrics {
+/// Maximum share of products that may fail conversion before a snapshot is rejected. A default
+/// applies to all stores and can be overridden for individual stores.
+#[derive(Debug, Clone)]
+pub struct ConversionThresholds {
+    default: f64,
+    stores: HashMap<Store, f64>,
+}
+
+impl ConversionThresholds {
+    pub fn new(default: f64) -> Self {
+        Self {
+            default,
+            stores: HashMap::new(),
+        }
+    }
+
+    pub fn set_default(&mut self, threshold: f64) {
+        self.default = threshold;
+    }
+
+    pub fn set_store(&mut self, store: Store, threshold: f64) {
+        self.stores.insert(store, threshold);
+    }
+
+    pub(crate) fn for_store(&self, store: Store) -> f64 {
+        self.stores.get(&store).copied().unwrap_or(self.default)
+    }
+}
+
+impl Default for ConversionThresholds {
+    fn default() -> Self {
+        Self::new(CONVERSION_SUCCESS_THRESHOLD)
+    }
+}
+
+/// Which categories of a store are scraped and converted. Categories are matched by the names the
+/// store gives them, e.g. the SEO token for Coles or the node id and description for Woolies.
+#[derive(Debug, Clone, Default)]
+pub struct CategoryFilter {
+    include: Vec<String>,
+    exclude: Vec<String>,
+    only: Vec<String>,
+}
+
+impl CategoryFilter {
+    pub fn new(include: Vec<String>, exclude: Vec<String>) -> Self {
+        Self {
+            include,
+            exclude,
+            only: Vec::new(),
+        }
+    }
+
+    /// Restricts the filter to the categories in `only`, leaving out every other one
+    pub fn only(mut self, only: Vec<String>) -> Self {
+        self.only = only;
+        self
+    }
+
+    /// Whether a category known by any of `names` is left out. Excluded categories are left out
+    /// unless they are included as well, and with an `only` list so is every category not in it.
+    pub(crate) fn filters(&self, names: &[&str]) -> bool {
+        let named = |list: &[String]| names.iter().any(|n| list.iter().any(|c| c == n));
+        if !self.only.is_empty() && !named(&self.only) {
+            return true;
+        }
+        named(&self.exclude) && !named(&self.include)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct ConversionMetrics {
+    store: Store,
+    #[serde(with = "date_serde")]
+    date: Date,
     success: usize,
     failure: usize,
+    #[serde(rename = "failuresByKind")]
+    failures_by_kind: BTreeMap<String, usize>,
 }
 
 impl ConversionMetrics {
+    pub(crate) fn store(&self) -> Store {
+        self.store
+    }
+
+    pub(crate) fn date(&self) -> Date {
+        self.date
+    }
+
+    pub(crate) fn failure(&self) -> usize {
+        self.failure
+    }
+
     pub(crate) fn failure_rate(&self) -> f64 {
         (self.failure) as f64 / (self.success + self.failure) as f64
     }
@@ -35,89 +124,167 @@ impl Display for ConversionMetrics {
             self.success,
             self.failure,
             self.failure_rate() * 100.0,
-        )
+        )?;
+        if !self.failures_by_kind.is_empty() {
+            let kinds = self
+                .failures_by_kind
+                .iter()
+                .map(|(kind, count)| format!("{kind}: {count}"))
+                .join(", ");
+            write!(f, " ({kinds})")?;
+        }
+        Ok(())
     }
 }
 
 pub(crate) trait Category {
     type Product: Product;
-    fn is_filtered(&self) -> bool;
-    fn into_products(self) -> anyhow::Result<Vec<Self::Product>>;
+    fn is_filtered(&self, filter: &CategoryFilter) -> bool;
+    /// Products in this category. Products that can't even be read as the store's product type
+    /// are returned as failures so they count against the threshold like any other.
+    fn into_products(self) -> anyhow::Result<Vec<ProductResult<Self::Product>>>;
 }
 
+pub(crate) type ProductResult<P> = std::result::Result<P, ConversionFailure>;
+
 pub(crate) trait Product {
     fn try_into_snapshot_and_date(self, date: Date) -> Result<ProductSnapshot>;
     fn store() -> Store;
+    /// Remove the raw JSON this product was loaded from so it can be reported if conversion fails
+    fn take_raw(&mut self) -> serde_json::Value;
+}
+
+/// A product that could not be converted, together with the reason and its raw JSON
+#[derive(Debug, Serialize)]
+pub(crate) struct ConversionFailure {
+    kind: String,
+    error: String,
+    product: serde_json::Value,
+}
+
+impl ConversionFailure {
+    pub(crate) fn new(error: &Error, product: serde_json::Value) -> Self {
+        Self {
+            kind: error.kind().to_string(),
+            error: error.to_string(),
+            product,
+        }
+    }
+}
+
+/// Result of converting all products of a store for a single day
+pub(crate) struct Conversion {
+    store: Store,
+    date: Date,
+    products: Vec<ProductSnapshot>,
+    failures: Vec<ConversionFailure>,
 }
 
-pub(crate) fn from_reader<C>(file: impl Read, date: Date) -> anyhow::Result<Vec<ProductSnapshot>>
+impl Conversion {
+    pub(crate) fn metrics(&self) -> ConversionMetrics {
+        let mut failures_by_kind = BTreeMap::new();
+        for failure in self.failures.iter() {
+            *failures_by_kind.entry(failure.kind.clone()).or_insert(0) += 1;
+        }
+        ConversionMetrics {
+            store: self.store,
+            date: self.date,
+            success: self.products.len(),
+            failure: self.failures.len(),
+            failures_by_kind,
+        }
+    }
+
+    pub(crate) fn failures(&self) -> &[ConversionFailure] {
+        &self.failures
+    }
+
+    /// All products that were converted, no matter how many failed
+    pub(crate) fn into_converted(self) -> Vec<ProductSnapshot> {
+        self.products
+    }
+
+    /// Returns successfully converted products unless the share of failed conversions exceeds
+    /// `threshold`
+    pub(crate) fn into_products(self, threshold: f64) -> Result<Vec<ProductSnapshot>> {
+        let metrics = self.metrics();
+        let store = self.store;
+        let date = self.date;
+
+        if metrics.failure_rate() > threshold {
+            error!("Conversion exceeds threshold of {}: {}", threshold, metrics);
+            return Err(Error::ConversionThreshold {
+                threshold,
+                store,
+                date,
+                metrics: metrics.to_string(),
+            });
+        }
+        info!("Conversion of {store}/{date} succeeded: {metrics}");
+
+        // Figure out how many products have a category
+        let products = self.products;
+        let category_count = products.iter().filter(|p| p.category().is_some()).count();
+        let percentage = category_count as f64 / products.len() as f64 * 100.0;
+        info!("Products with category: {category_count} ({percentage:.2}%)");
+
+        Ok(products)
+    }
+}
+
+pub(crate) fn from_reader<C>(
+    file: impl Read,
+    date: Date,
+    filter: &CategoryFilter,
+) -> anyhow::Result<Conversion>
 where
     C: for<'a> Deserialize<'a> + Category,
 {
     let categories: Vec<C> = serde_json::from_reader(file)?;
     let categories: Vec<C> = categories
         .into_iter()
-        .filter(|c| !c.is_filtered())
+        .filter(|c| !c.is_filtered(filter))
         .collect();
-    let success = convert_all::<C>(categories)?;
+    let (success, failures) = convert_all::<C>(categories)?;
 
-    let success = convert(success, date)?;
-    Ok(success)
+    Ok(convert(success, failures, date))
 }
 
-fn convert_all<C>(categories: Vec<C>) -> anyhow::Result<Vec<C::Product>>
+fn convert_all<C>(categories: Vec<C>) -> anyhow::Result<(Vec<C::Product>, Vec<ConversionFailure>)>
 where
     C: Category,
 {
-    categories
+    let results: Vec<ProductResult<C::Product>> = categories
         .into_iter()
         .map(|c| {
             c.into_products()
                 .with_context(|| "Failed to convert from json into store-specific product")
         })
         .flatten_ok()
-        .collect()
+        .collect::<anyhow::Result<_>>()?;
+    Ok(results.into_iter().partition_result())
 }
 
-fn convert<T>(success: Vec<T>, date: Date) -> Result<Vec<ProductSnapshot>>
+/// Converts `items` into snapshots, adding the ones that fail to `failures`
+fn convert<T>(items: Vec<T>, mut failures: Vec<ConversionFailure>, date: Date) -> Conversion
 where
     T: Product,
 {
-    let products: Vec<StdResult<ProductSnapshot, Error>> = success
-        .into_iter()
-        .map(|s| s.try_into_snapshot_and_date(date))
-        .collect();
-    let (success, failure): (Vec<_>, Vec<_>) = products.into_iter().partition_map(|v| match v {
-        Ok(v) => Either::Left(v),
-        Err(v) => Either::Right(v),
-    });
-
-    let metrics = ConversionMetrics {
-        success: success.len(),
-        failure: failure.len(),
-    };
-
-    // Global default value, currently fixed but could be changed
-    let success_threshold = CONVERSION_SUCCESS_THRESHOLD;
-
-    if metrics.failure_rate() > success_threshold {
-        error!(
-            "Conversion exceeds threshold of {}: {}",
-            success_threshold, metrics
-        );
-        return Err(Error::ProductConversion(format!(
-            "Error threshold of {success_threshold} for conversion of {date} exceeded: {metrics}",
-        )));
+    let mut products = Vec::with_capacity(items.len());
+    for mut item in items {
+        let raw = item.take_raw();
+        match item.try_into_snapshot_and_date(date) {
+            Ok(product) => products.push(product),
+            Err(error) => failures.push(ConversionFailure::new(&error, raw)),
+        }
     }
-    let store = T::store();
-    info!("Conversion of {store}/{date} succeeded: {metrics}");
-
-    // Figure out how many products have a category
-    let category_count = success.iter().filter(|p| p.category().is_some()).count();
-    let percentage = category_count as f64 / success.len() as f64 * 100.0;
-    info!("Products with category: {category_count} ({percentage:.2}%)");
 
-    Ok(success)
+    Conversion {
+        store: T::store(),
+        date,
+        products,
+        failures,
+    }
 }
 
 #[cfg(test)]
@@ -126,6 +293,7 @@ mod test {
     use serde_json::json;
 
     use super::*;
+    use crate::errors::ConversionError;
 
     #[derive(Deserialize)]
     struct TestProduct {}
@@ -138,6 +306,10 @@ mod test {
         fn try_into_snapshot_and_date(self, _: Date) -> Result<ProductSnapshot> {
             Ok(ProductSnapshot::default())
         }
+
+        fn take_raw(&mut self) -> serde_json::Value {
+            serde_json::Value::Null
+        }
     }
 
     #[derive(Deserialize)]
@@ -150,14 +322,14 @@ mod test {
 
     impl Category for TestCategory {
         type Product = TestProduct;
-        fn is_filtered(&self) -> bool {
+        fn is_filtered(&self, _filter: &CategoryFilter) -> bool {
             self.is_filtered
         }
-        fn into_products(self) -> anyhow::Result<Vec<Self::Product>> {
+        fn into_products(self) -> anyhow::Result<Vec<ProductResult<Self::Product>>> {
             if self.throw_error {
                 bail!("")
             } else {
-                Ok(self.products)
+                Ok(self.products.into_iter().map(Ok).collect())
             }
         }
     }
@@ -172,7 +344,11 @@ mod test {
         ])
         .to_string();
         let date = Date::from_calendar_date(2024, time::Month::January, 1).unwrap();
-        let products = from_reader::<TestCategory>(json_data.as_bytes(), date).unwrap();
+        let products =
+            from_reader::<TestCategory>(json_data.as_bytes(), date, &CategoryFilter::default())
+                .unwrap()
+                .into_products(CONVERSION_SUCCESS_THRESHOLD)
+                .unwrap();
         assert_eq!(products.len(), 1);
     }
 
@@ -190,10 +366,33 @@ mod test {
         ])
         .to_string();
         let date = Date::from_calendar_date(2024, time::Month::January, 1).unwrap();
-        let products = from_reader::<TestCategory>(json_data.as_bytes(), date).unwrap();
+        let products =
+            from_reader::<TestCategory>(json_data.as_bytes(), date, &CategoryFilter::default())
+                .unwrap()
+                .into_products(CONVERSION_SUCCESS_THRESHOLD)
+                .unwrap();
         assert_eq!(products.len(), 1);
     }
 
+    #[test]
+    fn category_filter() {
+        let exclude = vec![String::from("down-down")];
+        let filter = CategoryFilter::new(Vec::new(), exclude.clone());
+        assert!(filter.filters(&["down-down"]));
+        assert!(!filter.filters(&["fruit-vegetables"]));
+
+        // Including an excluded category scrapes it on top of all the others
+        let filter = CategoryFilter::new(vec![String::from("down-down")], exclude.clone());
+        assert!(!filter.filters(&["down-down"]));
+        assert!(!filter.filters(&["fruit-vegetables"]));
+
+        let filter = CategoryFilter::new(Vec::new(), exclude)
+            .only(vec![String::from("down-down"), String::from("bakery")]);
+        assert!(filter.filters(&["down-down"]));
+        assert!(!filter.filters(&["bakery"]));
+        assert!(filter.filters(&["fruit-vegetables"]));
+    }
+
     #[test]
     fn conversion_fail_into_products() {
         let categories = vec![TestCategory {
@@ -205,29 +404,97 @@ mod test {
         assert!(result.is_err());
     }
 
-    #[test]
-    fn conversion_fail_into_snapshot() {
-        struct TestProduct {}
+    struct FailingProduct {
+        raw: serde_json::Value,
+    }
 
-        impl Product for TestProduct {
-            fn store() -> Store {
-                Store::Woolies
-            }
+    impl Product for FailingProduct {
+        fn store() -> Store {
+            Store::Woolies
+        }
 
-            fn try_into_snapshot_and_date(self, _: Date) -> Result<ProductSnapshot> {
-                Err(Error::ProductConversion(String::new()))
-            }
+        fn try_into_snapshot_and_date(self, _: Date) -> Result<ProductSnapshot> {
+            Err(ConversionError::MissingPrice.into())
+        }
+
+        fn take_raw(&mut self) -> serde_json::Value {
+            self.raw.take()
         }
+    }
 
-        let success = vec![TestProduct {}];
+    #[test]
+    fn conversion_fail_into_snapshot() {
+        let success = vec![FailingProduct { raw: json!({}) }];
         let date = Date::from_calendar_date(2024, time::Month::January, 1).unwrap();
-        let err = convert(success, date).unwrap_err();
+        let err = convert(success, Vec::new(), date)
+            .into_products(CONVERSION_SUCCESS_THRESHOLD)
+            .unwrap_err();
         match err {
-            Error::ProductConversion(msg) => assert!(
-                msg.contains("Error threshold"),
-                "Message did not contain expected value in '{msg}'"
+            Error::ConversionThreshold { metrics, .. } => assert!(
+                metrics.contains("missing_price: 1"),
+                "Metrics did not contain failure kinds in '{metrics}'"
             ),
-            _ => panic!("Wrong error type, expected conversion error with threshold message"),
+            _ => panic!("Wrong error type, expected conversion threshold error"),
         };
     }
+
+    #[test]
+    fn conversion_failure_is_recorded() {
+        let success = vec![FailingProduct {
+            raw: json!({"id": 1}),
+        }];
+        let date = Date::from_calendar_date(2024, time::Month::January, 1).unwrap();
+        let conversion = convert(success, Vec::new(), date);
+        let [ref failure] = conversion.failures()[..] else {
+            panic!("expected exactly one failure");
+        };
+        assert_eq!(
+            serde_json::to_value(failure).unwrap(),
+            json!({
+                "kind": "missing_price",
+                "error": "Conversion error: missing price",
+                "product": {"id": 1},
+            })
+        );
+    }
+
+    #[test]
+    fn conversion_metrics_by_kind() {
+        let success = vec![
+            FailingProduct { raw: json!({}) },
+            FailingProduct { raw: json!({}) },
+        ];
+        let date = Date::from_calendar_date(2024, time::Month::January, 1).unwrap();
+        let metrics = convert(success, Vec::new(), date).metrics();
+        assert_eq!(
+            serde_json::to_value(&metrics).unwrap(),
+            json!({
+                "store": "woolies",
+                "date": "2024-01-01",
+                "success": 0,
+                "failure": 2,
+                "failuresByKind": {"missing_price": 2},
+            })
+        );
+    }
+
+    #[test]
+    fn conversion_below_store_threshold() {
+        let success = vec![FailingProduct { raw: json!({}) }];
+        let date = Date::from_calendar_date(2024, time::Month::January, 1).unwrap();
+        let mut thresholds = ConversionThresholds::default();
+        thresholds.set_store(Store::Woolies, 1.0);
+        let products = convert(success, Vec::new(), date)
+            .into_products(thresholds.for_store(Store::Woolies))
+            .unwrap();
+        assert!(products.is_empty());
+    }
+
+    #[test]
+    fn thresholds_for_store() {
+        let mut thresholds = ConversionThresholds::new(0.1);
+        thresholds.set_store(Store::Coles, 0.2);
+        assert_eq!(thresholds.for_store(Store::Coles), 0.2);
+        assert_eq!(thresholds.for_store(Store::Woolies), 0.1);
+    }
 }
@@ -1,3 +1,16 @@
+use anyhow::anyhow;
+use time::{Date, OffsetDateTime};
+use time_tz::{timezones, OffsetDateTimeExt, Tz};
+
+pub(crate) fn timezone(name: &str) -> anyhow::Result<&'static Tz> {
+    timezones::get_by_name(name).ok_or_else(|| anyhow!("Unknown timezone {name:?}"))
+}
+
+/// Date of `moment` in `timezone`, e.g. the afternoon in Sydney for a morning in UTC
+pub(crate) fn date_in(moment: OffsetDateTime, timezone: &Tz) -> Date {
+    moment.to_timezone(timezone).date()
+}
+
 pub(crate) mod date_serde {
     use serde::{Deserialize, Deserializer, Serializer};
     use std::result::Result as StdResult;
@@ -25,3 +38,21 @@ pub(crate) mod date_serde {
         Ok(date)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use time::macros::datetime;
+
+    use super::*;
+
+    #[test]
+    fn day_boundary() {
+        let sydney = timezone("Australia/Sydney").unwrap();
+        // Sydney is 11 hours ahead during daylight saving time
+        let moment = datetime!(2024-01-01 13:30 UTC);
+        assert_eq!(date_in(moment, sydney).to_string(), "2024-01-02");
+        let moment = datetime!(2024-01-01 12:30 UTC);
+        assert_eq!(date_in(moment, sydney).to_string(), "2024-01-01");
+        assert!(timezone("Australia/Nowhere").is_err());
+    }
+}
@@ -0,0 +1,279 @@
+//! Differences between the raw snapshots of a store on two days, to see what changed in the data
+//! when an analysis looks wrong
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use anyhow::Context;
+use serde::Serialize;
+use time::Date;
+
+use crate::config::Config;
+use crate::conversion::ConversionMetrics;
+use crate::date::date_serde;
+use crate::product::{deduplicate_products, price_serde, Price, ProductSnapshot};
+use crate::search::OutputFormat;
+use crate::storage::read_daily_snapshot;
+use crate::stores::Store;
+
+#[derive(Debug, PartialEq, Serialize)]
+struct ProductEntry {
+    id: i64,
+    name: String,
+    #[serde(with = "price_serde")]
+    price: Price,
+}
+
+impl From<&ProductSnapshot> for ProductEntry {
+    fn from(product: &ProductSnapshot) -> Self {
+        Self {
+            id: product.id(),
+            name: product.name().to_string(),
+            price: product.price(),
+        }
+    }
+}
+
+/// A changed field of a product, with the values formatted as they are shown
+#[derive(Debug, PartialEq, Serialize)]
+struct FieldChange {
+    field: &'static str,
+    from: String,
+    to: String,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct ProductChange {
+    id: i64,
+    name: String,
+    changes: Vec<FieldChange>,
+}
+
+#[derive(Debug, Serialize)]
+struct SnapshotDiff {
+    store: Store,
+    #[serde(with = "date_serde")]
+    from: Date,
+    #[serde(with = "date_serde")]
+    to: Date,
+    added: Vec<ProductEntry>,
+    removed: Vec<ProductEntry>,
+    changed: Vec<ProductChange>,
+    /// Conversion of both snapshots, products that failed to convert show up as removed or added
+    conversions: Vec<ConversionMetrics>,
+}
+
+/// Formats a field of a product for comparing and showing it
+type FieldValue = fn(&ProductSnapshot) -> String;
+
+fn quantity(product: &ProductSnapshot) -> String {
+    format!("{} {}", product.quantity(), product.unit().symbol())
+}
+
+fn category(product: &ProductSnapshot) -> String {
+    match product.category() {
+        Some(category) => format!("{} {}", category.code(), category.name()),
+        None => String::from("-"),
+    }
+}
+
+fn field_changes(before: &ProductSnapshot, after: &ProductSnapshot) -> Vec<FieldChange> {
+    let fields: [(&'static str, FieldValue); 5] = [
+        ("price", |p| p.price().to_string()),
+        ("name", |p| p.name().to_string()),
+        ("description", |p| p.description().to_string()),
+        ("quantity", quantity),
+        ("category", category),
+    ];
+    fields
+        .into_iter()
+        .filter_map(|(field, value)| {
+            let (from, to) = (value(before), value(after));
+            (from != to).then_some(FieldChange { field, from, to })
+        })
+        .collect()
+}
+
+fn diff(
+    store: Store,
+    (from, before): (Date, Vec<ProductSnapshot>),
+    (to, after): (Date, Vec<ProductSnapshot>),
+) -> SnapshotDiff {
+    let mut before: BTreeMap<i64, ProductSnapshot> =
+        before.into_iter().map(|p| (p.id(), p)).collect();
+    let mut added = Vec::new();
+    let mut changed = Vec::new();
+    let after: BTreeMap<i64, ProductSnapshot> = after.into_iter().map(|p| (p.id(), p)).collect();
+    for (id, product) in after.iter() {
+        match before.remove(id) {
+            None => added.push(ProductEntry::from(product)),
+            Some(previous) => {
+                let changes = field_changes(&previous, product);
+                if !changes.is_empty() {
+                    changed.push(ProductChange {
+                        id: *id,
+                        name: product.name().to_string(),
+                        changes,
+                    });
+                }
+            }
+        }
+    }
+    let removed = before.values().map(ProductEntry::from).collect();
+    SnapshotDiff {
+        store,
+        from,
+        to,
+        added,
+        removed,
+        changed,
+        conversions: Vec::new(),
+    }
+}
+
+impl SnapshotDiff {
+    fn write_table(&self, mut out: impl Write) -> std::io::Result<()> {
+        writeln!(
+            out,
+            "{} from {} to {}: {} added, {} removed, {} changed",
+            self.store,
+            self.from,
+            self.to,
+            self.added.len(),
+            self.removed.len(),
+            self.changed.len()
+        )?;
+        for conversion in self.conversions.iter().filter(|c| c.failure() > 0) {
+            writeln!(
+                out,
+                "Conversion failures on {}: {conversion}",
+                conversion.date()
+            )?;
+        }
+        for (title, entries) in [("ADDED", &self.added), ("REMOVED", &self.removed)] {
+            if entries.is_empty() {
+                continue;
+            }
+            writeln!(out, "\n{title}")?;
+            for entry in entries.iter() {
+                writeln!(
+                    out,
+                    "{:>10} {:>8}  {}",
+                    entry.id,
+                    entry.price.to_string(),
+                    entry.name
+                )?;
+            }
+        }
+        if !self.changed.is_empty() {
+            writeln!(out, "\nCHANGED")?;
+        }
+        for product in self.changed.iter() {
+            writeln!(out, "{:>10}  {}", product.id, product.name)?;
+            for change in product.changes.iter() {
+                writeln!(
+                    out,
+                    "{:>10}  {:<12} {} -> {}",
+                    "", change.field, change.from, change.to
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Compares the snapshots of `store` on the days `from` and `to`
+pub fn do_diff(
+    store: Store,
+    from: Date,
+    to: Date,
+    format: OutputFormat,
+    config: &Config,
+) -> anyhow::Result<()> {
+    // Failed conversions are reported instead of stopping the diff, it's most useful when the
+    // data looks wrong
+    let load = |day| -> anyhow::Result<(Vec<ProductSnapshot>, ConversionMetrics)> {
+        let conversion = read_daily_snapshot(config, store, day)
+            .with_context(|| format!("Failed to load snapshot for day {day}"))?;
+        let metrics = conversion.metrics();
+        Ok((deduplicate_products(conversion.into_converted()), metrics))
+    };
+    let (before, from_metrics) = load(from)?;
+    let (after, to_metrics) = load(to)?;
+    let diff = SnapshotDiff {
+        conversions: vec![from_metrics, to_metrics],
+        ..diff(store, (from, before), (to, after))
+    };
+
+    let mut stdout = std::io::stdout().lock();
+    match format {
+        OutputFormat::Table => diff.write_table(stdout)?,
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut stdout, &diff)?;
+            writeln!(stdout)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use time::Month;
+
+    use super::*;
+    use crate::product::ProductInfo;
+    use crate::unit::Unit;
+
+    fn day(day: u8) -> Date {
+        Date::from_calendar_date(2024, Month::January, day).unwrap()
+    }
+
+    fn product(id: i64, name: &str, quantity: f64, price: f64, date: Date) -> ProductSnapshot {
+        let info = ProductInfo::new(
+            id,
+            name.to_string(),
+            String::new(),
+            None,
+            None,
+            None,
+            Unit::Grams,
+            quantity,
+            Store::Coles,
+            None,
+        );
+        ProductSnapshot::new(info, price.into(), None, date)
+    }
+
+    #[test]
+    fn changes() {
+        let before = vec![
+            product(1, "Milk", 1000.0, 2.0, day(1)),
+            product(2, "Bread", 700.0, 4.0, day(1)),
+            product(3, "Eggs", 600.0, 6.0, day(1)),
+        ];
+        let after = vec![
+            product(1, "Milk", 1000.0, 2.0, day(2)),
+            product(2, "Wholemeal bread", 650.0, 4.5, day(2)),
+            product(4, "Butter", 250.0, 5.0, day(2)),
+        ];
+        let diff = diff(Store::Coles, (day(1), before), (day(2), after));
+        assert_eq!(diff.added.iter().map(|p| p.id).collect::<Vec<_>>(), vec![4]);
+        assert_eq!(
+            diff.removed.iter().map(|p| p.id).collect::<Vec<_>>(),
+            vec![3]
+        );
+        let [ref changed] = diff.changed[..] else {
+            panic!("only bread should have changed")
+        };
+        let fields: Vec<_> = changed.changes.iter().map(|c| c.field).collect();
+        assert_eq!(fields, vec!["price", "name", "quantity"]);
+        assert_eq!(changed.changes[2].from, "700 g");
+
+        let mut out = Vec::new();
+        diff.write_table(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(
+            out.starts_with("coles from 2024-01-01 to 2024-01-02: 1 added, 1 removed, 1 changed")
+        );
+        assert!(out.contains("           price        4.00 -> 4.50\n"));
+    }
+}
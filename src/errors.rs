@@ -1,4 +1,8 @@
+use strum::AsRefStr;
 use thiserror::Error;
+use time::Date;
+
+use crate::stores::Store;
 
 pub(crate) type Result<T> = std::result::Result<T, Error>;
 
@@ -7,9 +11,78 @@ pub enum Error {
     #[error("Serde error: {0}")]
     SerdeJson(#[from] serde_json::Error),
     #[error("Conversion error: {0}")]
-    ProductConversion(String),
+    ProductConversion(#[from] ConversionError),
+    #[error("Error threshold of {threshold} for conversion of {store}/{date} exceeded: {metrics}")]
+    ConversionThreshold {
+        threshold: f64,
+        store: Store,
+        date: Date,
+        metrics: String,
+    },
     #[error("Ad result")]
     AdResult,
     #[error("Anyhow error: {0}")]
     Anyhow(#[from] anyhow::Error),
 }
+
+impl Error {
+    /// Short machine-readable name for the kind of error, used to group conversion failures
+    pub(crate) fn kind(&self) -> &str {
+        match self {
+            Self::SerdeJson(_) => "serde_json",
+            Self::ProductConversion(err) => err.as_ref(),
+            Self::ConversionThreshold { .. } => "conversion_threshold",
+            Self::AdResult => "ad_result",
+            Self::Anyhow(_) => "other",
+        }
+    }
+}
+
+/// Reasons why a single product can't be converted into the canonical format
+#[derive(Debug, Error, AsRefStr, PartialEq)]
+#[strum(serialize_all = "snake_case")]
+pub enum ConversionError {
+    #[error("missing price")]
+    MissingPrice,
+    #[error("unparseable size {raw:?}")]
+    UnparseableSize { raw: String },
+    #[error("unknown unit {unit:?}")]
+    UnknownUnit { unit: String },
+    #[error("missing cup measure, ran out of options to convert")]
+    MissingCupMeasure,
+    #[error("missing cup price, unable to calculate quantity")]
+    MissingCupPrice,
+    #[error("low quantity {quantity} for conversion")]
+    LowQuantity { quantity: f64 },
+    #[error("unparseable category names {raw:?}")]
+    UnparseableCategories { raw: String },
+    #[error("none of the subcategories {names:?} are part of the product's category")]
+    MissingCategory { names: Vec<String> },
+    #[error("invalid number of products in bundle: {n}")]
+    BundleSize { n: usize },
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn kind() {
+        let err = Error::from(ConversionError::UnknownUnit {
+            unit: String::from("x"),
+        });
+        assert_eq!(err.kind(), "unknown_unit");
+        assert_eq!(Error::AdResult.kind(), "ad_result");
+    }
+
+    #[test]
+    fn display() {
+        let err = Error::from(ConversionError::UnparseableSize {
+            raw: String::from("big"),
+        });
+        assert_eq!(
+            err.to_string(),
+            "Conversion error: unparseable size \"big\""
+        );
+    }
+}
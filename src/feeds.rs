@@ -0,0 +1,498 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write as _;
+use std::path::Path;
+
+use log::info;
+use serde::{Deserialize, Serialize};
+use strum::IntoEnumIterator;
+use time::{Date, Duration};
+
+use crate::{
+    date::date_serde,
+    price_changes::PriceChange,
+    product::{price_serde, Price, ProductHistory},
+    site::{escape_html, product_path},
+    storage::{load_feed_events, save_feed, save_feed_events},
+    stores::Store,
+};
+
+// Days of events kept in the feeds, so readers that don't poll every day still see everything
+const FEED_DAYS: i64 = 7;
+// Number of biggest drops per feed and day
+const TOP_DROPS: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum EventKind {
+    Drop,
+    New,
+    Delisted,
+}
+
+impl EventKind {
+    const ALL: [EventKind; 3] = [EventKind::Drop, EventKind::New, EventKind::Delisted];
+
+    fn name(&self) -> &'static str {
+        match self {
+            EventKind::Drop => "drop",
+            EventKind::New => "new",
+            EventKind::Delisted => "delisted",
+        }
+    }
+
+    fn file_name(&self) -> &'static str {
+        match self {
+            EventKind::Drop => "drops.xml",
+            EventKind::New => "new.xml",
+            EventKind::Delisted => "delisted.xml",
+        }
+    }
+
+    fn title(&self) -> &'static str {
+        match self {
+            EventKind::Drop => "Biggest price drops",
+            EventKind::New => "New products",
+            EventKind::Delisted => "Delisted products",
+        }
+    }
+}
+
+/// Something that happened to a product on a given day and is worth a feed entry
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct FeedEvent {
+    kind: EventKind,
+    store: Store,
+    id: i64,
+    name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    category: Option<String>,
+    #[serde(with = "date_serde")]
+    date: Date,
+    #[serde(
+        rename = "oldPrice",
+        default,
+        with = "price_serde::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    old_price: Option<Price>,
+    #[serde(
+        rename = "newPrice",
+        default,
+        with = "price_serde::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    new_price: Option<Price>,
+}
+
+impl From<&PriceChange> for FeedEvent {
+    fn from(change: &PriceChange) -> Self {
+        Self {
+            kind: EventKind::Drop,
+            store: change.store(),
+            id: change.id(),
+            name: change.name().to_string(),
+            category: change.category().map(String::from),
+            date: change.date(),
+            old_price: Some(change.old_price()),
+            new_price: Some(change.new_price()),
+        }
+    }
+}
+
+impl FeedEvent {
+    fn percent(&self) -> f64 {
+        match (self.old_price, self.new_price) {
+            (Some(old), Some(new)) => new.change_from(old),
+            _ => 0.0,
+        }
+    }
+
+    fn title(&self) -> String {
+        let price = |price: Option<Price>| price.map(|p| p.to_string()).unwrap_or_default();
+        match self.kind {
+            EventKind::Drop => format!(
+                "{}: {} → {} ({:.0}%)",
+                self.name,
+                price(self.old_price),
+                price(self.new_price),
+                self.percent() * 100.0
+            ),
+            EventKind::New => format!("New: {} at {}", self.name, price(self.new_price)),
+            EventKind::Delisted => {
+                format!("Delisted: {}, last {}", self.name, price(self.old_price))
+            }
+        }
+    }
+}
+
+struct ListedProduct {
+    name: String,
+    category: Option<&'static str>,
+    price: Price,
+    barcode: Option<String>,
+}
+
+/// Products of the history at one point in time, to tell which ones a merge added or removed
+pub(crate) struct Listing {
+    products: HashMap<(Store, i64), ListedProduct>,
+    barcodes: HashSet<(Store, String)>,
+}
+
+impl Listing {
+    pub(crate) fn new(products: &[ProductHistory]) -> Self {
+        let mut listing = Self {
+            products: HashMap::with_capacity(products.len()),
+            barcodes: HashSet::new(),
+        };
+        for product in products {
+            let info = product.product_info();
+            listing.products.insert(
+                (info.store(), info.id()),
+                ListedProduct {
+                    name: info.name().to_string(),
+                    category: info.category().map(|c| c.code()),
+                    price: product.price_history().first().price(),
+                    barcode: info.barcode().map(String::from),
+                },
+            );
+            if let Some(barcode) = info.barcode() {
+                listing.barcodes.insert((info.store(), barcode.to_string()));
+            }
+        }
+        listing
+    }
+
+    /// New and delisted products on `day` going from this listing to `after`. A store without
+    /// products before is skipped, otherwise its first snapshot would list the whole catalogue as
+    /// new. Products continuing under a new id (see `merge_price_history`) are neither.
+    pub(crate) fn events(&self, after: &Listing, day: Date) -> Vec<FeedEvent> {
+        let stores: HashSet<Store> = self.products.keys().map(|(store, _)| *store).collect();
+        let mut events = Vec::new();
+        let mut add = |kind, (store, id): (Store, i64), product: &ListedProduct| {
+            events.push(FeedEvent {
+                kind,
+                store,
+                id,
+                name: product.name.clone(),
+                category: product.category.map(String::from),
+                date: day,
+                old_price: (kind == EventKind::Delisted).then_some(product.price),
+                new_price: (kind == EventKind::New).then_some(product.price),
+            })
+        };
+        for (key, product) in after.products.iter() {
+            if stores.contains(&key.0)
+                && !self.products.contains_key(key)
+                && !self.has_barcode(key.0, product)
+            {
+                add(EventKind::New, *key, product);
+            }
+        }
+        for (key, product) in self.products.iter() {
+            if !after.products.contains_key(key) && !after.has_barcode(key.0, product) {
+                add(EventKind::Delisted, *key, product);
+            }
+        }
+        events
+    }
+
+    /// Whether this listing has a product of `store` with the same barcode as `product`
+    fn has_barcode(&self, store: Store, product: &ListedProduct) -> bool {
+        product
+            .barcode
+            .as_ref()
+            .is_some_and(|barcode| self.barcodes.contains(&(store, barcode.clone())))
+    }
+}
+
+/// Replaces the events of `days` with `new_events` and forgets the ones that are too old to be
+/// in the feeds anymore. With a `store_filter` only the events of that store are replaced.
+fn update_events(
+    events: Vec<FeedEvent>,
+    new_events: Vec<FeedEvent>,
+    days: &[Date],
+    store_filter: Option<Store>,
+) -> Vec<FeedEvent> {
+    let Some(last) = days.iter().max() else {
+        return events;
+    };
+    let oldest = *last - Duration::days(FEED_DAYS - 1);
+    let mut events: Vec<FeedEvent> = events
+        .into_iter()
+        .filter(|e| !days.contains(&e.date) || store_filter.is_some_and(|s| s != e.store))
+        .chain(new_events)
+        .filter(|e| e.date >= oldest)
+        .collect();
+    events.sort_by(|a, b| {
+        b.date
+            .cmp(&a.date)
+            .then(a.store.cmp(&b.store))
+            .then(a.id.cmp(&b.id))
+    });
+    events
+}
+
+/// Events of one kind for a feed. Drops are limited to the biggest ones of each day.
+fn select(
+    events: &[FeedEvent],
+    kind: EventKind,
+    filter: impl Fn(&FeedEvent) -> bool,
+) -> Vec<&FeedEvent> {
+    let mut selected: Vec<&FeedEvent> = events
+        .iter()
+        .filter(|e| e.kind == kind && filter(e))
+        .collect();
+    if kind == EventKind::Drop {
+        let mut by_day: BTreeMap<Date, Vec<&FeedEvent>> = BTreeMap::new();
+        for event in selected {
+            by_day.entry(event.date).or_default().push(event);
+        }
+        selected = by_day
+            .into_values()
+            .rev()
+            .flat_map(|mut drops| {
+                drops.sort_by(|a, b| a.percent().total_cmp(&b.percent()));
+                drops.truncate(TOP_DROPS);
+                drops
+            })
+            .collect();
+    }
+    selected
+}
+
+fn atom_date(date: Date) -> String {
+    format!("{date}T00:00:00Z")
+}
+
+fn render_feed(
+    title: &str,
+    path: &str,
+    base_url: &str,
+    updated: Date,
+    events: &[&FeedEvent],
+) -> String {
+    let base_url = base_url.trim_end_matches('/');
+    let feed_url = escape_html(&format!("{base_url}/feeds/{path}"));
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    xml.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+    let _ = writeln!(xml, "<title>{}</title>", escape_html(title));
+    let _ = writeln!(xml, "<id>{feed_url}</id>");
+    let _ = writeln!(xml, "<link rel=\"self\" href=\"{feed_url}\"/>");
+    let _ = writeln!(xml, "<updated>{}</updated>", atom_date(updated));
+    xml.push_str("<author><name>Hot Prices</name></author>\n");
+    for event in events {
+        let url = escape_html(&format!(
+            "{base_url}/{}",
+            product_path(event.store, event.id)
+        ));
+        let mut summary = format!("{} at {}.", event.name, event.store);
+        if let Some(old) = event.old_price {
+            let _ = write!(summary, " Old price {old}.");
+        }
+        if let Some(new) = event.new_price {
+            let _ = write!(summary, " New price {new}.");
+        }
+        xml.push_str("<entry>\n");
+        let _ = writeln!(xml, "<title>{}</title>", escape_html(&event.title()));
+        // Unique per product, kind and day so readers show a repeated drop again
+        let _ = writeln!(xml, "<id>{url}#{}-{}</id>", event.kind.name(), event.date);
+        let _ = writeln!(xml, "<link href=\"{url}\"/>");
+        let _ = writeln!(xml, "<updated>{}</updated>", atom_date(event.date));
+        let _ = writeln!(xml, "<summary>{}</summary>", escape_html(&summary));
+        xml.push_str("</entry>\n");
+    }
+    xml.push_str("</feed>\n");
+    xml
+}
+
+/// Adds the events of this run to the stored ones and rewrites all feeds, one per kind for every
+/// store and category
+pub(crate) fn update_feeds(
+    products: &[ProductHistory],
+    new_events: Vec<FeedEvent>,
+    days: &[Date],
+    store_filter: Option<Store>,
+    base_url: &str,
+    output_dir: &Path,
+) -> anyhow::Result<()> {
+    let Some(updated) = days.iter().max().copied() else {
+        return Ok(());
+    };
+    info!("{} new feed events", new_events.len());
+    let events = update_events(
+        load_feed_events(output_dir)?,
+        new_events,
+        days,
+        store_filter,
+    );
+    save_feed_events(&events, output_dir)?;
+
+    for store in Store::iter() {
+        for kind in EventKind::ALL {
+            let path = format!("{store}/{}", kind.file_name());
+            let selected = select(&events, kind, |e| e.store == store);
+            let title = format!("Hot Prices: {} at {store}", kind.title());
+            let feed = render_feed(&title, &path, base_url, updated, &selected);
+            save_feed(&feed, &path, output_dir)?;
+        }
+    }
+
+    let categories: BTreeMap<&'static str, &'static str> = products
+        .iter()
+        .filter_map(|p| p.product_info().category())
+        .map(|c| (c.code(), c.name()))
+        .collect();
+    for (code, name) in categories {
+        for kind in EventKind::ALL {
+            let path = format!("categories/{code}/{}", kind.file_name());
+            let selected = select(&events, kind, |e| e.category.as_deref() == Some(code));
+            let title = format!("Hot Prices: {} in {name}", kind.title());
+            let feed = render_feed(&title, &path, base_url, updated, &selected);
+            save_feed(&feed, &path, output_dir)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use tempfile::tempdir;
+    use time::Month;
+
+    use super::*;
+    use crate::product::ProductInfo;
+    use crate::unit::Unit;
+
+    fn day(day: u8) -> Date {
+        Date::from_calendar_date(2024, Month::January, day).unwrap()
+    }
+
+    fn product(store: Store, id: i64, barcode: Option<&str>) -> ProductHistory {
+        let info = ProductInfo::new(
+            id,
+            format!("Product {id}"),
+            String::new(),
+            None,
+            barcode.map(String::from),
+            None,
+            Unit::Each,
+            1.0,
+            store,
+            None,
+        );
+        ProductHistory::with_prices(info, &[(day(1), 2.0)])
+    }
+
+    fn event(kind: EventKind, id: i64, date: Date, old: f64, new: f64) -> FeedEvent {
+        FeedEvent {
+            kind,
+            store: Store::Coles,
+            id,
+            name: format!("Product {id}"),
+            category: None,
+            date,
+            old_price: Some(old.into()),
+            new_price: Some(new.into()),
+        }
+    }
+
+    #[test]
+    fn new_and_delisted() {
+        let before = Listing::new(&[
+            product(Store::Coles, 1, None),
+            product(Store::Coles, 2, None),
+            product(Store::Coles, 3, Some("9310036001140")),
+        ]);
+        let after = Listing::new(&[
+            product(Store::Coles, 1, None),
+            product(Store::Coles, 4, None),
+            // Continues product 3 under a new id
+            product(Store::Coles, 5, Some("9310036001140")),
+            // First snapshot of a store isn't a list of new products
+            product(Store::Woolies, 6, None),
+        ]);
+        let mut events: Vec<(i64, EventKind)> = before
+            .events(&after, day(2))
+            .iter()
+            .map(|e| (e.id, e.kind))
+            .collect();
+        events.sort_by_key(|(id, _)| *id);
+        assert_eq!(events, vec![(2, EventKind::Delisted), (4, EventKind::New)]);
+    }
+
+    #[test]
+    fn keeps_recent_events() {
+        let events = vec![
+            event(EventKind::Drop, 1, day(1), 2.0, 1.0),
+            event(EventKind::Drop, 2, day(8), 2.0, 1.0),
+        ];
+        let events = update_events(
+            events,
+            vec![event(EventKind::Drop, 3, day(8), 2.0, 1.5)],
+            &[day(8)],
+            None,
+        );
+        // Day 1 is too old and day 8 was replaced
+        let ids: Vec<i64> = events.iter().map(|e| e.id).collect();
+        assert_eq!(ids, vec![3]);
+    }
+
+    #[test]
+    fn replaces_events_per_store() {
+        let woolies = |kind, id| FeedEvent {
+            store: Store::Woolies,
+            ..event(kind, id, day(8), 2.0, 1.0)
+        };
+        // Each store is analysed on its own on the same day
+        let events = update_events(
+            Vec::new(),
+            vec![event(EventKind::New, 1, day(8), 2.0, 1.0)],
+            &[day(8)],
+            Some(Store::Coles),
+        );
+        let events = update_events(
+            events,
+            vec![woolies(EventKind::Delisted, 2)],
+            &[day(8)],
+            Some(Store::Woolies),
+        );
+        // Running coles again replaces only its own events
+        let events = update_events(
+            events,
+            vec![event(EventKind::Drop, 3, day(8), 2.0, 1.0)],
+            &[day(8)],
+            Some(Store::Coles),
+        );
+        let mut ids: Vec<(Store, i64)> = events.iter().map(|e| (e.store, e.id)).collect();
+        ids.sort();
+        assert_eq!(ids, vec![(Store::Coles, 3), (Store::Woolies, 2)]);
+    }
+
+    #[test]
+    fn writes_feeds() {
+        let events = vec![
+            event(EventKind::Drop, 1, day(2), 2.0, 1.5),
+            event(EventKind::Drop, 2, day(2), 2.0, 1.0),
+        ];
+        let selected = select(&events, EventKind::Drop, |_| true);
+        let ids: Vec<i64> = selected.iter().map(|e| e.id).collect();
+        assert_eq!(ids, vec![2, 1]);
+
+        let tmpdir = tempdir().unwrap();
+        update_feeds(
+            &[],
+            events,
+            &[day(2)],
+            None,
+            "https://example.com/",
+            tmpdir.path(),
+        )
+        .unwrap();
+        let feed = std::fs::read_to_string(tmpdir.path().join("feeds/coles/drops.xml")).unwrap();
+        assert!(feed.contains("<id>https://example.com/feeds/coles/drops.xml</id>"));
+        assert!(feed.contains("<title>Product 2: 2.00 → 1.00 (-50%)</title>"));
+        assert!(feed.contains("<id>https://example.com/products/coles/2.html#drop-2024-01-02</id>"));
+        assert!(tmpdir.path().join("feeds/woolies/delisted.xml").exists());
+        assert_eq!(load_feed_events(tmpdir.path()).unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,429 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use anyhow::Context;
+use clap::ValueEnum;
+use itertools::Itertools;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use time::{Date, Duration};
+
+use crate::{
+    date::date_serde,
+    product::{Price, ProductHistory},
+    storage::{load_history, load_inflation_index, save_inflation_index},
+    stores::Store,
+};
+
+// Index value of the first period
+const BASE_VALUE: f64 = 100.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+impl Frequency {
+    /// Days on which prices are sampled, starting with `start` and never after `end`. Weekly
+    /// samples fall on Mondays and monthly ones on the first of the month.
+    fn sample_days(&self, start: Date, end: Date) -> Vec<Date> {
+        let mut day = match self {
+            Frequency::Daily => start,
+            Frequency::Weekly => {
+                start - Duration::days(start.weekday().number_days_from_monday() as i64)
+            }
+            Frequency::Monthly => start.replace_day(1).expect("every month has a first day"),
+        };
+        let mut days = Vec::new();
+        while day <= end {
+            days.push(day);
+            day = match self {
+                Frequency::Daily => day + Duration::days(1),
+                Frequency::Weekly => day + Duration::days(7),
+                Frequency::Monthly => {
+                    let (year, month) = match day.month() {
+                        time::Month::December => (day.year() + 1, time::Month::January),
+                        month => (day.year(), month.next()),
+                    };
+                    Date::from_calendar_date(year, month, 1).expect("first of month is valid")
+                }
+            };
+        }
+        days
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct BasketProduct {
+    store: Store,
+    id: i64,
+    #[serde(default = "default_weight")]
+    weight: f64,
+}
+
+#[derive(Debug, Deserialize)]
+struct BasketCategory {
+    /// Category code or a prefix of it, e.g. "3" for all meat & seafood
+    code: String,
+    weight: f64,
+}
+
+fn default_weight() -> f64 {
+    1.0
+}
+
+/// Products whose prices make up the index, either listed individually or as whole categories.
+/// The weight of a category is split evenly between its products.
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct Basket {
+    #[serde(default)]
+    products: Vec<BasketProduct>,
+    #[serde(default)]
+    categories: Vec<BasketCategory>,
+}
+
+impl Basket {
+    pub(crate) fn load(path: &Path) -> anyhow::Result<Self> {
+        let fpath = path.to_string_lossy();
+        let file = File::open(path).with_context(|| format!("Failed to open basket {fpath}"))?;
+        serde_json::from_reader(BufReader::new(file))
+            .with_context(|| format!("Failed to load basket from {fpath}"))
+    }
+
+    /// Products of the basket with their weights
+    fn resolve<'a>(&self, products: &'a [ProductHistory]) -> Vec<(&'a ProductHistory, f64)> {
+        let lookup: HashMap<(Store, i64), &ProductHistory> =
+            products.iter().map(|p| ((p.store(), p.id()), p)).collect();
+        let mut items = Vec::new();
+        for item in self.products.iter() {
+            match lookup.get(&(item.store, item.id)) {
+                Some(product) => items.push((*product, item.weight)),
+                None => warn!(
+                    "Basket product {} of {} is not in the history",
+                    item.id, item.store
+                ),
+            }
+        }
+        for category in self.categories.iter() {
+            let members: Vec<&ProductHistory> = products
+                .iter()
+                .filter(|p| {
+                    p.product_info()
+                        .category()
+                        .is_some_and(|c| c.code().starts_with(category.code.as_str()))
+                })
+                .collect();
+            if members.is_empty() {
+                warn!("Basket category {} has no products", category.code);
+                continue;
+            }
+            let weight = category.weight / members.len() as f64;
+            items.extend(members.into_iter().map(|p| (p, weight)));
+        }
+        items
+    }
+}
+
+/// Price in effect on `day`, or `None` if the product wasn't sold yet. The history only records
+/// changes, so this is the most recent change on or before `day`. Delisted products are removed
+/// from the history, so any product it has is still sold at its latest price.
+fn price_at(product: &ProductHistory, day: Date) -> Option<Price> {
+    // Latest first, so the first entry that isn't after `day` is the one in effect
+    product
+        .price_history()
+        .iter()
+        .find(|snapshot| snapshot.date() <= day)
+        .map(|snapshot| snapshot.price())
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct IndexValue {
+    #[serde(with = "date_serde")]
+    date: Date,
+    value: f64,
+}
+
+/// Chain-linked, weighted Jevons index starting at `base`. Each period is linked to the previous
+/// one using only products priced in both, so products entering the basket later don't cause
+/// jumps.
+fn chain_index(items: &[(&ProductHistory, f64)], days: &[Date], base: f64) -> Vec<IndexValue> {
+    let mut series: Vec<IndexValue> = Vec::new();
+    let mut value = base;
+    let mut previous: Option<Vec<Option<Price>>> = None;
+    for day in days {
+        let prices: Vec<Option<Price>> = items.iter().map(|(p, _)| price_at(p, *day)).collect();
+        match previous {
+            Some(ref previous) => {
+                let mut log_sum = 0.0;
+                let mut weights = 0.0;
+                for ((old, new), (_, weight)) in previous.iter().zip(prices.iter()).zip(items) {
+                    if let (Some(old), Some(new)) = (old, new) {
+                        log_sum += weight * (1.0 + new.change_from(*old)).ln();
+                        weights += weight;
+                    }
+                }
+                if weights > 0.0 {
+                    value *= (log_sum / weights).exp();
+                }
+            }
+            None => {
+                // The series starts once at least one basket product has a price
+                if prices.iter().all(Option::is_none) {
+                    continue;
+                }
+            }
+        }
+        series.push(IndexValue { date: *day, value });
+        previous = Some(prices);
+    }
+    series
+}
+
+/// Continues the `published` series with the periods after its last value. Published values never
+/// change, as the products that left since are gone from the history and would change the past.
+/// Those products are only left out of the links after they left.
+fn extend_series(
+    published: Option<&Vec<IndexValue>>,
+    items: &[(&ProductHistory, f64)],
+    days: &[Date],
+) -> Vec<IndexValue> {
+    let Some(last) = published.and_then(|series| series.last()) else {
+        return chain_index(items, days, BASE_VALUE);
+    };
+    let days: Vec<Date> = days.iter().copied().filter(|d| *d >= last.date).collect();
+    let mut series = published.cloned().unwrap_or_default();
+    series.extend(
+        chain_index(items, &days, last.value)
+            .into_iter()
+            .filter(|v| v.date > last.date),
+    );
+    series
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct InflationIndex {
+    frequency: Frequency,
+    overall: Vec<IndexValue>,
+    stores: BTreeMap<Store, Vec<IndexValue>>,
+    categories: BTreeMap<String, Vec<IndexValue>>,
+}
+
+impl InflationIndex {
+    /// Computes the index, continuing the values of the `published` index if it has the same
+    /// frequency
+    pub(crate) fn new(
+        products: &[ProductHistory],
+        basket: &Basket,
+        frequency: Frequency,
+        published: Option<&InflationIndex>,
+    ) -> Self {
+        let published = published.filter(|index| index.frequency == frequency);
+        let items = basket.resolve(products);
+        let days = items
+            .iter()
+            .flat_map(|(p, _)| p.price_history().iter().map(|s| s.date()))
+            .minmax()
+            .into_option()
+            .map(|(start, end)| frequency.sample_days(start, end))
+            .unwrap_or_default();
+
+        let mut by_store: BTreeMap<Store, Vec<(&ProductHistory, f64)>> = BTreeMap::new();
+        let mut by_category: BTreeMap<String, Vec<(&ProductHistory, f64)>> = BTreeMap::new();
+        for item in items.iter() {
+            by_store.entry(item.0.store()).or_default().push(*item);
+            if let Some(category) = item.0.product_info().category() {
+                by_category
+                    .entry(category.code().to_string())
+                    .or_default()
+                    .push(*item);
+            }
+        }
+
+        Self {
+            frequency,
+            overall: extend_series(published.map(|p| &p.overall), &items, &days),
+            stores: by_store
+                .into_iter()
+                .map(|(store, items)| {
+                    let series = published.and_then(|p| p.stores.get(&store));
+                    (store, extend_series(series, &items, &days))
+                })
+                .collect(),
+            categories: by_category
+                .into_iter()
+                .map(|(category, items)| {
+                    let series = published.and_then(|p| p.categories.get(&category));
+                    let series = extend_series(series, &items, &days);
+                    (category, series)
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Computes the index for the basket at `basket_path` from the canonical history. Values that were
+/// already published are kept unless `rebuild` is set, e.g. after the basket changed.
+pub fn do_inflation_index(
+    output_dir: &Path,
+    basket_path: &Path,
+    frequency: Frequency,
+    rebuild: bool,
+) -> anyhow::Result<()> {
+    let basket = Basket::load(basket_path)?;
+    let products = load_history(output_dir)?;
+    let published = match rebuild {
+        true => None,
+        false => load_inflation_index(output_dir)?,
+    };
+    let index = InflationIndex::new(&products, &basket, frequency, published.as_ref());
+    if let Some(latest) = index.overall.last() {
+        info!("Index on {} is {:.2}", latest.date, latest.value);
+    }
+    save_inflation_index(&index, output_dir)
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+    use time::Month;
+
+    use super::*;
+    use crate::product::ProductInfo;
+
+    fn day(month: Month, day: u8) -> Date {
+        Date::from_calendar_date(2024, month, day).unwrap()
+    }
+
+    fn product(store: Store, id: i64, prices: &[(Date, f64)]) -> ProductHistory {
+        ProductHistory::with_prices(ProductInfo::with_store(store).with_id(id), prices)
+    }
+
+    #[test]
+    fn sample_days() {
+        let start = day(Month::January, 3);
+        let end = day(Month::March, 1);
+        let days = Frequency::Monthly.sample_days(start, end);
+        assert_eq!(
+            days,
+            vec![
+                day(Month::January, 1),
+                day(Month::February, 1),
+                day(Month::March, 1)
+            ]
+        );
+        // 2024-01-01 was a Monday
+        let days = Frequency::Weekly.sample_days(start, day(Month::January, 15));
+        assert_eq!(days[0], day(Month::January, 1));
+        assert_eq!(days.len(), 3);
+        assert_eq!(Frequency::Daily.sample_days(start, start), vec![start]);
+    }
+
+    #[test]
+    fn price_in_effect() {
+        let p = product(
+            Store::Coles,
+            1,
+            &[
+                (day(Month::January, 10), 2.0),
+                (day(Month::January, 1), 1.0),
+            ],
+        );
+        assert_eq!(price_at(&p, day(Month::January, 5)), Some(1.0.into()));
+        assert_eq!(price_at(&p, day(Month::January, 10)), Some(2.0.into()));
+        assert_eq!(price_at(&p, day(Month::February, 1)), Some(2.0.into()));
+        assert_eq!(
+            price_at(
+                &p,
+                Date::from_calendar_date(2023, Month::December, 1).unwrap()
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn chain_links_new_products() {
+        let a = product(
+            Store::Coles,
+            1,
+            &[(day(Month::January, 3), 2.0), (day(Month::January, 1), 1.0)],
+        );
+        // Appears on the second day at a high price, which must not move the index
+        let b = product(Store::Coles, 2, &[(day(Month::January, 2), 50.0)]);
+        let items = vec![(&a, 1.0), (&b, 1.0)];
+        let days = Frequency::Daily.sample_days(day(Month::January, 1), day(Month::January, 3));
+        let series = chain_index(&items, &days, BASE_VALUE);
+        let values: Vec<f64> = series.iter().map(|v| v.value).collect();
+        assert_eq!(values[0], 100.0);
+        assert_eq!(values[1], 100.0);
+        // a doubled and b stayed the same, so the geometric mean rose by sqrt(2)
+        assert!((values[2] - 100.0 * 2f64.sqrt()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn basket() {
+        let products = vec![
+            product(Store::Coles, 1, &[(day(Month::January, 1), 1.0)]),
+            product(Store::Woolies, 2, &[(day(Month::January, 1), 1.0)]),
+        ];
+        let basket: Basket = serde_json::from_value(json!({
+            "products": [
+                {"store": "coles", "id": 1},
+                {"store": "coles", "id": 3, "weight": 2.0},
+                {"store": "woolies", "id": 2, "weight": 2.0},
+            ],
+        }))
+        .unwrap();
+        let items = basket.resolve(&products);
+        let weights: Vec<f64> = items.iter().map(|(_, w)| *w).collect();
+        assert_eq!(weights, vec![1.0, 2.0]);
+
+        let index = InflationIndex::new(&products, &basket, Frequency::Daily, None);
+        assert_eq!(index.stores.len(), 2);
+        assert_eq!(
+            index.overall,
+            vec![IndexValue {
+                date: day(Month::January, 1),
+                value: 100.0
+            }]
+        );
+    }
+
+    #[test]
+    fn published_values_stay() {
+        let basket: Basket = serde_json::from_value(json!({
+            "products": [{"store": "coles", "id": 1}, {"store": "coles", "id": 2}],
+        }))
+        .unwrap();
+        let a = product(
+            Store::Coles,
+            1,
+            &[(day(Month::January, 2), 2.0), (day(Month::January, 1), 1.0)],
+        );
+        let b = product(Store::Coles, 2, &[(day(Month::January, 1), 1.0)]);
+        let published = InflationIndex::new(&[a, b], &basket, Frequency::Daily, None);
+        let before: Vec<f64> = published.overall.iter().map(|v| v.value).collect();
+
+        // a is delisted and b doubles on the third day
+        let b = product(
+            Store::Coles,
+            2,
+            &[(day(Month::January, 3), 2.0), (day(Month::January, 1), 1.0)],
+        );
+        let index = InflationIndex::new(&[b], &basket, Frequency::Daily, Some(&published));
+        let values: Vec<f64> = index.overall.iter().map(|v| v.value).collect();
+        assert_eq!(values[..2], before[..]);
+        // Only b links the second and third day
+        assert!((values[2] - 2.0 * before[1]).abs() < 1e-9);
+
+        let rebuilt = InflationIndex::new(&[], &basket, Frequency::Weekly, Some(&published));
+        assert!(
+            rebuilt.overall.is_empty(),
+            "other frequencies aren't continued"
+        );
+    }
+}
@@ -6,15 +6,32 @@
 //!
 //! The library is unlikely to be all the useful to you. Its documentation exists mostly as an
 //! exercise for me, but you're welcome to experiment with it.
+pub mod alerts;
 pub mod analysis;
 mod cache;
 mod category;
-mod conversion;
+pub mod config;
+pub mod conversion;
 mod date;
+pub mod diff;
 mod errors;
+mod feeds;
+pub mod inflation;
+mod manifest;
+mod matching;
+mod price_changes;
 mod product;
+mod reprocess;
 mod retry;
+pub mod run;
+pub mod schema;
+pub mod search;
+pub mod server;
+pub mod shopping;
+pub mod site;
+mod stats;
 mod storage;
 pub mod stores;
 pub mod sync;
 mod unit;
+mod validation;
@@ -1,12 +1,23 @@
 use anyhow::Context;
+use clap::ValueEnum;
 use clap::{Parser, Subcommand};
-use hotprices_au_rs::analysis::{do_analysis, AnalysisType};
+use hotprices_au_rs::alerts::do_alerts;
+use hotprices_au_rs::analysis::{do_analysis, do_restore, AnalysisType};
+use hotprices_au_rs::config::{Config, ValidationAction};
+use hotprices_au_rs::diff::do_diff;
+use hotprices_au_rs::inflation::{do_inflation_index, Frequency};
+use hotprices_au_rs::run::{do_run, RunOptions};
+use hotprices_au_rs::schema::{do_schema, SchemaFormat};
+use hotprices_au_rs::search::{do_search, OutputFormat, SearchQuery, SortOrder};
+use hotprices_au_rs::server::do_serve;
+use hotprices_au_rs::shopping::do_shopping_list;
+use hotprices_au_rs::site::do_site;
 use hotprices_au_rs::stores::Store;
-use hotprices_au_rs::sync::do_sync;
+use hotprices_au_rs::sync::{do_status, do_sync};
 use log::error;
 use std::path::PathBuf;
 use std::result::Result as StdResult;
-use time::{macros::format_description, Date, OffsetDateTime};
+use time::{macros::format_description, Date};
 
 fn configure_logging(cli: &Cli) {
     let mut builder = env_logger::Builder::new();
@@ -24,43 +35,188 @@ fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
     configure_logging(&cli);
 
-    let result = match cli.command {
+    let result = Config::load(cli.config.as_deref())
+        .context("Failed to load configuration")
+        .and_then(|mut config| {
+            if let Some(output_dir) = cli.output_dir {
+                config.output_dir = output_dir;
+            }
+            run(cli.command, config)
+        });
+
+    // Print error message if result contained an error
+    if let Err(ref error) = result.as_ref() {
+        error!("Unexpected error from program: {}", error);
+    }
+    result
+}
+
+fn run(command: Commands, mut config: Config) -> anyhow::Result<()> {
+    match command {
         Commands::Sync {
+            day,
             quick,
             print_save_path,
             skip_existing,
+            resume,
             store,
             cache_path,
-        } => do_sync(
-            store,
-            quick,
-            print_save_path,
-            skip_existing,
-            cli.output_dir,
-            cache_path,
-        ),
+            include_category,
+            exclude_category,
+            only_category,
+            validation,
+        } => {
+            if let Some(action) = validation {
+                config.validation.action = action;
+            }
+            if let Some(cache_path) = cache_path {
+                config.cache_path = cache_path;
+            }
+            config.add_category_filters(store, include_category, exclude_category, only_category);
+            do_sync(
+                store,
+                day,
+                quick,
+                print_save_path,
+                skip_existing,
+                resume,
+                &config,
+            )
+            .map(|_| ())
+        }
         Commands::Analysis {
             day,
             store,
             compress,
             history,
+            reprocess,
             data_dir,
+            conversion_threshold,
+            feeds,
+            base_url,
         } => {
-            let analysis_type = if history {
-                AnalysisType::History
-            } else {
-                AnalysisType::Day(day)
+            let analysis_type = match (history, reprocess, day) {
+                (_, true, _) => AnalysisType::Reprocess,
+                (true, _, _) => AnalysisType::History,
+                (false, false, Some(day)) => AnalysisType::Day(day),
+                (false, false, None) => AnalysisType::Day(config.today()?),
             };
-            do_analysis(analysis_type, store, compress, &cli.output_dir, &data_dir)
-                .context("Failed to perform analysis")
+            if let Some(data_dir) = data_dir {
+                config.data_dir = data_dir;
+            }
+            for (threshold_store, threshold) in conversion_threshold {
+                config.set_conversion_threshold(threshold_store, threshold);
+            }
+            do_analysis(
+                analysis_type,
+                store,
+                compress,
+                &config,
+                feeds.then_some(base_url.as_str()),
+            )
+            .map(|_| ())
+            .context("Failed to perform analysis")
+        }
+        Commands::Run {
+            day,
+            parallel,
+            quick,
+            skip_existing,
+            resume,
+            compress,
+            validation,
+            feeds,
+            base_url,
+        } => {
+            if let Some(action) = validation {
+                config.validation.action = action;
+            }
+            let options = RunOptions {
+                day,
+                parallel,
+                quick,
+                skip_existing,
+                resume,
+                compress,
+            };
+            do_run(&options, &config, feeds.then_some(base_url.as_str()))
+        }
+        Commands::Restore { backup } => {
+            do_restore(&config.output_dir, backup, config.history_backups)
+                .context("Failed to restore history")
+        }
+        Commands::Status { day } => {
+            let day = match day {
+                Some(day) => day,
+                None => config.today()?,
+            };
+            do_status(&config.output_dir, day).context("Failed to show sync status")
+        }
+        Commands::Diff {
+            store,
+            from,
+            to,
+            format,
+        } => do_diff(store, from, to, format, &config).context("Failed to compare snapshots"),
+        Commands::Schema { format } => do_schema(format).context("Failed to print schema"),
+        Commands::Search {
+            text,
+            store,
+            category,
+            id,
+            barcode,
+            min_price,
+            max_price,
+            min_unit_price,
+            max_unit_price,
+            sort,
+            limit,
+            changes,
+            format,
+        } => {
+            let query = SearchQuery {
+                text,
+                store,
+                category,
+                id,
+                barcode,
+                min_price,
+                max_price,
+                min_unit_price,
+                max_unit_price,
+                sort,
+            };
+            do_search(&query, limit, changes, format, &config.output_dir)
+                .context("Failed to search products")
+        }
+        Commands::Alerts { watchlist } => {
+            do_alerts(&config.output_dir, &watchlist).context("Failed to evaluate alerts")
+        }
+        Commands::Inflation {
+            basket,
+            frequency,
+            rebuild,
+        } => do_inflation_index(&config.output_dir, &basket, frequency, rebuild)
+            .context("Failed to compute inflation index"),
+        Commands::Shopping {
+            items,
+            list,
+            format,
+        } => do_shopping_list(&items, list.as_deref(), format, &config.output_dir)
+            .context("Failed to plan shopping list"),
+        Commands::Site { site_dir, base_url } => {
+            do_site(&config.output_dir, &site_dir, &base_url).context("Failed to render site")
+        }
+        Commands::Serve { address } => {
+            do_serve(&config.output_dir, &address).context("Failed to serve API")
+        }
+        Commands::Config {
+            command: ConfigCommand::Show,
+        } => {
+            print!("{}", config.to_toml()?);
+            Ok(())
         }
-    };
-
-    // Print error message if result contained an error
-    if let Err(ref error) = result.as_ref() {
-        error!("Unexpected error from program: {}", error);
     }
-    result
 }
 
 #[derive(Parser)]
@@ -68,8 +224,12 @@ fn main() -> anyhow::Result<()> {
 struct Cli {
     #[arg(long, default_value_t = false)]
     debug: bool,
-    #[arg(long, default_value = "output")]
-    output_dir: PathBuf,
+    /// TOML file with settings, defaults to $HOTPRICES_CONFIG or hotprices.toml if present
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+    /// Overrides `output_dir` from the configuration (default: output)
+    #[arg(long, global = true)]
+    output_dir: Option<PathBuf>,
     #[command(subcommand)]
     command: Commands,
 }
@@ -77,30 +237,204 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
     Sync {
+        /// Day the snapshot is saved for, defaults to today in the configured timezone
+        #[arg(long, value_parser = date_from_str)]
+        day: Option<Date>,
         #[arg(long, default_value_t = false)]
         quick: bool,
         #[arg(long, default_value_t = false)]
         print_save_path: bool,
         #[arg(long, default_value_t = false)]
         skip_existing: bool,
+        /// Skip the store if its manifest shows that today's sync is complete, otherwise only
+        /// fetch the pages that are missing
+        #[arg(long, default_value_t = false)]
+        resume: bool,
         store: Store,
-        #[arg(long, default_value = "cache")]
-        cache_path: PathBuf,
+        /// Overrides `cache_path` from the configuration (default: cache)
+        #[arg(long)]
+        cache_path: Option<PathBuf>,
+        /// Also scrape this category even though it's excluded, e.g. "Beer, Wine & Spirits". Can
+        /// be repeated.
+        #[arg(long)]
+        include_category: Vec<String>,
+        /// Don't scrape this category in addition to the configured ones. Can be repeated.
+        #[arg(long)]
+        exclude_category: Vec<String>,
+        /// Only scrape this category and no others. Can be repeated.
+        #[arg(long)]
+        only_category: Vec<String>,
+        /// What to do with a snapshot missing products, overrides `validation.action`
+        #[arg(long, value_enum)]
+        validation: Option<ValidationAction>,
     },
     Analysis {
-        #[arg(long, value_parser = date_from_str, default_value_t = OffsetDateTime::now_utc().date())]
-        day: Date,
+        /// Day to analyse, defaults to today in the configured timezone
+        #[arg(long, value_parser = date_from_str)]
+        day: Option<Date>,
         #[arg(long)]
         store: Option<Store>,
         #[arg(long, default_value_t = false)]
         compress: bool,
         #[arg(long, default_value_t = false)]
         history: bool,
-        #[arg(long, default_value = "static/data")]
-        data_dir: PathBuf,
+        /// Rebuild the history from all raw snapshots with the current conversion rules and
+        /// report how many products changed quantity, unit or category
+        #[arg(long, default_value_t = false, conflicts_with_all = ["history", "day"])]
+        reprocess: bool,
+        /// Overrides `data_dir` from the configuration (default: static/data)
+        #[arg(long)]
+        data_dir: Option<PathBuf>,
+        /// Maximum share of failed product conversions, either for all stores (e.g. 0.05) or
+        /// for a single store (e.g. coles=0.1). Can be repeated.
+        #[arg(long, value_parser = threshold_from_str)]
+        conversion_threshold: Vec<(Option<Store>, f64)>,
+        /// Write Atom feeds of price drops, new and delisted products to the output directory
+        #[arg(long, default_value_t = false)]
+        feeds: bool,
+        /// Address of the site the feed entries link to
+        #[arg(long, default_value = "https://hotprices.org")]
+        base_url: String,
+    },
+    /// Sync every store and then analyse the day, printing a summary of the run
+    Run {
+        /// Day the snapshots are saved for, defaults to today in the configured timezone
+        #[arg(long, value_parser = date_from_str)]
+        day: Option<Date>,
+        /// Sync the stores at the same time instead of one after another
+        #[arg(long, default_value_t = false)]
+        parallel: bool,
+        #[arg(long, default_value_t = false)]
+        quick: bool,
+        #[arg(long, default_value_t = false)]
+        skip_existing: bool,
+        /// Skip stores whose manifest shows that today's sync is complete
+        #[arg(long, default_value_t = false)]
+        resume: bool,
+        #[arg(long, default_value_t = false)]
+        compress: bool,
+        /// What to do with a snapshot missing products, overrides `validation.action`
+        #[arg(long, value_enum)]
+        validation: Option<ValidationAction>,
+        /// Write Atom feeds of price drops, new and delisted products to the output directory
+        #[arg(long, default_value_t = false)]
+        feeds: bool,
+        /// Address of the site the feed entries link to
+        #[arg(long, default_value = "https://hotprices.org")]
+        base_url: String,
+    },
+    /// Replace the canonical history with one of its backups, keeping the replaced one as backup 1
+    Restore {
+        /// Which backup to restore, 1 is the most recent
+        #[arg(default_value_t = 1)]
+        backup: usize,
+    },
+    /// Show how far the sync of every store got on a day
+    Status {
+        /// Defaults to today in the configured timezone
+        #[arg(long, value_parser = date_from_str)]
+        day: Option<Date>,
+    },
+    /// Compare the raw snapshots of a store on two days
+    Diff {
+        store: Store,
+        #[arg(value_parser = date_from_str)]
+        from: Date,
+        #[arg(value_parser = date_from_str)]
+        to: Date,
+        #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+        format: OutputFormat,
+    },
+    /// Print the JSON Schema of the canonical history or of the site data
+    Schema {
+        #[arg(value_enum, default_value_t = SchemaFormat::Canonical)]
+        format: SchemaFormat,
+    },
+    /// Look up products in the canonical history
+    Search {
+        /// Words that have to appear in the product name or brand
+        text: Option<String>,
+        #[arg(long)]
+        store: Option<Store>,
+        /// Category code or prefix of one, e.g. 31 for meat or 3 for all meat & seafood
+        #[arg(long)]
+        category: Option<String>,
+        #[arg(long)]
+        id: Option<i64>,
+        /// GTIN printed under the barcode, e.g. 9300633603608
+        #[arg(long)]
+        barcode: Option<String>,
+        #[arg(long)]
+        min_price: Option<f64>,
+        #[arg(long)]
+        max_price: Option<f64>,
+        #[arg(long)]
+        min_unit_price: Option<f64>,
+        #[arg(long)]
+        max_unit_price: Option<f64>,
+        #[arg(long, value_enum, default_value_t = SortOrder::Name)]
+        sort: SortOrder,
+        /// Maximum number of products to show
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+        /// Number of previous prices to show for each product
+        #[arg(long, default_value_t = 5)]
+        changes: usize,
+        #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+        format: OutputFormat,
+    },
+    /// Check watched products against their alert rules, run after analysis
+    Alerts {
+        #[arg(long, default_value = "watchlist.json")]
+        watchlist: PathBuf,
+    },
+    /// Compute a price index over a basket of products
+    Inflation {
+        /// JSON file listing basket products and/or category weights
+        #[arg(long, default_value = "basket.json")]
+        basket: PathBuf,
+        #[arg(long, value_enum, default_value_t = Frequency::Monthly)]
+        frequency: Frequency,
+        /// Recompute values that were already published, e.g. after changing the basket
+        #[arg(long, default_value_t = false)]
+        rebuild: bool,
+    },
+    /// Find the cheapest way to buy a shopping list, per store and split across stores
+    Shopping {
+        /// Items such as "2L full cream milk" or "1kg carrots"
+        items: Vec<String>,
+        /// File with one item per line
+        #[arg(long)]
+        list: Option<PathBuf>,
+        #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+        format: OutputFormat,
+    },
+    /// Render a static site with product pages, price charts, search and a sitemap
+    Site {
+        #[arg(long, default_value = "site")]
+        site_dir: PathBuf,
+        /// Address the site is published at, used for the sitemaps
+        #[arg(long, default_value = "https://hotprices.org")]
+        base_url: String,
+    },
+    /// Serve the canonical history over a JSON API
+    Serve {
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        address: String,
+    },
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
     },
 }
 
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Print the effective configuration after applying the file, environment and flags
+    Show,
+}
+
 fn date_from_str(s: &str) -> StdResult<Date, String> {
     let format = format_description!("[year]-[month]-[day]");
     match Date::parse(s, &format) {
@@ -111,6 +445,20 @@ fn date_from_str(s: &str) -> StdResult<Date, String> {
     }
 }
 
+fn threshold_from_str(s: &str) -> StdResult<(Option<Store>, f64), String> {
+    let (store, threshold) = match s.split_once('=') {
+        Some((store, threshold)) => (Some(Store::from_str(store, true)?), threshold),
+        None => (None, s),
+    };
+    let threshold: f64 = threshold
+        .parse()
+        .map_err(|e| format!("Error parsing threshold {threshold:?}: {e}"))?;
+    if !(0.0..=1.0).contains(&threshold) {
+        return Err(format!("Threshold {threshold} must be between 0 and 1"));
+    }
+    Ok((store, threshold))
+}
+
 #[test]
 fn verify_cli() {
     use clap::CommandFactory;
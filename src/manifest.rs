@@ -0,0 +1,175 @@
+//! Record of how far the sync of a store got on a day, so an interrupted or partially failed sync
+//! can be resumed and its progress reported
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use time::Date;
+
+use crate::date::date_serde;
+use crate::storage::save_manifest;
+use crate::stores::Store;
+use crate::validation::Issue;
+
+/// Progress of fetching the pages of a single category
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct CategoryProgress {
+    /// Number of products the store reported for the category
+    pub(crate) expected_products: Option<i64>,
+    pub(crate) products: usize,
+    pub(crate) pages: usize,
+    /// Pages that failed but were tolerated, they are fetched again by `sync --resume`
+    pub(crate) failed_pages: Vec<i32>,
+    /// Error the category failed with
+    pub(crate) error: Option<String>,
+}
+
+impl CategoryProgress {
+    pub(crate) fn is_complete(&self) -> bool {
+        self.error.is_none() && self.failed_pages.is_empty()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SyncManifest {
+    #[serde(skip)]
+    path: PathBuf,
+    store: Store,
+    #[serde(with = "date_serde")]
+    day: Date,
+    categories: BTreeMap<String, CategoryProgress>,
+    /// Whether the snapshot was written
+    finished: bool,
+    /// Problems found by validating the snapshot before it was written
+    #[serde(default)]
+    issues: Vec<Issue>,
+    /// The snapshot is known to miss products, so the ones missing from it aren't delisted
+    #[serde(default)]
+    partial: bool,
+}
+
+impl SyncManifest {
+    pub(crate) fn new(path: PathBuf, store: Store, day: Date) -> Self {
+        Self {
+            path,
+            store,
+            day,
+            categories: BTreeMap::new(),
+            finished: false,
+            issues: Vec::new(),
+            partial: false,
+        }
+    }
+
+    /// Restores the path a loaded manifest is saved to
+    pub(crate) fn with_path(mut self, path: PathBuf) -> Self {
+        self.path = path;
+        self
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub(crate) fn insert(&mut self, category: String, progress: CategoryProgress) {
+        self.categories.insert(category, progress);
+    }
+
+    /// Records the progress of a category and saves the manifest, so it's up to date even if the
+    /// sync is interrupted
+    pub(crate) fn record(
+        &mut self,
+        category: String,
+        progress: CategoryProgress,
+    ) -> anyhow::Result<()> {
+        self.insert(category, progress);
+        save_manifest(self)
+    }
+
+    pub(crate) fn finish(&mut self) -> anyhow::Result<()> {
+        self.finished = true;
+        save_manifest(self)
+    }
+
+    /// Keeps the validation result, `partial` marks the snapshot as missing products
+    pub(crate) fn set_validation(&mut self, issues: Vec<Issue>, partial: bool) {
+        self.issues = issues;
+        self.partial = partial;
+    }
+
+    pub(crate) fn is_partial(&self) -> bool {
+        self.partial
+    }
+
+    pub(crate) fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// The snapshot was written and no page is missing from it
+    pub(crate) fn is_complete(&self) -> bool {
+        self.finished && self.categories.values().all(CategoryProgress::is_complete)
+    }
+
+    pub(crate) fn status(&self) -> &'static str {
+        if self.is_complete() && !self.partial {
+            "complete"
+        } else if self.finished {
+            "partial"
+        } else if !self.issues.is_empty() {
+            // Only a snapshot that failed validation isn't written after all categories were
+            "blocked"
+        } else {
+            "incomplete"
+        }
+    }
+
+    pub(crate) fn issues(&self) -> &[Issue] {
+        &self.issues
+    }
+
+    pub(crate) fn categories(&self) -> &BTreeMap<String, CategoryProgress> {
+        &self.categories
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use time::Month;
+
+    use super::*;
+    use crate::storage::load_manifest;
+
+    #[test]
+    fn record_and_finish() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("manifest.json");
+        let day = Date::from_calendar_date(2024, Month::January, 1).unwrap();
+        let mut manifest = SyncManifest::new(path.clone(), Store::Coles, day);
+        manifest
+            .record(
+                String::from("bakery"),
+                CategoryProgress {
+                    expected_products: Some(100),
+                    products: 52,
+                    pages: 3,
+                    failed_pages: vec![2],
+                    error: None,
+                },
+            )
+            .unwrap();
+        assert_eq!(manifest.status(), "incomplete");
+
+        let mut loaded = load_manifest(&path).unwrap().expect("manifest was saved");
+        assert_eq!(loaded.path(), path);
+        assert_eq!(loaded.categories()["bakery"].failed_pages, vec![2]);
+        loaded.finish().unwrap();
+        assert_eq!(loaded.status(), "partial");
+
+        loaded
+            .record(String::from("bakery"), CategoryProgress::default())
+            .unwrap();
+        assert_eq!(loaded.status(), "complete");
+    }
+}
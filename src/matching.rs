@@ -0,0 +1,472 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    product::{is_store_barcode, ProductHistory, ProductInfo},
+    stores::Store,
+    unit::{parse_str_unit, Unit},
+};
+
+// Automatic matches below this confidence are discarded
+const MIN_CONFIDENCE: f64 = 0.6;
+// Relative difference in quantity that still counts as the same pack size
+const QUANTITY_TOLERANCE: f64 = 0.02;
+// Share of the confidence score contributed by name similarity, the rest comes from the brand
+const NAME_WEIGHT: f64 = 0.7;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub(crate) struct ProductKey {
+    store: Store,
+    id: i64,
+}
+
+impl ProductKey {
+    pub(crate) fn new(store: Store, id: i64) -> Self {
+        Self { store, id }
+    }
+}
+
+/// Manually curated matches that take precedence over automatic matching
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct MatchOverrides {
+    /// Products that are always put into the same group
+    #[serde(default)]
+    groups: Vec<Vec<ProductKey>>,
+    /// Products that must never be matched with each other
+    #[serde(default)]
+    exclude: Vec<Vec<ProductKey>>,
+}
+
+impl MatchOverrides {
+    fn is_excluded(&self, a: ProductKey, b: ProductKey) -> bool {
+        self.exclude
+            .iter()
+            .any(|keys| keys.contains(&a) && keys.contains(&b))
+    }
+}
+
+/// Equivalent products across stores
+#[derive(Debug, Serialize)]
+pub(crate) struct MatchGroup {
+    id: String,
+    confidence: f64,
+    manual: bool,
+    products: Vec<ProductKey>,
+}
+
+impl MatchGroup {
+    fn new(mut products: Vec<ProductKey>, confidence: f64, manual: bool) -> Self {
+        products.sort();
+        // The first product after sorting is stable across runs, so its key makes a stable id
+        let id = format!("{}-{}", products[0].store, products[0].id);
+        Self {
+            id,
+            confidence,
+            manual,
+            products,
+        }
+    }
+}
+
+struct Candidate<'a> {
+    key: ProductKey,
+    info: &'a ProductInfo,
+    brand: Option<String>,
+    tokens: HashSet<String>,
+}
+
+impl<'a> Candidate<'a> {
+    fn new(info: &'a ProductInfo) -> Self {
+        let brand = info.brand().map(|b| normalise_tokens(b).join(" "));
+        let mut tokens: HashSet<String> = normalise_tokens(info.name()).into_iter().collect();
+        // Brand is scored separately and Coles prefixes it to the name, so don't count it twice
+        if let Some(brand) = info.brand() {
+            for token in normalise_tokens(brand) {
+                tokens.remove(&token);
+            }
+        }
+        Self {
+            key: ProductKey::new(info.store(), info.id()),
+            info,
+            brand,
+            tokens,
+        }
+    }
+
+    fn confidence(&self, other: &Candidate) -> Option<f64> {
+        if self.info.unit() != other.info.unit() {
+            return None;
+        }
+        let (a, b) = (self.info.quantity(), other.info.quantity());
+        if (a - b).abs() > a.max(b) * QUANTITY_TOLERANCE {
+            return None;
+        }
+        if let (Some(a), Some(b)) = (self.info.category(), other.info.category()) {
+            if a != b {
+                return None;
+            }
+        }
+
+        let brand_score = match (&self.brand, &other.brand) {
+            (Some(a), Some(b)) if a == b => 1.0,
+            // Different brands are never the same product, even if the names are identical
+            (Some(_), Some(_)) => return None,
+            // Unknown brand is neither evidence for nor against a match
+            _ => 0.5,
+        };
+
+        let intersection = self.tokens.intersection(&other.tokens).count();
+        let union = self.tokens.union(&other.tokens).count();
+        if union == 0 {
+            return None;
+        }
+        let name_score = intersection as f64 / union as f64;
+        Some(name_score * NAME_WEIGHT + brand_score * (1.0 - NAME_WEIGHT))
+    }
+}
+
+/// Lowercase alphanumeric words of `s`, without pack sizes like "150g" which are compared
+/// separately through quantity and unit
+fn normalise_tokens(s: &str) -> Vec<String> {
+    s.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|t| !t.is_empty())
+        .filter(|t| !(t.starts_with(|c: char| c.is_ascii_digit()) && parse_str_unit(t).is_ok()))
+        .map(String::from)
+        .collect()
+}
+
+/// Pairs equivalent products across stores based on barcode or, where that's missing, on brand,
+/// name, pack size and category. Coles only has barcodes if they were fetched, otherwise its
+/// products are matched on the latter. Every product ends up in at most one group.
+pub(crate) fn match_products(
+    products: &[ProductHistory],
+    overrides: &MatchOverrides,
+) -> Vec<MatchGroup> {
+    let mut groups = Vec::new();
+    let mut matched: HashSet<ProductKey> = HashSet::new();
+    let known: HashSet<ProductKey> = products
+        .iter()
+        .map(|p| ProductKey::new(p.store(), p.id()))
+        .collect();
+
+    for keys in overrides.groups.iter() {
+        let keys: Vec<ProductKey> = keys
+            .iter()
+            .filter(|k| known.contains(k) && !matched.contains(k))
+            .copied()
+            .collect();
+        if keys.len() < 2 {
+            continue;
+        }
+        matched.extend(keys.iter().copied());
+        groups.push(MatchGroup::new(keys, 1.0, true));
+    }
+
+    // Products sharing a barcode are the same product, no matter how each store names them
+    let mut by_barcode: BTreeMap<&str, Vec<ProductKey>> = BTreeMap::new();
+    for product in products {
+        let key = ProductKey::new(product.store(), product.id());
+        match product.product_info().barcode() {
+            Some(barcode) if !matched.contains(&key) && !is_store_barcode(barcode) => {
+                by_barcode.entry(barcode).or_default().push(key)
+            }
+            _ => {}
+        }
+    }
+    for keys in by_barcode.into_values() {
+        let stores: HashSet<Store> = keys.iter().map(|k| k.store).collect();
+        if stores.len() < 2 {
+            continue;
+        }
+        let excluded = keys
+            .iter()
+            .enumerate()
+            .any(|(i, a)| keys[i + 1..].iter().any(|b| overrides.is_excluded(*a, *b)));
+        if excluded {
+            continue;
+        }
+        matched.extend(keys.iter().copied());
+        groups.push(MatchGroup::new(keys, 1.0, false));
+    }
+
+    let mut by_store: HashMap<Store, Vec<Candidate>> = HashMap::new();
+    for product in products {
+        let candidate = Candidate::new(product.product_info());
+        if matched.contains(&candidate.key) {
+            continue;
+        }
+        by_store.entry(product.store()).or_default().push(candidate);
+    }
+
+    let (Some(coles), Some(woolies)) = (by_store.get(&Store::Coles), by_store.get(&Store::Woolies))
+    else {
+        return groups;
+    };
+
+    // Only compare products with the same unit and a similar quantity to keep the number of
+    // comparisons down
+    let mut woolies_by_unit: HashMap<Unit, Vec<&Candidate>> = HashMap::new();
+    for candidate in woolies {
+        woolies_by_unit
+            .entry(candidate.info.unit())
+            .or_default()
+            .push(candidate);
+    }
+    for candidates in woolies_by_unit.values_mut() {
+        candidates.sort_by(|a, b| a.info.quantity().total_cmp(&b.info.quantity()));
+    }
+
+    let mut pairs = Vec::new();
+    for a in coles {
+        let Some(others) = woolies_by_unit.get(&a.info.unit()) else {
+            continue;
+        };
+        let quantity = a.info.quantity();
+        let start =
+            others.partition_point(|b| b.info.quantity() < quantity * (1.0 - QUANTITY_TOLERANCE));
+        for b in others[start..].iter() {
+            if b.info.quantity() > quantity * (1.0 + QUANTITY_TOLERANCE) {
+                break;
+            }
+            if overrides.is_excluded(a.key, b.key) {
+                continue;
+            }
+            if let Some(confidence) = a.confidence(b) {
+                if confidence >= MIN_CONFIDENCE {
+                    pairs.push((confidence, a.key, b.key));
+                }
+            }
+        }
+    }
+
+    // Greedily accept the best pairs first so each product is matched at most once
+    pairs.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)).then(a.2.cmp(&b.2)));
+    let mut automatic = groups.iter().filter(|g| !g.manual).count();
+    for (confidence, a, b) in pairs {
+        if matched.contains(&a) || matched.contains(&b) {
+            continue;
+        }
+        matched.insert(a);
+        matched.insert(b);
+        groups.push(MatchGroup::new(vec![a, b], confidence, false));
+        automatic += 1;
+    }
+    info!(
+        "Matched {} product groups across stores ({automatic} automatic)",
+        groups.len()
+    );
+
+    groups.sort_by(|a, b| a.id.cmp(&b.id));
+    groups
+}
+
+/// Sets the match group id on every product that is part of a group and clears it on all others
+pub(crate) fn apply_match_groups(products: &mut [ProductHistory], groups: &[MatchGroup]) {
+    let mut lookup = HashMap::new();
+    for group in groups {
+        for key in group.products.iter() {
+            lookup.insert(*key, group.id.as_str());
+        }
+    }
+    for product in products.iter_mut() {
+        let key = ProductKey::new(product.store(), product.id());
+        product.set_match_group(lookup.get(&key).map(|id| id.to_string()));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn product(store: Store, id: i64, name: &str, brand: &str, quantity: f64) -> ProductHistory {
+        ProductHistory::with_info(ProductInfo::new(
+            id,
+            String::from(name),
+            String::new(),
+            Some(String::from(brand)),
+            None,
+            None,
+            Unit::Millilitre,
+            quantity,
+            store,
+            None,
+        ))
+    }
+
+    #[test]
+    fn tokens() {
+        assert_eq!(
+            normalise_tokens("Pauls Full Cream Milk | 2L"),
+            vec!["pauls", "full", "cream", "milk"]
+        );
+    }
+
+    #[test]
+    fn matches_equivalent_products() {
+        let products = vec![
+            product(Store::Coles, 1, "Pauls Full Cream Milk 2L", "Pauls", 2000.0),
+            product(
+                Store::Woolies,
+                2,
+                "Pauls Full Cream Milk 2l",
+                "Pauls",
+                2000.0,
+            ),
+            product(Store::Woolies, 3, "Pauls Light Milk 2l", "Pauls", 2000.0),
+        ];
+        let groups = match_products(&products, &MatchOverrides::default());
+        let [ref group] = groups[..] else {
+            panic!("expected exactly one group, got {groups:?}");
+        };
+        assert_eq!(group.id, "coles-1");
+        assert_eq!(
+            group.products,
+            vec![
+                ProductKey::new(Store::Coles, 1),
+                ProductKey::new(Store::Woolies, 2)
+            ]
+        );
+        assert!(group.confidence > 0.9);
+        assert!(!group.manual);
+    }
+
+    #[test]
+    fn skips_different_quantity() {
+        let products = vec![
+            product(Store::Coles, 1, "Pauls Full Cream Milk", "Pauls", 2000.0),
+            product(Store::Woolies, 2, "Pauls Full Cream Milk", "Pauls", 1000.0),
+        ];
+        let groups = match_products(&products, &MatchOverrides::default());
+        assert!(groups.is_empty());
+    }
+
+    #[test]
+    fn skips_different_brand() {
+        let products = vec![
+            product(Store::Coles, 1, "Coles Full Cream Milk", "Coles", 2000.0),
+            product(
+                Store::Woolies,
+                2,
+                "Woolworths Full Cream Milk",
+                "Woolworths",
+                2000.0,
+            ),
+        ];
+        let groups = match_products(&products, &MatchOverrides::default());
+        assert!(groups.is_empty());
+    }
+
+    #[test]
+    fn overrides() {
+        let products = vec![
+            product(Store::Coles, 1, "Pauls Full Cream Milk", "Pauls", 2000.0),
+            product(Store::Woolies, 2, "Pauls Full Cream Milk", "Pauls", 2000.0),
+            product(Store::Woolies, 3, "Something else", "Other", 1.0),
+        ];
+        let overrides: MatchOverrides = serde_json::from_value(serde_json::json!({
+            "groups": [[{"store": "woolies", "id": 3}, {"store": "coles", "id": 1}]],
+            "exclude": [],
+        }))
+        .unwrap();
+        let groups = match_products(&products, &overrides);
+        let [ref group] = groups[..] else {
+            panic!("expected exactly one group, got {groups:?}");
+        };
+        assert!(group.manual);
+        assert_eq!(group.confidence, 1.0);
+        assert_eq!(group.products[1], ProductKey::new(Store::Woolies, 3));
+    }
+
+    #[test]
+    fn exclude_override() {
+        let products = vec![
+            product(Store::Coles, 1, "Pauls Full Cream Milk", "Pauls", 2000.0),
+            product(Store::Woolies, 2, "Pauls Full Cream Milk", "Pauls", 2000.0),
+        ];
+        let overrides: MatchOverrides = serde_json::from_value(serde_json::json!({
+            "exclude": [[{"store": "woolies", "id": 2}, {"store": "coles", "id": 1}]],
+        }))
+        .unwrap();
+        assert!(match_products(&products, &overrides).is_empty());
+    }
+
+    fn with_barcode(product: ProductHistory, barcode: &str) -> ProductHistory {
+        let info = product.product_info();
+        let info = ProductInfo::new(
+            info.id(),
+            String::from(info.name()),
+            String::new(),
+            info.brand().map(String::from),
+            Some(String::from(barcode)),
+            None,
+            info.unit(),
+            info.quantity(),
+            info.store(),
+            None,
+        );
+        ProductHistory::with_info(info)
+    }
+
+    #[test]
+    fn matches_barcode() {
+        let products = vec![
+            with_barcode(
+                product(Store::Coles, 1, "Full Cream Milk", "Pauls", 2000.0),
+                "9310036001140",
+            ),
+            with_barcode(
+                product(Store::Woolies, 2, "Milk Full Cream", "Pauls", 1000.0),
+                "09310036001140",
+            ),
+            with_barcode(
+                product(Store::Woolies, 3, "Full Cream Milk", "Pauls", 2000.0),
+                "9300633603069",
+            ),
+        ];
+        let groups = match_products(&products, &MatchOverrides::default());
+        let [ref group] = groups[..] else {
+            panic!("expected exactly one group, got {groups:?}");
+        };
+        assert_eq!(group.confidence, 1.0);
+        assert_eq!(
+            group.products,
+            vec![
+                ProductKey::new(Store::Coles, 1),
+                ProductKey::new(Store::Woolies, 2)
+            ]
+        );
+    }
+
+    #[test]
+    fn ignores_store_barcode() {
+        let products = vec![
+            with_barcode(
+                product(Store::Coles, 1, "Bananas", "", 1.0),
+                "2000000000008",
+            ),
+            with_barcode(
+                product(Store::Woolies, 2, "Apples", "", 1.0),
+                "2000000000008",
+            ),
+        ];
+        assert!(match_products(&products, &MatchOverrides::default()).is_empty());
+    }
+
+    #[test]
+    fn apply_groups() {
+        let mut products = vec![
+            product(Store::Coles, 1, "Pauls Full Cream Milk", "Pauls", 2000.0),
+            product(Store::Woolies, 2, "Pauls Full Cream Milk", "Pauls", 2000.0),
+            product(Store::Woolies, 3, "Something else", "Other", 1.0),
+        ];
+        let groups = match_products(&products, &MatchOverrides::default());
+        apply_match_groups(&mut products, &groups);
+        let products = serde_json::to_value(&products).unwrap();
+        assert_eq!(products[0]["matchGroup"], "coles-1");
+        assert_eq!(products[1]["matchGroup"], "coles-1");
+        assert!(products[2].get("matchGroup").is_none());
+    }
+}
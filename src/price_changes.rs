@@ -0,0 +1,244 @@
+use std::collections::{BTreeMap, HashSet};
+
+use serde::Serialize;
+use time::Date;
+
+use crate::{
+    date::date_serde,
+    product::{price_serde, Price, ProductHistory},
+    stores::Store,
+};
+
+// Number of biggest drops and rises listed per store and category
+const TOP_CHANGES: usize = 20;
+
+/// A product's price going from one value to another on a given day
+#[derive(Debug, Serialize)]
+pub(crate) struct PriceChange {
+    store: Store,
+    id: i64,
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    category: Option<&'static str>,
+    #[serde(with = "date_serde")]
+    date: Date,
+    #[serde(rename = "oldPrice", with = "price_serde")]
+    old_price: Price,
+    #[serde(rename = "newPrice", with = "price_serde")]
+    new_price: Price,
+    #[serde(with = "price_serde")]
+    delta: Price,
+    /// Relative change, e.g. -0.25 for a 25% drop
+    percent: f64,
+    #[serde(
+        rename = "unitPriceDelta",
+        with = "price_serde::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    unit_price_delta: Option<Price>,
+    promotion: bool,
+}
+
+impl PriceChange {
+    pub(crate) fn store(&self) -> Store {
+        self.store
+    }
+
+    pub(crate) fn id(&self) -> i64 {
+        self.id
+    }
+
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub(crate) fn category(&self) -> Option<&'static str> {
+        self.category
+    }
+
+    pub(crate) fn date(&self) -> Date {
+        self.date
+    }
+
+    pub(crate) fn old_price(&self) -> Price {
+        self.old_price
+    }
+
+    pub(crate) fn new_price(&self) -> Price {
+        self.new_price
+    }
+
+    pub(crate) fn percent(&self) -> f64 {
+        self.percent
+    }
+}
+
+/// All price changes that happened on one of `days`. Both prices of a change come from the
+/// history, so this also works when several days were merged in one run.
+pub(crate) fn price_changes(products: &[ProductHistory], days: &[Date]) -> Vec<PriceChange> {
+    let days: HashSet<Date> = days.iter().copied().collect();
+    let mut changes = Vec::new();
+    for product in products {
+        let info = product.product_info();
+        let history: Vec<_> = product.price_history().iter().collect();
+        // History is sorted with the latest price first
+        for pair in history.windows(2) {
+            let [new, old] = pair else {
+                unreachable!("windows of two")
+            };
+            if !days.contains(&new.date()) {
+                continue;
+            }
+            // Quantity and unit can only be taken from the current product info, so unit prices
+            // are compared as if the pack size didn't change
+            let unit_price = |price: Price| price.per_unit(info.quantity(), info.unit());
+            let unit_price_delta = match (unit_price(new.price()), unit_price(old.price())) {
+                (Some(new), Some(old)) => Some(new - old),
+                _ => None,
+            };
+            changes.push(PriceChange {
+                store: info.store(),
+                id: info.id(),
+                name: info.name().to_string(),
+                category: info.category().map(|c| c.code()),
+                date: new.date(),
+                old_price: old.price(),
+                new_price: new.price(),
+                delta: new.price() - old.price(),
+                percent: new.price().change_from(old.price()),
+                unit_price_delta,
+                promotion: new.promotion(),
+            });
+        }
+    }
+    changes
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Ranking<'a> {
+    drops: Vec<&'a PriceChange>,
+    rises: Vec<&'a PriceChange>,
+}
+
+impl<'a> Ranking<'a> {
+    fn new(changes: impl Iterator<Item = &'a PriceChange>) -> Self {
+        let mut drops = Vec::new();
+        let mut rises = Vec::new();
+        for change in changes {
+            if change.percent < 0.0 {
+                drops.push(change);
+            } else if change.percent > 0.0 {
+                rises.push(change);
+            }
+        }
+        drops.sort_by(|a, b| a.percent.total_cmp(&b.percent));
+        drops.truncate(TOP_CHANGES);
+        rises.sort_by(|a, b| b.percent.total_cmp(&a.percent));
+        rises.truncate(TOP_CHANGES);
+        Self { drops, rises }
+    }
+}
+
+/// Price changes of an analysis run with the biggest drops and rises per store and category
+#[derive(Debug, Serialize)]
+pub(crate) struct PriceChangeReport<'a> {
+    days: Vec<String>,
+    stores: BTreeMap<Store, Ranking<'a>>,
+    categories: BTreeMap<&'static str, Ranking<'a>>,
+    changes: &'a [PriceChange],
+}
+
+impl<'a> PriceChangeReport<'a> {
+    pub(crate) fn new(days: &[Date], changes: &'a [PriceChange]) -> Self {
+        let stores = changes
+            .iter()
+            .map(|c| c.store)
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .map(|store| {
+                let ranking = Ranking::new(changes.iter().filter(|c| c.store == store));
+                (store, ranking)
+            })
+            .collect();
+        let categories = changes
+            .iter()
+            .filter_map(|c| c.category)
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .map(|category| {
+                let ranking = Ranking::new(changes.iter().filter(|c| c.category == Some(category)));
+                (category, ranking)
+            })
+            .collect();
+        Self {
+            days: days.iter().map(|d| d.to_string()).collect(),
+            stores,
+            categories,
+            changes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use time::Month;
+
+    use super::*;
+    use crate::product::ProductInfo;
+
+    fn day(day: u8) -> Date {
+        Date::from_calendar_date(2024, Month::January, day).unwrap()
+    }
+
+    fn product(store: Store, id: i64, prices: &[(Date, f64)]) -> ProductHistory {
+        ProductHistory::with_prices(ProductInfo::with_store(store).with_id(id), prices)
+    }
+
+    #[test]
+    fn changes_on_day() {
+        let products = vec![
+            product(
+                Store::Coles,
+                1,
+                &[(day(3), 3.0), (day(2), 4.0), (day(1), 5.0)],
+            ),
+            product(Store::Coles, 2, &[(day(2), 2.0), (day(1), 1.0)]),
+            product(Store::Woolies, 3, &[(day(3), 1.0)]),
+        ];
+        let changes = price_changes(&products, &[day(3)]);
+        let [ref change] = changes[..] else {
+            panic!("expected one change, got {changes:?}");
+        };
+        assert_eq!(change.id, 1);
+        assert_eq!(change.old_price, 4.0.into());
+        assert_eq!(change.new_price, 3.0.into());
+        assert_eq!(change.delta, (-1.0).into());
+        assert_eq!(change.percent, -0.25);
+        assert!(!change.promotion);
+
+        // Merging several days at once reports the changes of all of them
+        let changes = price_changes(&products, &[day(2), day(3)]);
+        assert_eq!(changes.len(), 3);
+    }
+
+    #[test]
+    fn report_ranking() {
+        let products = vec![
+            product(Store::Coles, 1, &[(day(2), 3.0), (day(1), 4.0)]),
+            product(Store::Coles, 2, &[(day(2), 1.0), (day(1), 2.0)]),
+            product(Store::Coles, 3, &[(day(2), 2.0), (day(1), 1.0)]),
+        ];
+        let days = [day(2)];
+        let changes = price_changes(&products, &days);
+        let report = PriceChangeReport::new(&days, &changes);
+        let ranking = &report.stores[&Store::Coles];
+        let drops: Vec<i64> = ranking.drops.iter().map(|c| c.id).collect();
+        assert_eq!(drops, vec![2, 1]);
+        let rises: Vec<i64> = ranking.rises.iter().map(|c| c.id).collect();
+        assert_eq!(rises, vec![3]);
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["days"], serde_json::json!(["2024-01-02"]));
+        assert_eq!(json["stores"]["coles"]["drops"][0]["percent"], -0.5);
+    }
+}
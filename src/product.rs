@@ -1,8 +1,11 @@
 use std::cmp::Ordering;
 use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::ops::{Add, Mul, Sub};
 
-use log::info;
+use log::{debug, info};
 use nonempty::{nonempty, NonEmpty};
+use schemars::JsonSchema;
 use serde::{self, Deserialize, Serialize};
 use time::Date;
 
@@ -11,11 +14,17 @@ use crate::category::Category;
 use crate::category::CategoryCode;
 use crate::{stores::Store, unit::Unit};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub(crate) struct ProductInfo {
     id: i64,
     name: String,
     description: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    brand: Option<String>,
+    /// GTIN of the product, zero-padded to 14 digits. Woolies has it for most products, Coles
+    /// only if the sync fetched barcodes or the search result happened to include it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    barcode: Option<String>,
     #[serde(rename = "isWeighted")]
     is_weighted: bool,
     unit: Unit,
@@ -23,6 +32,14 @@ pub(crate) struct ProductInfo {
     store: Store,
     #[serde(flatten)]
     category: Option<CategoryCode>,
+    #[serde(
+        rename = "unitPrice",
+        default,
+        with = "price_serde::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[schemars(with = "Option<f64>")]
+    unit_price: Option<Price>,
 }
 
 impl ProductInfo {
@@ -31,6 +48,8 @@ impl ProductInfo {
         id: i64,
         name: String,
         description: String,
+        brand: Option<String>,
+        barcode: Option<String>,
         is_weighted: Option<bool>,
         unit: Unit,
         quantity: f64,
@@ -41,13 +60,82 @@ impl ProductInfo {
             id,
             name,
             description,
+            brand: brand.filter(|b| !b.is_empty()),
+            barcode: barcode.as_deref().and_then(normalise_barcode),
             is_weighted: is_weighted.unwrap_or(false),
             unit,
             quantity,
             store,
             category,
+            unit_price: None,
         }
     }
+
+    pub(crate) fn id(&self) -> i64 {
+        self.id
+    }
+
+    pub(crate) fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    pub(crate) fn brand(&self) -> Option<&str> {
+        self.brand.as_deref()
+    }
+
+    pub(crate) fn barcode(&self) -> Option<&str> {
+        self.barcode.as_deref()
+    }
+
+    pub(crate) fn unit(&self) -> Unit {
+        self.unit
+    }
+
+    pub(crate) fn quantity(&self) -> f64 {
+        self.quantity
+    }
+
+    pub(crate) fn store(&self) -> Store {
+        self.store
+    }
+
+    pub(crate) fn category(&self) -> Option<Category> {
+        self.category.as_ref().map(|v| v.category)
+    }
+
+    pub(crate) fn unit_price(&self) -> Option<Price> {
+        self.unit_price
+    }
+}
+
+/// Validates a GTIN-8/12/13/14 and zero-pads it to 14 digits so the same product compares equal
+/// regardless of which length a store reports
+pub(crate) fn normalise_barcode(raw: &str) -> Option<String> {
+    let raw = raw.trim();
+    if ![8, 12, 13, 14].contains(&raw.len()) || !raw.bytes().all(|b| b.is_ascii_digit()) {
+        debug!("Ignoring invalid barcode '{raw}'");
+        return None;
+    }
+    let gtin = format!("{raw:0>14}");
+    let digits: Vec<u32> = gtin.bytes().map(|b| (b - b'0') as u32).collect();
+    // Weights alternate 3, 1, 3, ... starting from the digit left of the check digit
+    let sum: u32 = digits[..13]
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, d)| if i % 2 == 0 { d * 3 } else { *d })
+        .sum();
+    if (10 - sum % 10) % 10 != digits[13] {
+        debug!("Ignoring barcode '{raw}' with invalid check digit");
+        return None;
+    }
+    Some(gtin)
+}
+
+/// Barcodes in the restricted circulation range (GTIN-13 prefix 2) are assigned by each store
+/// for things like weighed produce and don't identify the same product across stores
+pub(crate) fn is_store_barcode(gtin: &str) -> bool {
+    gtin.as_bytes().get(1) == Some(&b'2')
 }
 
 #[cfg_attr(test, derive(Default))]
@@ -58,10 +146,22 @@ pub(crate) struct ProductSnapshot {
 }
 
 impl ProductSnapshot {
-    pub(crate) fn new(product_info: ProductInfo, price: Price, date: Date) -> Self {
+    /// `was_price` is the regular price the store reports next to a reduced one, if any
+    pub(crate) fn new(
+        mut product_info: ProductInfo,
+        price: Price,
+        was_price: Option<Price>,
+        date: Date,
+    ) -> Self {
+        // Unit prices are always derived here so both stores follow the same rules
+        product_info.unit_price = price.per_unit(product_info.quantity, product_info.unit);
         Self {
             product_info,
-            price_snapshot: PriceSnapshot { date, price },
+            price_snapshot: PriceSnapshot {
+                date,
+                price,
+                promotion: was_price.is_some_and(|was_price| was_price > price),
+            },
         }
     }
 
@@ -69,12 +169,10 @@ impl ProductSnapshot {
         self.product_info.id
     }
 
-    #[cfg(test)]
     pub(crate) fn name(&self) -> &str {
         self.product_info.name.as_str()
     }
 
-    #[cfg(test)]
     pub(crate) fn description(&self) -> &str {
         self.product_info.description.as_str()
     }
@@ -88,12 +186,10 @@ impl ProductSnapshot {
         self.product_info.store
     }
 
-    #[cfg(test)]
     pub(crate) fn unit(&self) -> Unit {
         self.product_info.unit
     }
 
-    #[cfg(test)]
     pub(crate) fn quantity(&self) -> f64 {
         self.product_info.quantity
     }
@@ -105,14 +201,27 @@ impl ProductSnapshot {
     pub(crate) fn price(&self) -> Price {
         self.price_snapshot.price
     }
+
+    #[cfg(test)]
+    pub(crate) fn unit_price(&self) -> Option<Price> {
+        self.product_info.unit_price
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub(crate) struct ProductHistory {
     #[serde(flatten)]
     product_info: ProductInfo,
+    /// Most recent first, never empty
     #[serde(rename = "priceHistory")]
+    #[schemars(with = "Vec<PriceSnapshot>", length(min = 1))]
     price_history: NonEmpty<PriceSnapshot>,
+    #[serde(
+        rename = "matchGroup",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    match_group: Option<String>,
 }
 
 impl ProductHistory {
@@ -141,6 +250,23 @@ impl ProductHistory {
     pub(crate) fn store(&self) -> Store {
         self.product_info.store
     }
+
+    pub(crate) fn product_info(&self) -> &ProductInfo {
+        &self.product_info
+    }
+
+    /// Price changes, most recent first
+    pub(crate) fn price_history(&self) -> &NonEmpty<PriceSnapshot> {
+        &self.price_history
+    }
+
+    pub(crate) fn match_group(&self) -> Option<&str> {
+        self.match_group.as_deref()
+    }
+
+    pub(crate) fn set_match_group(&mut self, match_group: Option<String>) {
+        self.match_group = match_group;
+    }
 }
 
 impl From<ProductSnapshot> for ProductHistory {
@@ -148,18 +274,38 @@ impl From<ProductSnapshot> for ProductHistory {
         Self {
             product_info: product_snapshot.product_info,
             price_history: nonempty![product_snapshot.price_snapshot],
+            match_group: None,
         }
     }
 }
 
 use crate::date::date_serde;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub(crate) struct PriceSnapshot {
     #[serde(with = "date_serde")]
+    #[schemars(with = "String", regex(pattern = r"^\d{4}-\d{2}-\d{2}$"))]
     date: Date,
     #[serde(with = "price_serde")]
+    #[schemars(with = "f64")]
     price: Price,
+    /// Whether the store advertised this price as reduced from a higher regular price
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    promotion: bool,
+}
+
+impl PriceSnapshot {
+    pub(crate) fn date(&self) -> Date {
+        self.date
+    }
+
+    pub(crate) fn price(&self) -> Price {
+        self.price
+    }
+
+    pub(crate) fn promotion(&self) -> bool {
+        self.promotion
+    }
 }
 
 impl Ord for PriceSnapshot {
@@ -186,11 +332,77 @@ impl PartialEq for PriceSnapshot {
 
 impl Eq for PriceSnapshot {}
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub(crate) struct Price {
     price: i32,
 }
 
+impl Price {
+    /// Price for the standard amount of `unit` (see [`Unit::unit_price_quantity`]) if `quantity`
+    /// of it cost this price. Returns `None` if no meaningful unit price can be calculated.
+    pub(crate) fn per_unit(&self, quantity: f64, unit: Unit) -> Option<Price> {
+        if !quantity.is_finite() || quantity <= 0.0 {
+            return None;
+        }
+        let price = f64::from(self.price) * unit.unit_price_quantity() / quantity;
+        Some(Self {
+            price: price.round() as i32,
+        })
+    }
+
+    /// Relative change from `previous` to this price, e.g. -0.25 for a 25% discount
+    pub(crate) fn change_from(&self, previous: Price) -> f64 {
+        if previous.price == 0 {
+            return 0.0;
+        }
+        f64::from(self.price - previous.price) / f64::from(previous.price)
+    }
+}
+
+impl Display for Price {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sign = if self.price < 0 { "-" } else { "" };
+        let cents = self.price.unsigned_abs();
+        write!(f, "{sign}{}.{:02}", cents / 100, cents % 100)
+    }
+}
+
+impl From<Price> for f64 {
+    fn from(price: Price) -> Self {
+        f64::from(price.price) / 100.0
+    }
+}
+
+impl Sub for Price {
+    type Output = Price;
+
+    fn sub(self, other: Price) -> Price {
+        Price {
+            price: self.price - other.price,
+        }
+    }
+}
+
+impl Add for Price {
+    type Output = Price;
+
+    fn add(self, other: Price) -> Price {
+        Price {
+            price: self.price + other.price,
+        }
+    }
+}
+
+impl Mul<u32> for Price {
+    type Output = Price;
+
+    fn mul(self, count: u32) -> Price {
+        Price {
+            price: self.price * count as i32,
+        }
+    }
+}
+
 impl From<f64> for Price {
     fn from(price: f64) -> Self {
         Self {
@@ -218,12 +430,39 @@ pub(crate) mod price_serde {
         let price = f64::deserialize(deserializer)?;
         Ok(price.into())
     }
+
+    pub(crate) mod option {
+        use super::Price;
+        use serde::{self, Deserialize, Deserializer, Serializer};
+
+        pub(crate) fn serialize<S>(price: &Option<Price>, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            match price {
+                Some(price) => super::serialize(price, serializer),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub(crate) fn deserialize<'de, D>(deserializer: D) -> Result<Option<Price>, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            let price = Option::<f64>::deserialize(deserializer)?;
+            Ok(price.map(Price::from))
+        }
+    }
 }
 
+/// Adds the prices of `new_items` to the history of `old_items`. Old products that aren't in
+/// `new_items` are dropped, unless their store's snapshot is `partial` and might just be missing
+/// them.
 pub(crate) fn merge_price_history(
     old_items: Vec<ProductHistory>,
     new_items: Vec<ProductSnapshot>,
     store_filter: Option<Store>,
+    partial: &[Store],
 ) -> Vec<ProductHistory> {
     let mut result: Vec<ProductHistory> = Vec::with_capacity(new_items.len());
 
@@ -237,23 +476,57 @@ pub(crate) fn merge_price_history(
     }
 
     let mut store_price_count: HashMap<Store, u64> = HashMap::new();
+    let mut unknown = Vec::new();
     for new in new_items {
-        if let Some(mut old) = old_map.remove(&(new.store(), new.id())) {
-            let has_new_price = old.update_from_snapshot(new);
+        if let Some(old) = old_map.remove(&(new.store(), new.id())) {
+            result.push(update_history(old, new, &mut store_price_count));
+        } else {
+            unknown.push(new);
+        }
+    }
 
-            // Track new prices
-            if has_new_price {
-                *store_price_count.entry(old.store()).or_insert(0) += 1;
+    // Products with a new id might be re-issued stockcodes, in which case their history is
+    // found through the barcode. Only products that weren't matched by id above are considered,
+    // and only ones with a barcode, which excludes most Coles products.
+    let mut old_barcodes = HashMap::new();
+    for (key, old) in old_map.iter() {
+        if let Some(barcode) = old.product_info.barcode() {
+            old_barcodes.insert((old.store(), barcode.to_string()), *key);
+        }
+    }
+    for new in unknown {
+        let old_key = new
+            .product_info
+            .barcode()
+            .and_then(|barcode| old_barcodes.remove(&(new.store(), barcode.to_string())));
+        match old_key.and_then(|key| old_map.remove(&key)) {
+            Some(old) => {
+                info!(
+                    "Product {} of store '{}' continues as {} based on its barcode",
+                    old.id(),
+                    old.store(),
+                    new.id()
+                );
+                result.push(update_history(old, new, &mut store_price_count));
             }
-            result.push(old);
-        } else {
-            result.push(new.into());
+            None => result.push(new.into()),
         }
     }
 
     if !old_map.is_empty() {
         info!("{} products not in latest product list", old_map.len());
     }
+    let kept: Vec<ProductHistory> = old_map
+        .into_values()
+        .filter(|old| partial.contains(&old.store()))
+        .collect();
+    if !kept.is_empty() {
+        info!(
+            "Keeping {} products missing from partial snapshots",
+            kept.len()
+        );
+    }
+    result.extend(kept);
 
     for (store, count) in store_price_count {
         info!("Store '{store}' has {count} new prices");
@@ -262,9 +535,23 @@ pub(crate) fn merge_price_history(
     result
 }
 
+fn update_history(
+    mut old: ProductHistory,
+    new: ProductSnapshot,
+    store_price_count: &mut HashMap<Store, u64>,
+) -> ProductHistory {
+    let has_new_price = old.update_from_snapshot(new);
+
+    // Track new prices
+    if has_new_price {
+        *store_price_count.entry(old.store()).or_insert(0) += 1;
+    }
+    old
+}
+
 #[cfg(test)]
 mod test_merge_price_history {
-    use nonempty::nonempty;
+    use nonempty::{nonempty, NonEmpty};
     use time::{Date, Month};
 
     use crate::{stores::Store, unit::Unit};
@@ -277,11 +564,14 @@ mod test_merge_price_history {
                 id: 1,
                 name: String::from("test name"),
                 description: String::from("test description"),
+                brand: None,
+                barcode: None,
                 is_weighted: false,
                 unit: Unit::Grams,
                 quantity: 1.0,
                 store: Store::Coles,
                 category: None,
+                unit_price: None,
             }
         }
     }
@@ -293,6 +583,10 @@ mod test_merge_price_history {
                 ..Default::default()
             }
         }
+
+        pub(crate) fn with_id(self, id: i64) -> Self {
+            Self { id, ..self }
+        }
     }
 
     impl Default for PriceSnapshot {
@@ -300,6 +594,7 @@ mod test_merge_price_history {
             Self {
                 date: Date::from_calendar_date(2024, Month::January, 10).expect("valid date"),
                 price: 1.0.into(),
+                promotion: false,
             }
         }
     }
@@ -309,6 +604,7 @@ mod test_merge_price_history {
             Self {
                 product_info: ProductInfo::default(),
                 price_history: nonempty![PriceSnapshot::default()],
+                match_group: None,
             }
         }
     }
@@ -320,6 +616,23 @@ mod test_merge_price_history {
                 ..Default::default()
             }
         }
+
+        /// History with the given prices, most recent first
+        pub(crate) fn with_prices(product_info: ProductInfo, prices: &[(Date, f64)]) -> Self {
+            let prices: Vec<PriceSnapshot> = prices
+                .iter()
+                .map(|(date, price)| PriceSnapshot {
+                    date: *date,
+                    price: (*price).into(),
+                    promotion: false,
+                })
+                .collect();
+            Self {
+                product_info,
+                price_history: NonEmpty::from_vec(prices).expect("at least one price"),
+                match_group: None,
+            }
+        }
     }
 
     #[test]
@@ -329,6 +642,7 @@ mod test_merge_price_history {
                 date: Date::from_calendar_date(2024, Month::January, 10)
                     .expect("should be valid date"),
                 price: 1.0.into(),
+                promotion: false,
             }],
             ..Default::default()
         }];
@@ -338,11 +652,12 @@ mod test_merge_price_history {
                 date: Date::from_calendar_date(2024, Month::January, 11)
                     .expect("should be valid date"),
                 price: 0.5.into(),
+                promotion: false,
             },
             ..Default::default()
         }];
 
-        let merged = merge_price_history(old, new, None);
+        let merged = merge_price_history(old, new, None, &[]);
         let [ref merged] = merged[..] else {
             panic!("unexpected result size")
         };
@@ -376,7 +691,7 @@ mod test_merge_price_history {
             ..Default::default()
         }];
 
-        let merged = merge_price_history(old, new, None);
+        let merged = merge_price_history(old, new, None, &[]);
         let product_ids: Vec<i64> = merged.iter().map(|p| p.id()).collect();
         assert!(!product_ids.contains(&1));
         assert!(product_ids.contains(&2));
@@ -389,6 +704,7 @@ mod test_merge_price_history {
                 date: Date::from_calendar_date(2024, Month::January, 10)
                     .expect("should be valid date"),
                 price: 1.0.into(),
+                promotion: false,
             }],
             ..Default::default()
         }];
@@ -398,11 +714,12 @@ mod test_merge_price_history {
                 date: Date::from_calendar_date(2024, Month::January, 11)
                     .expect("should be valid date"),
                 price: 1.0.into(),
+                promotion: false,
             },
             ..Default::default()
         }];
 
-        let merged = merge_price_history(old, new, None);
+        let merged = merge_price_history(old, new, None, &[]);
         let [ref merged] = merged[..] else {
             panic!("unexpected result size")
         };
@@ -419,11 +736,13 @@ mod test_merge_price_history {
                     date: Date::from_calendar_date(2024, Month::January, 10)
                         .expect("should be valid date"),
                     price: 1.0.into(),
+                    promotion: false,
                 },
                 PriceSnapshot {
                     date: Date::from_calendar_date(2024, Month::January, 9)
                         .expect("should be valid date"),
                     price: 0.5.into(),
+                    promotion: false,
                 },
             ],
             ..Default::default()
@@ -434,11 +753,12 @@ mod test_merge_price_history {
                 date: Date::from_calendar_date(2024, Month::January, 11)
                     .expect("should be valid date"),
                 price: 1.0.into(),
+                promotion: false,
             },
             ..Default::default()
         }];
 
-        let merged = merge_price_history(old, new, None);
+        let merged = merge_price_history(old, new, None, &[]);
         let [ref merged] = merged[..] else {
             panic!("unexpected result size")
         };
@@ -457,6 +777,7 @@ mod test_merge_price_history {
                 date: Date::from_calendar_date(2024, Month::January, 10)
                     .expect("should be valid date"),
                 price: 1.0.into(),
+                promotion: false,
             }],
             ..Default::default()
         }];
@@ -470,10 +791,11 @@ mod test_merge_price_history {
                 date: Date::from_calendar_date(2024, Month::January, 11)
                     .expect("should be valid date"),
                 price: 0.5.into(),
+                promotion: false,
             },
         }];
 
-        let merged = merge_price_history(old, new, None);
+        let merged = merge_price_history(old, new, None, &[]);
         let [ref merged] = merged[..] else {
             panic!("unexpected result size")
         };
@@ -485,7 +807,7 @@ mod test_merge_price_history {
     fn it_has_no_old_products() {
         let old: Vec<ProductHistory> = Vec::new();
         let new = vec![ProductSnapshot::default()];
-        let merged = merge_price_history(old, new, None);
+        let merged = merge_price_history(old, new, None, &[]);
         assert_eq!(merged.len(), 1);
     }
 
@@ -505,13 +827,35 @@ mod test_merge_price_history {
             },
             ..Default::default()
         }];
-        let merged = merge_price_history(old, new, None);
+        let merged = merge_price_history(old, new, None, &[]);
         let [ref merged] = merged[..] else {
             panic!("unexpected result size")
         };
         assert_eq!(merged.id(), 2);
     }
 
+    #[test]
+    fn it_keeps_products_missing_from_partial_snapshots() {
+        let old: Vec<ProductHistory> = vec![ProductHistory {
+            product_info: ProductInfo {
+                id: 1,
+                ..Default::default()
+            },
+            ..Default::default()
+        }];
+        let new = vec![ProductSnapshot {
+            product_info: ProductInfo {
+                id: 2,
+                ..Default::default()
+            },
+            ..Default::default()
+        }];
+        let store = ProductSnapshot::default().store();
+        let merged = merge_price_history(old, new, None, &[store]);
+        let ids: Vec<i64> = merged.iter().map(|p| p.id()).collect();
+        assert_eq!(ids, vec![2, 1]);
+    }
+
     #[test]
     fn merge_with_store_filter() {
         let old = vec![
@@ -539,7 +883,7 @@ mod test_merge_price_history {
             ..Default::default()
         }];
 
-        let merged = merge_price_history(old, new, Some(Store::Coles));
+        let merged = merge_price_history(old, new, Some(Store::Coles), &[]);
         assert_eq!(
             merged.len(),
             2,
@@ -579,6 +923,38 @@ mod test_merge_price_history {
         assert_eq!(first.date, new_date);
         assert_eq!(second.date, old_date);
     }
+
+    #[test]
+    fn it_follows_barcode() {
+        let old = vec![ProductHistory {
+            product_info: ProductInfo {
+                id: 1,
+                barcode: Some(String::from("09310036001140")),
+                ..Default::default()
+            },
+            ..Default::default()
+        }];
+
+        let new = vec![ProductSnapshot {
+            product_info: ProductInfo {
+                id: 2,
+                barcode: Some(String::from("09310036001140")),
+                ..Default::default()
+            },
+            price_snapshot: PriceSnapshot {
+                date: Date::from_calendar_date(2024, Month::January, 11).unwrap(),
+                price: 0.5.into(),
+                promotion: false,
+            },
+        }];
+
+        let merged = merge_price_history(old, new, None, &[]);
+        let [ref merged] = merged[..] else {
+            panic!("unexpected result size")
+        };
+        assert_eq!(merged.id(), 2);
+        assert_eq!(merged.price_history.len(), 2);
+    }
 }
 
 pub(crate) fn deduplicate_products(products: Vec<ProductSnapshot>) -> Vec<ProductSnapshot> {
@@ -613,9 +989,39 @@ mod test_deduplicate_products {
     }
 }
 
+#[cfg(test)]
+mod test_barcode {
+    use super::{is_store_barcode, normalise_barcode};
+
+    #[test]
+    fn test_normalise() {
+        assert_eq!(
+            normalise_barcode("9310036001140"),
+            Some(String::from("09310036001140"))
+        );
+        assert_eq!(
+            normalise_barcode(" 96385074 "),
+            Some(String::from("00000096385074"))
+        );
+        assert_eq!(normalise_barcode("9310036001147"), None);
+        assert_eq!(normalise_barcode("93100360"), None);
+        assert_eq!(normalise_barcode("abc"), None);
+        assert_eq!(normalise_barcode(""), None);
+    }
+
+    #[test]
+    fn test_store_barcode() {
+        assert!(is_store_barcode("02000000000008"));
+        assert!(!is_store_barcode("09310036001140"));
+    }
+}
+
 #[cfg(test)]
 mod test_price {
-    use super::Price;
+    use time::{Date, Month};
+
+    use super::{Price, ProductInfo, ProductSnapshot};
+    use crate::unit::Unit;
 
     #[test]
     fn test_into() {
@@ -624,4 +1030,49 @@ mod test_price {
         let price: Price = 0.5.into();
         assert_eq!(price.price, 50);
     }
+
+    #[test]
+    fn test_change_from() {
+        assert_eq!(Price::from(3.0).change_from(4.0.into()), -0.25);
+        assert_eq!(Price::from(5.0).change_from(4.0.into()), 0.25);
+        assert_eq!(Price::from(5.0).change_from(0.0.into()), 0.0);
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(Price::from(12.5).to_string(), "12.50");
+        assert_eq!(Price::from(0.05).to_string(), "0.05");
+        assert_eq!(Price::from(-1.5).to_string(), "-1.50");
+        assert_eq!((Price::from(1.0) - Price::from(1.25)).to_string(), "-0.25");
+    }
+
+    #[test]
+    fn test_per_unit() {
+        let price: Price = 6.7.into();
+        assert_eq!(price.per_unit(150.0, Unit::Grams), Some(44.67.into()));
+        assert_eq!(price.per_unit(2000.0, Unit::Millilitre), Some(3.35.into()));
+        assert_eq!(price.per_unit(4.0, Unit::Each), Some(1.68.into()));
+        assert_eq!(price.per_unit(50.0, Unit::Centimetre), Some(13.4.into()));
+    }
+
+    #[test]
+    fn test_per_unit_invalid_quantity() {
+        let price: Price = 1.0.into();
+        assert_eq!(price.per_unit(0.0, Unit::Grams), None);
+        assert_eq!(price.per_unit(-1.0, Unit::Grams), None);
+        assert_eq!(price.per_unit(f64::NAN, Unit::Grams), None);
+    }
+
+    #[test]
+    fn test_snapshot_unit_price() {
+        let product_info = ProductInfo {
+            quantity: 500.0,
+            unit: Unit::Grams,
+            ..Default::default()
+        };
+        let date = Date::from_calendar_date(2024, Month::January, 1).unwrap();
+        let snapshot = ProductSnapshot::new(product_info, 2.0.into(), Some(2.5.into()), date);
+        assert_eq!(snapshot.unit_price(), Some(4.0.into()));
+        assert!(snapshot.price_snapshot.promotion);
+    }
 }
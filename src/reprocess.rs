@@ -0,0 +1,111 @@
+//! What rebuilding the history from the raw snapshots changed, see [`AnalysisType::Reprocess`]
+//!
+//! [`AnalysisType::Reprocess`]: crate::analysis::AnalysisType::Reprocess
+use std::collections::HashMap;
+use std::io::Write;
+
+use crate::product::ProductHistory;
+
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct ReprocessReport {
+    products: usize,
+    quantity: usize,
+    unit: usize,
+    category: usize,
+    /// Products that weren't in the history before
+    added: usize,
+    /// Products of the previous history that aren't in any snapshot
+    dropped: usize,
+}
+
+impl ReprocessReport {
+    pub(crate) fn new(previous: &[ProductHistory], current: &[ProductHistory]) -> Self {
+        let mut previous: HashMap<_, _> = previous
+            .iter()
+            .map(|p| ((p.store(), p.id()), p.product_info()))
+            .collect();
+        let mut report = Self {
+            products: current.len(),
+            ..Self::default()
+        };
+        for product in current.iter() {
+            let Some(before) = previous.remove(&(product.store(), product.id())) else {
+                report.added += 1;
+                continue;
+            };
+            let after = product.product_info();
+            if before.quantity() != after.quantity() {
+                report.quantity += 1;
+            }
+            if before.unit() != after.unit() {
+                report.unit += 1;
+            }
+            if before.category() != after.category() {
+                report.category += 1;
+            }
+        }
+        report.dropped = previous.len();
+        report
+    }
+
+    pub(crate) fn write_table(&self, mut out: impl Write) -> std::io::Result<()> {
+        writeln!(out, "Reprocessed {} products", self.products)?;
+        writeln!(out, "{:<18} {:>8}", "Changed quantity", self.quantity)?;
+        writeln!(out, "{:<18} {:>8}", "Changed unit", self.unit)?;
+        writeln!(out, "{:<18} {:>8}", "Changed category", self.category)?;
+        writeln!(out, "{:<18} {:>8}", "Added", self.added)?;
+        writeln!(out, "{:<18} {:>8}", "Dropped", self.dropped)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use time::{Date, Month};
+
+    use super::*;
+    use crate::product::{ProductInfo, ProductSnapshot};
+    use crate::stores::Store;
+    use crate::unit::Unit;
+
+    fn product(id: i64, unit: Unit, quantity: f64) -> ProductHistory {
+        let info = ProductInfo::new(
+            id,
+            String::from("Product"),
+            String::new(),
+            None,
+            None,
+            None,
+            unit,
+            quantity,
+            Store::Woolies,
+            None,
+        );
+        let day = Date::from_calendar_date(2024, Month::January, 1).unwrap();
+        ProductSnapshot::new(info, 1.0.into(), None, day).into()
+    }
+
+    #[test]
+    fn counts_changes() {
+        let previous = vec![
+            product(1, Unit::Each, 1.0),
+            product(2, Unit::Each, 1.0),
+            product(3, Unit::Grams, 500.0),
+        ];
+        let current = vec![
+            product(1, Unit::Each, 1.0),
+            product(2, Unit::Grams, 150.0),
+            product(4, Unit::Grams, 500.0),
+        ];
+        assert_eq!(
+            ReprocessReport::new(&previous, &current),
+            ReprocessReport {
+                products: 3,
+                quantity: 1,
+                unit: 1,
+                category: 0,
+                added: 1,
+                dropped: 1,
+            }
+        );
+    }
+}
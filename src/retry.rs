@@ -1,8 +1,19 @@
 use log::{error, info};
+use std::cell::Cell;
 use std::num::NonZeroU32;
 use std::result::Result as StdResult;
 use std::{thread, time::Duration};
 
+thread_local! {
+    // Stores are synced on separate threads, so retries are counted per thread
+    static RETRIES: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Number of retried requests on the current thread so far
+pub(crate) fn retry_count() -> usize {
+    RETRIES.with(|retries| retries.get())
+}
+
 pub struct RetryPolicy {
     total: NonZeroU32,
     max_backoff: Duration,
@@ -37,6 +48,7 @@ impl RetryPolicy {
                             sleep_time.as_secs(),
                             error
                         );
+                        RETRIES.with(|retries| retries.set(retries.get() + 1));
                         thread::sleep(sleep_time);
                         continue;
                     }
@@ -108,6 +120,8 @@ mod test_retry {
             .unwrap();
         assert_eq!(result.status(), 200);
         assert_eq!(retry_counter.into_inner(), 2);
+        // Tests run on their own thread, so only this test's retry is counted
+        assert_eq!(retry_count(), 1);
     }
 
     #[test]
@@ -0,0 +1,258 @@
+//! Syncs every store and analyses the day in one go, which is what the daily scrape does
+use std::io::Write;
+use std::thread;
+use std::time::Instant;
+
+use anyhow::{anyhow, bail, Context};
+use itertools::Itertools;
+use log::error;
+use serde::Serialize;
+use strum::IntoEnumIterator;
+use time::Date;
+
+use crate::analysis::{do_analysis, AnalysisType};
+use crate::config::Config;
+use crate::conversion::ConversionMetrics;
+use crate::date::date_serde;
+use crate::storage::save_run_summary;
+use crate::stores::Store;
+use crate::sync::{do_sync, SyncSummary};
+
+#[derive(Serialize)]
+pub struct FailedSync {
+    store: Store,
+    error: String,
+}
+
+/// Everything a run did, printed at the end and saved to `runs/{day}.json`
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RunSummary {
+    #[serde(with = "date_serde")]
+    day: Date,
+    syncs: Vec<SyncSummary>,
+    /// Stores whose sync failed, which are left out of the analysis
+    failed: Vec<FailedSync>,
+    conversions: Vec<ConversionMetrics>,
+    /// Products with a new price
+    price_changes: usize,
+    duration_secs: f64,
+}
+
+impl RunSummary {
+    fn write_table(&self, mut out: impl Write) -> std::io::Result<()> {
+        writeln!(
+            out,
+            "{:<8} {:>8} {:>6} {:>6} {:>7} {:>7} {:>8}",
+            "STORE", "PRODUCTS", "PAGES", "CACHED", "RETRIES", "FAILED", "TIME"
+        )?;
+        for sync in self.syncs.iter() {
+            let failed = self
+                .conversions
+                .iter()
+                .find(|c| c.store() == sync.store)
+                .map_or(String::from("-"), |c| c.failure().to_string());
+            let products = if sync.skipped {
+                String::from("skipped")
+            } else {
+                sync.products.to_string()
+            };
+            writeln!(
+                out,
+                "{:<8} {:>8} {:>6} {:>6} {:>7} {:>7} {:>7.0}s",
+                sync.store.to_string(),
+                products,
+                sync.pages_fetched,
+                sync.pages_cached,
+                sync.retries,
+                failed,
+                sync.duration_secs
+            )?;
+        }
+        for failed in self.failed.iter() {
+            writeln!(out, "{:<8} {:>8}", failed.store.to_string(), "failed")?;
+        }
+        writeln!(out)?;
+        for failed in self.failed.iter() {
+            writeln!(out, "Failed to sync {}: {}", failed.store, failed.error)?;
+        }
+        writeln!(out, "New prices: {}", self.price_changes)?;
+        writeln!(out, "Total time: {:.0}s", self.duration_secs)
+    }
+}
+
+/// Flags of [`do_run`]
+#[derive(Debug, Default)]
+pub struct RunOptions {
+    /// Day the snapshots are saved for, today in the configured timezone if not set
+    pub day: Option<Date>,
+    /// Sync the stores at the same time instead of one after another
+    pub parallel: bool,
+    pub quick: bool,
+    pub skip_existing: bool,
+    pub resume: bool,
+    pub compress: bool,
+}
+
+fn sync_all(options: &RunOptions, config: &Config) -> Vec<(Store, anyhow::Result<SyncSummary>)> {
+    let sync = |store| {
+        do_sync(
+            store,
+            options.day,
+            options.quick,
+            false,
+            options.skip_existing,
+            options.resume,
+            config,
+        )
+    };
+    if !options.parallel {
+        return Store::iter().map(|store| (store, sync(store))).collect();
+    }
+    thread::scope(|scope| {
+        let handles: Vec<_> = Store::iter()
+            .map(|store| {
+                let handle = scope.spawn(move || sync(store));
+                (store, handle)
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|(store, handle)| {
+                let result = handle
+                    .join()
+                    .unwrap_or_else(|_| Err(anyhow!("Sync of {store} panicked")));
+                (store, result)
+            })
+            .collect()
+    })
+}
+
+/// Syncs all stores, one after another or in parallel, and then runs the analysis for the day
+/// that was synced. A store that fails to sync doesn't stop the others from being analysed, but
+/// the run still fails once the summary is written.
+pub fn do_run(
+    options: &RunOptions,
+    config: &Config,
+    feeds_base_url: Option<&str>,
+) -> anyhow::Result<()> {
+    let start = Instant::now();
+    let mut syncs = Vec::new();
+    let mut failed = Vec::new();
+    for (store, result) in sync_all(options, config) {
+        match result {
+            Ok(sync) => syncs.push(sync),
+            Err(e) => {
+                error!("Failed to sync {store}: {e:?}");
+                failed.push(FailedSync {
+                    store,
+                    error: format!("{e:#}"),
+                });
+            }
+        }
+    }
+    let day = match syncs.iter().map(|s| s.day).max() {
+        Some(day) => day,
+        None => match options.day {
+            Some(day) => day,
+            None => config.today()?,
+        },
+    };
+
+    // Without a snapshot for every store the ones that synced are analysed one at a time
+    let stores: Vec<Option<Store>> = if failed.is_empty() {
+        vec![None]
+    } else {
+        syncs.iter().map(|s| Some(s.store)).collect()
+    };
+    let mut conversions = Vec::new();
+    let mut price_changes = 0;
+    for store in stores {
+        let analysis = do_analysis(
+            AnalysisType::Day(day),
+            store,
+            options.compress,
+            config,
+            feeds_base_url,
+        )
+        .context("Failed to perform analysis")?;
+        conversions.extend(analysis.conversions);
+        price_changes += analysis.price_changes;
+    }
+
+    let summary = RunSummary {
+        day,
+        syncs,
+        failed,
+        conversions,
+        price_changes,
+        duration_secs: start.elapsed().as_secs_f64(),
+    };
+    save_run_summary(&summary, day, &config.output_dir)?;
+    summary.write_table(std::io::stdout().lock())?;
+    if !summary.failed.is_empty() {
+        let stores = summary.failed.iter().map(|f| f.store).join(", ");
+        bail!("Failed to sync {stores}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use time::Month;
+
+    use super::*;
+
+    #[test]
+    fn table() {
+        let day = Date::from_calendar_date(2024, Month::January, 1).unwrap();
+        let summary = RunSummary {
+            day,
+            syncs: vec![
+                SyncSummary {
+                    store: Store::Coles,
+                    day,
+                    skipped: false,
+                    products: 1234,
+                    pages_fetched: 40,
+                    pages_cached: 2,
+                    retries: 3,
+                    issues: 0,
+                    partial: false,
+                    duration_secs: 61.4,
+                },
+                SyncSummary {
+                    store: Store::Woolies,
+                    day,
+                    skipped: true,
+                    products: 0,
+                    pages_fetched: 0,
+                    pages_cached: 0,
+                    retries: 0,
+                    issues: 0,
+                    partial: false,
+                    duration_secs: 0.0,
+                },
+            ],
+            failed: vec![FailedSync {
+                store: Store::Coles,
+                error: String::from("Too many errors"),
+            }],
+            conversions: Vec::new(),
+            price_changes: 17,
+            duration_secs: 65.0,
+        };
+        let mut out = Vec::new();
+        summary.write_table(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(
+            lines[1],
+            "coles        1234     40      2       3       -      61s"
+        );
+        assert!(lines[2].starts_with("woolies   skipped"));
+        assert_eq!(lines[3], "coles      failed");
+        assert_eq!(lines[5], "Failed to sync coles: Too many errors");
+        assert_eq!(lines[6], "New prices: 17");
+    }
+}
@@ -0,0 +1,194 @@
+//! Versions of the canonical history, the migrations between them and JSON Schemas of the files
+//! read by other tools
+use std::io::Write;
+
+use anyhow::{anyhow, bail, Context};
+use clap::ValueEnum;
+use log::info;
+use schemars::{schema::RootSchema, schema_for, JsonSchema};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::product::ProductHistory;
+
+/// Version of the canonical history written by this code. Bumping it requires a migration from
+/// the previous version in [`MIGRATIONS`].
+pub(crate) const CANONICAL_VERSION: u64 = 2;
+
+/// The canonical history as it's saved to `latest-canonical.json.gz`
+#[derive(Serialize, JsonSchema)]
+#[schemars(rename = "Canonical")]
+pub(crate) struct Canonical<'a> {
+    version: u64,
+    products: &'a [ProductHistory],
+}
+
+impl<'a> Canonical<'a> {
+    pub(crate) fn new(products: &'a [ProductHistory]) -> Self {
+        Self {
+            version: CANONICAL_VERSION,
+            products,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct CanonicalFile {
+    products: Vec<ProductHistory>,
+}
+
+struct Migration {
+    /// Version the migration upgrades from, to the one after it
+    from: u64,
+    description: &'static str,
+    migrate: fn(Value) -> anyhow::Result<Value>,
+}
+
+const MIGRATIONS: &[Migration] = &[Migration {
+    from: 1,
+    description: "wrap products in a versioned envelope",
+    migrate: wrap_in_envelope,
+}];
+
+/// Unversioned histories are a bare list of products
+fn wrap_in_envelope(history: Value) -> anyhow::Result<Value> {
+    let Value::Array(products) = history else {
+        bail!("Expected a list of products");
+    };
+    Ok(json!({ "version": 2, "products": products }))
+}
+
+fn version(history: &Value) -> anyhow::Result<u64> {
+    match history {
+        Value::Array(_) => Ok(1),
+        Value::Object(envelope) => envelope
+            .get("version")
+            .and_then(Value::as_u64)
+            .ok_or_else(|| anyhow!("History has no version")),
+        _ => bail!("History is neither a list of products nor a versioned envelope"),
+    }
+}
+
+/// Upgrades a history of any known version to the current one and reads its products
+pub(crate) fn migrate(mut history: Value) -> anyhow::Result<Vec<ProductHistory>> {
+    let mut version = version(&history)?;
+    if version > CANONICAL_VERSION {
+        bail!("History has version {version}, but only up to {CANONICAL_VERSION} is supported");
+    }
+    while version < CANONICAL_VERSION {
+        let migration = MIGRATIONS
+            .iter()
+            .find(|m| m.from == version)
+            .ok_or_else(|| anyhow!("No migration from history version {version}"))?;
+        info!(
+            "Migrating history from version {version}: {}",
+            migration.description
+        );
+        history = (migration.migrate)(history)
+            .with_context(|| format!("Failed to migrate history from version {version}"))?;
+        version += 1;
+    }
+    let history: CanonicalFile = serde_json::from_value(history)?;
+    Ok(history.products)
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum SchemaFormat {
+    /// The canonical history, `latest-canonical.json.gz`
+    Canonical,
+    /// The products of a store on the site, `latest-canonical.{store}.compressed.json`
+    Site,
+}
+
+fn schema(format: SchemaFormat) -> RootSchema {
+    match format {
+        SchemaFormat::Canonical => schema_for!(Canonical),
+        SchemaFormat::Site => schema_for!(Vec<ProductHistory>),
+    }
+}
+
+/// Prints the JSON Schema of `format`
+pub fn do_schema(format: SchemaFormat) -> anyhow::Result<()> {
+    let mut stdout = std::io::stdout().lock();
+    serde_json::to_writer_pretty(&mut stdout, &schema(format))?;
+    writeln!(stdout)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::File;
+
+    use flate2::{write::GzEncoder, Compression};
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::storage::{get_history_path, load_history};
+
+    #[test]
+    fn migrates_unversioned_history() {
+        // A history as written before it was versioned, with the fields products had back then
+        let history = r#"[
+            {
+                "id": 1,
+                "name": "Product",
+                "description": "PRODUCT",
+                "isWeighted": false,
+                "unit": "g",
+                "quantity": 500.0,
+                "store": "woolies",
+                "category": "00",
+                "priceHistory": [
+                    { "date": "2024-01-02", "price": 2.0 },
+                    { "date": "2024-01-01", "price": 2.5 }
+                ]
+            }
+        ]"#;
+        let tmpdir = tempdir().unwrap();
+        let file = File::create(get_history_path(tmpdir.path())).unwrap();
+        let mut file = GzEncoder::new(file, Compression::default());
+        file.write_all(history.as_bytes()).unwrap();
+        file.finish().unwrap();
+
+        let products = load_history(tmpdir.path()).unwrap();
+        let [ref product] = products[..] else {
+            panic!("expected exactly one product");
+        };
+        assert_eq!(product.id(), 1);
+        assert_eq!(product.price_history().len(), 2);
+        assert_eq!(product.price_history().first().price(), 2.0.into());
+        let migrated = serde_json::to_value(Canonical::new(&products)).unwrap();
+        assert_eq!(migrated["version"], CANONICAL_VERSION);
+        assert_eq!(migrated["products"][0]["category"], "00");
+    }
+
+    #[test]
+    fn rejects_newer_history() {
+        let history = json!({ "version": CANONICAL_VERSION + 1, "products": [] });
+        assert!(migrate(history).is_err());
+    }
+
+    #[test]
+    fn migrations_reach_current_version() {
+        for version in 1..CANONICAL_VERSION {
+            assert!(MIGRATIONS.iter().any(|m| m.from == version));
+        }
+    }
+
+    #[test]
+    fn canonical_schema() {
+        let schema = serde_json::to_value(schema(SchemaFormat::Canonical)).unwrap();
+        assert_eq!(schema["title"], "Canonical");
+        assert_eq!(
+            schema["required"],
+            json!(["products", "version"]),
+            "{schema:#}"
+        );
+        let product = &schema["definitions"]["ProductHistory"];
+        assert_eq!(product["properties"]["priceHistory"]["minItems"], 1);
+        assert_eq!(
+            schema["definitions"]["PriceSnapshot"]["properties"]["price"]["type"],
+            "number"
+        );
+    }
+}
@@ -0,0 +1,615 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+use std::path::Path;
+
+use clap::ValueEnum;
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    product::{normalise_barcode, price_serde, Price, PriceSnapshot, ProductHistory},
+    storage::{
+        history_modified, load_history, load_search_entries, load_search_index, save_search_index,
+    },
+    stores::Store,
+    unit::Unit,
+};
+
+// Number of price changes kept per product in the index, more than anyone wants on a terminal
+const MAX_INDEXED_CHANGES: usize = 20;
+/// Number of entries stored per file, so a search only loads the files holding its results
+pub(crate) const SHARD_SIZE: usize = 1000;
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum OutputFormat {
+    Table,
+    Json,
+}
+
+/// Order of search results and product lists
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum SortOrder {
+    /// By name, then store and id
+    #[default]
+    Name,
+    /// Cheapest unit price first, products without one last
+    UnitPrice,
+}
+
+/// Compares unit prices so that the cheapest comes first and missing ones last
+pub(crate) fn cmp_unit_price(a: Option<Price>, b: Option<Price>) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => a.cmp(&b),
+        (a, b) => a.is_none().cmp(&b.is_none()),
+    }
+}
+
+/// Filters for [`do_search`]. All given filters have to match.
+#[derive(Debug, Default)]
+pub struct SearchQuery {
+    /// Every word has to appear in the name or brand, either as a whole word or part of one
+    pub text: Option<String>,
+    pub store: Option<Store>,
+    /// Category code or a prefix of it, e.g. "3" for all meat & seafood
+    pub category: Option<String>,
+    pub id: Option<i64>,
+    /// GTIN of any length, which is compared zero-padded to 14 digits like the stored ones
+    pub barcode: Option<String>,
+    pub min_price: Option<f64>,
+    pub max_price: Option<f64>,
+    pub min_unit_price: Option<f64>,
+    pub max_unit_price: Option<f64>,
+    pub sort: SortOrder,
+}
+
+fn in_range(price: Option<Price>, min: Option<f64>, max: Option<f64>) -> bool {
+    if min.is_none() && max.is_none() {
+        return true;
+    }
+    // Products without a price can't be in any range
+    let Some(price) = price else {
+        return false;
+    };
+    min.is_none_or(|min| price >= Price::from(min))
+        && max.is_none_or(|max| price <= Price::from(max))
+}
+
+/// Summary of a product with its latest price changes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct IndexEntry {
+    store: Store,
+    id: i64,
+    name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    brand: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    barcode: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    category: Option<String>,
+    unit: Unit,
+    quantity: f64,
+    #[serde(with = "price_serde")]
+    price: Price,
+    #[serde(
+        rename = "unitPrice",
+        default,
+        with = "price_serde::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    unit_price: Option<Price>,
+    /// Most recent first, including the current price
+    changes: Vec<PriceSnapshot>,
+}
+
+impl IndexEntry {
+    /// Copy of this entry with only the current price and `changes` previous ones
+    pub(crate) fn with_changes(&self, changes: usize) -> Self {
+        let mut entry = self.clone();
+        entry.changes.truncate(changes + 1);
+        entry
+    }
+}
+
+impl From<&ProductHistory> for IndexEntry {
+    fn from(product: &ProductHistory) -> Self {
+        let info = product.product_info();
+        let history = product.price_history();
+        Self {
+            store: info.store(),
+            id: info.id(),
+            name: info.name().to_string(),
+            brand: info.brand().map(String::from),
+            barcode: info.barcode().map(String::from),
+            category: info.category().map(|c| c.code().to_string()),
+            unit: info.unit(),
+            quantity: info.quantity(),
+            price: history.first().price(),
+            unit_price: info.unit_price(),
+            changes: history.iter().take(MAX_INDEXED_CHANGES).cloned().collect(),
+        }
+    }
+}
+
+/// Prices of an entry, kept in the lookup so price filters don't need the entries
+#[derive(Debug, Serialize, Deserialize)]
+struct IndexPrices {
+    #[serde(with = "price_serde")]
+    price: Price,
+    #[serde(
+        rename = "unitPrice",
+        default,
+        with = "price_serde::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    unit_price: Option<Price>,
+}
+
+/// Lookup structures over the canonical history so searches don't need to load and scan the
+/// full history file.
+///
+/// Positions refer to the entries returned by [`SearchIndex::build`], which are sorted by name
+/// so results come out in order. The entries themselves are stored in shards of
+/// [`SHARD_SIZE`] next to the index and only the shards holding results get loaded.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct SearchIndex {
+    /// Modification time of the history file this index was built from, in milliseconds since epoch
+    #[serde(rename = "historyModified")]
+    history_modified: u64,
+    /// Lowercase word to positions of entries containing it
+    tokens: BTreeMap<String, Vec<usize>>,
+    stores: BTreeMap<Store, Vec<usize>>,
+    /// Category code to positions, sorted so prefixes are a range
+    categories: BTreeMap<String, Vec<usize>>,
+    ids: BTreeMap<i64, Vec<usize>>,
+    barcodes: BTreeMap<String, Vec<usize>>,
+    /// Indexed by position
+    prices: Vec<IndexPrices>,
+}
+
+impl SearchIndex {
+    /// Builds the index along with the entries its positions refer to
+    pub(crate) fn build(
+        products: &[ProductHistory],
+        history_modified: u64,
+    ) -> (Self, Vec<IndexEntry>) {
+        let mut entries: Vec<IndexEntry> = products.iter().map(IndexEntry::from).collect();
+        entries.sort_by(|a, b| {
+            a.name
+                .cmp(&b.name)
+                .then(a.store.cmp(&b.store))
+                .then(a.id.cmp(&b.id))
+        });
+        let mut index = Self {
+            history_modified,
+            tokens: BTreeMap::new(),
+            stores: BTreeMap::new(),
+            categories: BTreeMap::new(),
+            ids: BTreeMap::new(),
+            barcodes: BTreeMap::new(),
+            prices: Vec::with_capacity(entries.len()),
+        };
+        for (pos, entry) in entries.iter().enumerate() {
+            let words: BTreeSet<String> = tokenize(&entry.name)
+                .chain(entry.brand.iter().flat_map(|b| tokenize(b)))
+                .collect();
+            for word in words {
+                index.tokens.entry(word).or_default().push(pos);
+            }
+            index.stores.entry(entry.store).or_default().push(pos);
+            if let Some(ref category) = entry.category {
+                index
+                    .categories
+                    .entry(category.clone())
+                    .or_default()
+                    .push(pos);
+            }
+            index.ids.entry(entry.id).or_default().push(pos);
+            if let Some(ref barcode) = entry.barcode {
+                index.barcodes.entry(barcode.clone()).or_default().push(pos);
+            }
+            index.prices.push(IndexPrices {
+                price: entry.price,
+                unit_price: entry.unit_price,
+            });
+        }
+        (index, entries)
+    }
+
+    pub(crate) fn history_modified(&self) -> u64 {
+        self.history_modified
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.prices.len()
+    }
+
+    /// Positions of entries that contain every word of `text`
+    fn text_matches(&self, text: &str) -> Option<BTreeSet<usize>> {
+        let mut result: Option<BTreeSet<usize>> = None;
+        for word in tokenize(text) {
+            // The vocabulary is much smaller than the catalogue so scanning it for partial words
+            // is cheap
+            let positions = self
+                .tokens
+                .iter()
+                .filter(|(token, _)| token.contains(&word))
+                .flat_map(|(_, positions)| positions.iter().copied());
+            narrow(&mut result, positions);
+        }
+        result
+    }
+
+    /// Positions of the entries matching `query`, in the order it asks for
+    pub(crate) fn search(&self, query: &SearchQuery) -> Vec<usize> {
+        let mut candidates = match query.text {
+            Some(ref text) => self.text_matches(text),
+            None => None,
+        };
+        if let Some(store) = query.store {
+            let positions = self.stores.get(&store).into_iter().flatten().copied();
+            narrow(&mut candidates, positions);
+        }
+        if let Some(ref prefix) = query.category {
+            let positions = self
+                .categories
+                .range(prefix.clone()..)
+                .take_while(|(code, _)| code.starts_with(prefix.as_str()))
+                .flat_map(|(_, positions)| positions.iter().copied());
+            narrow(&mut candidates, positions);
+        }
+        if let Some(id) = query.id {
+            let positions = self.ids.get(&id).into_iter().flatten().copied();
+            narrow(&mut candidates, positions);
+        }
+        if let Some(ref barcode) = query.barcode {
+            // An invalid barcode can't match any product
+            let positions = normalise_barcode(barcode)
+                .and_then(|barcode| self.barcodes.get(&barcode))
+                .into_iter()
+                .flatten()
+                .copied();
+            narrow(&mut candidates, positions);
+        }
+        let candidates: Box<dyn Iterator<Item = usize>> = match candidates {
+            Some(candidates) => Box::new(candidates.into_iter()),
+            None => Box::new(0..self.len()),
+        };
+        let mut positions: Vec<usize> = candidates
+            .filter(|&pos| {
+                let prices = &self.prices[pos];
+                in_range(Some(prices.price), query.min_price, query.max_price)
+                    && in_range(
+                        prices.unit_price,
+                        query.min_unit_price,
+                        query.max_unit_price,
+                    )
+            })
+            .collect();
+        // Positions are in name order already, which the stable sort keeps for equal unit prices
+        if query.sort == SortOrder::UnitPrice {
+            positions.sort_by(|&a, &b| {
+                cmp_unit_price(self.prices[a].unit_price, self.prices[b].unit_price)
+            });
+        }
+        positions
+    }
+}
+
+/// Restricts `candidates` to `positions`, or starts them off with `positions` if no filter
+/// applied yet
+fn narrow(candidates: &mut Option<BTreeSet<usize>>, positions: impl Iterator<Item = usize>) {
+    *candidates = Some(match candidates.take() {
+        Some(candidates) => positions.filter(|pos| candidates.contains(pos)).collect(),
+        None => positions.collect(),
+    });
+}
+
+pub(crate) fn tokenize(s: &str) -> impl Iterator<Item = String> + '_ {
+    s.split(|c: char| !c.is_alphanumeric())
+        .filter(|t| !t.is_empty())
+        .map(|t| t.to_lowercase())
+}
+
+/// Loads the search index, rebuilding it from the canonical history if it is missing or older
+/// than the history
+fn load_or_build_index(output_dir: &Path) -> anyhow::Result<SearchIndex> {
+    let modified = history_modified(output_dir)?;
+    if let Some(index) = load_search_index(output_dir)? {
+        if index.history_modified() == modified {
+            return Ok(index);
+        }
+    }
+    info!("Search index is missing or outdated, rebuilding it");
+    let products = load_history(output_dir)?;
+    let (index, entries) = SearchIndex::build(&products, modified);
+    save_search_index(&index, &entries, output_dir)?;
+    Ok(index)
+}
+
+fn write_table(results: &[IndexEntry], mut out: impl Write) -> std::io::Result<()> {
+    writeln!(
+        out,
+        "{:<8} {:>10} {:>8} {:>10}  NAME",
+        "STORE", "ID", "PRICE", "UNIT PRICE"
+    )?;
+    for entry in results {
+        let unit_price = entry
+            .unit_price
+            .map(|p| p.to_string())
+            .unwrap_or_else(|| String::from("-"));
+        writeln!(
+            out,
+            "{:<8} {:>10} {:>8} {:>10}  {}",
+            entry.store.to_string(),
+            entry.id,
+            entry.price.to_string(),
+            unit_price,
+            entry.name
+        )?;
+        // The first entry is the current price which is already shown above
+        for change in entry.changes.iter().skip(1) {
+            writeln!(
+                out,
+                "{:<8} {:>10} {:>8}",
+                "",
+                change.date().to_string(),
+                change.price().to_string()
+            )?;
+        }
+    }
+    Ok(())
+}
+
+pub fn do_search(
+    query: &SearchQuery,
+    limit: usize,
+    changes: usize,
+    format: OutputFormat,
+    output_dir: &Path,
+) -> anyhow::Result<()> {
+    let index = load_or_build_index(output_dir)?;
+    let mut positions = index.search(query);
+    if positions.len() > limit {
+        info!("Showing {limit} of {} results", positions.len());
+        positions.truncate(limit);
+    }
+    let results: Vec<IndexEntry> = load_search_entries(output_dir, &positions)?
+        .into_iter()
+        .map(|entry| entry.with_changes(changes))
+        .collect();
+
+    let mut stdout = std::io::stdout().lock();
+    match format {
+        OutputFormat::Table => write_table(&results, stdout)?,
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut stdout, &results)?;
+            writeln!(stdout)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::product::{ProductInfo, ProductSnapshot};
+    use crate::storage::save_result;
+    use time::Date;
+
+    fn product(store: Store, id: i64, name: &str) -> ProductHistory {
+        ProductHistory::with_info(ProductInfo::new(
+            id,
+            String::from(name),
+            String::new(),
+            None,
+            None,
+            None,
+            Unit::Grams,
+            500.0,
+            store,
+            None,
+        ))
+    }
+
+    fn products() -> Vec<ProductHistory> {
+        vec![
+            product(Store::Coles, 1, "Pauls Full Cream Milk"),
+            product(Store::Woolies, 2, "Pauls Light Milk"),
+            product(Store::Woolies, 3, "Cheddar Cheese"),
+        ]
+    }
+
+    fn search(query: &SearchQuery) -> Vec<IndexEntry> {
+        let (index, entries) = SearchIndex::build(&products(), 0);
+        index
+            .search(query)
+            .into_iter()
+            .map(|pos| entries[pos].clone())
+            .collect()
+    }
+
+    fn ids(results: Vec<IndexEntry>) -> Vec<i64> {
+        results.iter().map(|e| e.id).collect()
+    }
+
+    #[test]
+    fn search_text() {
+        let query = SearchQuery {
+            text: Some(String::from("MILK pau")),
+            ..Default::default()
+        };
+        assert_eq!(ids(search(&query)), vec![1, 2]);
+
+        let query = SearchQuery {
+            text: Some(String::from("milk cheese")),
+            ..Default::default()
+        };
+        assert!(search(&query).is_empty());
+    }
+
+    #[test]
+    fn search_filters() {
+        let query = SearchQuery {
+            text: Some(String::from("milk")),
+            store: Some(Store::Woolies),
+            ..Default::default()
+        };
+        assert_eq!(ids(search(&query)), vec![2]);
+
+        let query = SearchQuery {
+            id: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(ids(search(&query)), vec![3]);
+
+        // Test products all cost 1.00
+        let query = SearchQuery {
+            min_price: Some(1.5),
+            ..Default::default()
+        };
+        assert!(search(&query).is_empty());
+
+        let query = SearchQuery {
+            category: Some(String::from("0")),
+            ..Default::default()
+        };
+        assert!(search(&query).is_empty());
+    }
+
+    /// Product with a unit price, which is derived when a snapshot is taken
+    fn priced(id: i64, name: &str, quantity: f64, price: f64) -> ProductHistory {
+        let info = ProductInfo::new(
+            id,
+            String::from(name),
+            String::new(),
+            None,
+            None,
+            None,
+            Unit::Grams,
+            quantity,
+            Store::Coles,
+            None,
+        );
+        let date = Date::from_calendar_date(2024, time::Month::January, 1).unwrap();
+        ProductHistory::from(ProductSnapshot::new(info, price.into(), None, date))
+    }
+
+    #[test]
+    fn sort_by_unit_price() {
+        let products = vec![
+            priced(1, "A Milk", 500.0, 2.0),
+            priced(2, "B Milk", 1000.0, 2.0),
+            product(Store::Coles, 3, "C Milk"),
+        ];
+        let (index, entries) = SearchIndex::build(&products, 0);
+        let mut query = SearchQuery {
+            text: Some(String::from("milk")),
+            ..Default::default()
+        };
+        let ids = |query: &SearchQuery| -> Vec<i64> {
+            index
+                .search(query)
+                .iter()
+                .map(|&pos| entries[pos].id)
+                .collect()
+        };
+        assert_eq!(ids(&query), vec![1, 2, 3]);
+        // Products without a unit price come last
+        query.sort = SortOrder::UnitPrice;
+        assert_eq!(ids(&query), vec![2, 1, 3]);
+    }
+
+    #[test]
+    fn search_barcode() {
+        let mut products = products();
+        products.push(ProductHistory::with_info(ProductInfo::new(
+            4,
+            String::from("Tasty Cheese"),
+            String::new(),
+            None,
+            Some(String::from("9300633603601")),
+            None,
+            Unit::Grams,
+            500.0,
+            Store::Coles,
+            None,
+        )));
+        let (index, entries) = SearchIndex::build(&products, 0);
+        let ids = |barcode: &str| -> Vec<i64> {
+            let query = SearchQuery {
+                barcode: Some(String::from(barcode)),
+                ..Default::default()
+            };
+            index
+                .search(&query)
+                .iter()
+                .map(|&pos| entries[pos].id)
+                .collect()
+        };
+        assert_eq!(ids("9300633603601"), vec![4]);
+        // Stored barcodes are zero-padded to 14 digits
+        assert_eq!(ids("09300633603601"), vec![4]);
+        assert!(ids("9300633603602").is_empty());
+    }
+
+    #[test]
+    fn table() {
+        let query = SearchQuery {
+            id: Some(3),
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        write_table(&search(&query), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let [header, row] = out.lines().collect::<Vec<_>>()[..] else {
+            panic!("unexpected output {out}");
+        };
+        assert!(header.starts_with("STORE"));
+        assert!(row.starts_with("woolies"));
+        assert!(row.ends_with("Cheddar Cheese"));
+    }
+
+    #[test]
+    fn rebuilds_outdated_index() {
+        let tmpdir = tempdir().unwrap();
+        save_result(&[product(Store::Coles, 1, "Milk")], tmpdir.path(), 0).unwrap();
+        let (index, entries) = SearchIndex::build(&[], 0);
+        save_search_index(&index, &entries, tmpdir.path()).unwrap();
+
+        let index = load_or_build_index(tmpdir.path()).unwrap();
+        assert_eq!(index.len(), 1);
+        assert_eq!(
+            index.history_modified(),
+            history_modified(tmpdir.path()).unwrap()
+        );
+    }
+
+    #[test]
+    fn loads_entries_from_shards() {
+        let tmpdir = tempdir().unwrap();
+        let products: Vec<ProductHistory> = (0..SHARD_SIZE as i64 + 1)
+            .map(|id| product(Store::Coles, id, &format!("Product {id:05}")))
+            .collect();
+        let (index, entries) = SearchIndex::build(&products, 0);
+        save_search_index(&index, &entries, tmpdir.path()).unwrap();
+        let shard = tmpdir.path().join("search-index").join("1.json.gz");
+        assert!(shard.exists());
+
+        let query = SearchQuery {
+            text: Some(format!("{SHARD_SIZE:05}")),
+            ..Default::default()
+        };
+        let positions = index.search(&query);
+        assert_eq!(positions, vec![SHARD_SIZE]);
+        let results = load_search_entries(tmpdir.path(), &positions).unwrap();
+        assert_eq!(ids(results), vec![SHARD_SIZE as i64]);
+
+        // A smaller catalogue removes the shards it no longer needs
+        let (index, entries) = SearchIndex::build(&products[..1], 0);
+        save_search_index(&index, &entries, tmpdir.path()).unwrap();
+        assert!(!shard.exists());
+    }
+}
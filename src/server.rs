@@ -0,0 +1,611 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use clap::ValueEnum;
+use flate2::{write::GzEncoder, Compression};
+use log::{debug, error, info};
+use serde::Serialize;
+use time::Date;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::{
+    date::date_serde,
+    product::{price_serde, Price, ProductHistory, ProductInfo},
+    search::{cmp_unit_price, IndexEntry, SearchIndex, SearchQuery, SortOrder},
+    storage::{history_modified, load_history},
+    stores::Store,
+};
+
+const DEFAULT_PER_PAGE: usize = 50;
+const MAX_PER_PAGE: usize = 500;
+// Previous prices included with search results unless asked for otherwise
+const DEFAULT_SEARCH_CHANGES: usize = 5;
+
+#[derive(Debug, PartialEq)]
+struct ApiError {
+    status: u16,
+    message: String,
+}
+
+impl ApiError {
+    fn bad_request(message: impl Into<String>) -> Self {
+        Self {
+            status: 400,
+            message: message.into(),
+        }
+    }
+
+    fn not_found(message: impl Into<String>) -> Self {
+        Self {
+            status: 404,
+            message: message.into(),
+        }
+    }
+}
+
+type ApiResult = std::result::Result<String, ApiError>;
+
+fn to_json(value: &impl Serialize) -> ApiResult {
+    serde_json::to_string(value).map_err(|e| ApiError {
+        status: 500,
+        message: e.to_string(),
+    })
+}
+
+/// Product with its current price but without the full history
+#[derive(Serialize)]
+struct ProductSummary<'a> {
+    #[serde(flatten)]
+    info: &'a ProductInfo,
+    #[serde(with = "price_serde")]
+    price: Price,
+    #[serde(rename = "matchGroup", skip_serializing_if = "Option::is_none")]
+    match_group: Option<&'a str>,
+}
+
+impl<'a> From<&'a ProductHistory> for ProductSummary<'a> {
+    fn from(product: &'a ProductHistory) -> Self {
+        Self {
+            info: product.product_info(),
+            price: product.price_history().first().price(),
+            match_group: product.match_group(),
+        }
+    }
+}
+
+/// Product whose latest price is lower than the one before
+#[derive(Serialize)]
+struct Deal<'a> {
+    #[serde(flatten)]
+    product: ProductSummary<'a>,
+    #[serde(rename = "previousPrice", with = "price_serde")]
+    previous_price: Price,
+    #[serde(with = "date_serde")]
+    since: Date,
+    /// Relative price change, e.g. -0.25 for 25% off
+    change: f64,
+}
+
+#[derive(Serialize)]
+struct Page<T> {
+    page: usize,
+    #[serde(rename = "perPage")]
+    per_page: usize,
+    total: usize,
+    items: Vec<T>,
+}
+
+#[derive(Serialize)]
+struct CategoryCount {
+    code: &'static str,
+    products: usize,
+}
+
+type Params = HashMap<String, String>;
+
+fn param<T: FromStr>(params: &Params, name: &str) -> std::result::Result<Option<T>, ApiError> {
+    match params.get(name) {
+        Some(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|_| ApiError::bad_request(format!("Invalid value {value:?} for {name}"))),
+        None => Ok(None),
+    }
+}
+
+fn store_param(value: &str) -> std::result::Result<Store, ApiError> {
+    Store::from_str(value, true).map_err(|_| ApiError::not_found(format!("Unknown store {value}")))
+}
+
+/// The `sort` parameter, either `name` or `unitPrice`
+fn sort_param(params: &Params) -> std::result::Result<Option<SortOrder>, ApiError> {
+    match params.get("sort").map(String::as_str) {
+        None => Ok(None),
+        Some("name") => Ok(Some(SortOrder::Name)),
+        Some("unitPrice") => Ok(Some(SortOrder::UnitPrice)),
+        Some(value) => Err(ApiError::bad_request(format!(
+            "Invalid value {value:?} for sort, expected name or unitPrice"
+        ))),
+    }
+}
+
+fn paginate<T>(items: Vec<T>, params: &Params) -> std::result::Result<Page<T>, ApiError> {
+    let page: usize = param(params, "page")?.unwrap_or(1);
+    let per_page = param(params, "per_page")?.unwrap_or(DEFAULT_PER_PAGE);
+    if page == 0 {
+        return Err(ApiError::bad_request("Pages start at 1"));
+    }
+    if per_page == 0 || per_page > MAX_PER_PAGE {
+        return Err(ApiError::bad_request(format!(
+            "per_page must be between 1 and {MAX_PER_PAGE}"
+        )));
+    }
+    // The page comes straight from the query string, so it can be anything
+    let skip = page
+        .checked_sub(1)
+        .and_then(|p| p.checked_mul(per_page))
+        .ok_or_else(|| ApiError::bad_request(format!("Invalid page {page}")))?;
+    let total = items.len();
+    let items = items.into_iter().skip(skip).take(per_page).collect();
+    Ok(Page {
+        page,
+        per_page,
+        total,
+        items,
+    })
+}
+
+/// Canonical history as served by the API, reloaded whenever the history file changes
+struct ApiData {
+    modified: u64,
+    products: Vec<ProductHistory>,
+    lookup: HashMap<(Store, i64), usize>,
+    index: SearchIndex,
+    /// Entries the positions in `index` refer to
+    entries: Vec<IndexEntry>,
+}
+
+impl ApiData {
+    fn new(mut products: Vec<ProductHistory>, modified: u64) -> Self {
+        products.sort_by_key(|p| (p.store(), p.id()));
+        let lookup = products
+            .iter()
+            .enumerate()
+            .map(|(pos, p)| ((p.store(), p.id()), pos))
+            .collect();
+        let (index, entries) = SearchIndex::build(&products, modified);
+        Self {
+            modified,
+            products,
+            lookup,
+            index,
+            entries,
+        }
+    }
+
+    fn load(output_dir: &Path) -> anyhow::Result<Self> {
+        let modified = history_modified(output_dir)?;
+        let products = load_history(output_dir)?;
+        info!("Serving {} products", products.len());
+        Ok(Self::new(products, modified))
+    }
+
+    fn product(&self, store: &str, id: &str) -> std::result::Result<&ProductHistory, ApiError> {
+        let store = store_param(store)?;
+        let id: i64 = id
+            .parse()
+            .map_err(|_| ApiError::not_found(format!("Invalid product id {id}")))?;
+        self.lookup
+            .get(&(store, id))
+            .map(|pos| &self.products[*pos])
+            .ok_or_else(|| ApiError::not_found(format!("No product {id} for {store}")))
+    }
+
+    /// Products filtered by the `store` and `category` parameters
+    fn filtered(
+        &self,
+        params: &Params,
+    ) -> std::result::Result<impl Iterator<Item = &ProductHistory>, ApiError> {
+        let store = match params.get("store") {
+            Some(store) => Some(store_param(store)?),
+            None => None,
+        };
+        let category = params.get("category").cloned();
+        Ok(self.products.iter().filter(move |p| {
+            store.is_none_or(|store| p.store() == store)
+                && category.as_ref().is_none_or(|category| {
+                    p.product_info()
+                        .category()
+                        .is_some_and(|c| c.code().starts_with(category.as_str()))
+                })
+        }))
+    }
+
+    /// Products by store and id unless `sort` asks for something else
+    fn products(&self, params: &Params) -> ApiResult {
+        let sort = sort_param(params)?;
+        let mut products: Vec<&ProductHistory> = self.filtered(params)?.collect();
+        match sort {
+            None => {}
+            Some(SortOrder::Name) => products.sort_by(|a, b| {
+                a.product_info()
+                    .name()
+                    .cmp(b.product_info().name())
+                    .then(a.store().cmp(&b.store()))
+                    .then(a.id().cmp(&b.id()))
+            }),
+            Some(SortOrder::UnitPrice) => products.sort_by(|a, b| {
+                cmp_unit_price(a.product_info().unit_price(), b.product_info().unit_price())
+            }),
+        }
+        let products: Vec<ProductSummary> = products.into_iter().map(Into::into).collect();
+        to_json(&paginate(products, params)?)
+    }
+
+    fn categories(&self) -> ApiResult {
+        let mut counts: BTreeMap<&'static str, usize> = BTreeMap::new();
+        for product in self.products.iter() {
+            if let Some(category) = product.product_info().category() {
+                *counts.entry(category.code()).or_default() += 1;
+            }
+        }
+        let categories: Vec<CategoryCount> = counts
+            .into_iter()
+            .map(|(code, products)| CategoryCount { code, products })
+            .collect();
+        to_json(&categories)
+    }
+
+    fn search(&self, params: &Params) -> ApiResult {
+        let query = SearchQuery {
+            text: params.get("q").cloned(),
+            store: match params.get("store") {
+                Some(store) => Some(store_param(store)?),
+                None => None,
+            },
+            category: params.get("category").cloned(),
+            id: param(params, "id")?,
+            barcode: params.get("barcode").cloned(),
+            min_price: param(params, "min_price")?,
+            max_price: param(params, "max_price")?,
+            min_unit_price: param(params, "min_unit_price")?,
+            max_unit_price: param(params, "max_unit_price")?,
+            sort: sort_param(params)?.unwrap_or_default(),
+        };
+        let changes = param(params, "changes")?.unwrap_or(DEFAULT_SEARCH_CHANGES);
+        let results: Vec<IndexEntry> = self
+            .index
+            .search(&query)
+            .into_iter()
+            .map(|pos| self.entries[pos].with_changes(changes))
+            .collect();
+        to_json(&paginate(results, params)?)
+    }
+
+    fn deals(&self, params: &Params) -> ApiResult {
+        let mut deals: Vec<Deal> = self
+            .filtered(params)?
+            .filter_map(|product| {
+                let history = product.price_history();
+                let latest = history.first();
+                let previous = history.get(1)?;
+                (latest.price() < previous.price()).then(|| Deal {
+                    product: product.into(),
+                    previous_price: previous.price(),
+                    since: latest.date(),
+                    change: latest.price().change_from(previous.price()),
+                })
+            })
+            .collect();
+        deals.sort_by(|a, b| a.change.total_cmp(&b.change));
+        to_json(&paginate(deals, params)?)
+    }
+
+    fn route(&self, path: &str, params: &Params) -> ApiResult {
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+        match segments[..] {
+            ["products"] => self.products(params),
+            ["products", store, id] => to_json(&ProductSummary::from(self.product(store, id)?)),
+            ["products", store, id, "history"] => to_json(self.product(store, id)?.price_history()),
+            ["categories"] => self.categories(),
+            ["search"] => self.search(params),
+            ["deals"] => self.deals(params),
+            _ => Err(ApiError::not_found(format!("No route for {path}"))),
+        }
+    }
+
+    /// Answers a request for `url` (path and query string) with a status code and JSON body
+    fn handle(&self, url: &str) -> (u16, String) {
+        let (path, query) = url.split_once('?').unwrap_or((url, ""));
+        let params: Params = url::form_urlencoded::parse(query.as_bytes())
+            .into_owned()
+            .collect();
+        match self.route(path, &params) {
+            Ok(body) => (200, body),
+            Err(e) => {
+                debug!("Request for {url} failed: {}", e.message);
+                let body = serde_json::json!({ "error": e.message }).to_string();
+                (e.status, body)
+            }
+        }
+    }
+}
+
+struct HttpResponse {
+    status: u16,
+    headers: Vec<(&'static str, String)>,
+    body: Vec<u8>,
+}
+
+/// Adds an ETag to the response and compresses it if the client supports it. Returns an empty
+/// 304 response if the client already has the current version.
+fn finish_response(
+    status: u16,
+    body: String,
+    if_none_match: Option<&str>,
+    accepts_gzip: bool,
+) -> anyhow::Result<HttpResponse> {
+    let mut hasher = DefaultHasher::new();
+    body.hash(&mut hasher);
+    let etag = format!("\"{:016x}\"", hasher.finish());
+    let mut headers = vec![
+        ("Content-Type", String::from("application/json")),
+        ("Vary", String::from("Accept-Encoding")),
+        ("ETag", etag.clone()),
+    ];
+
+    if status == 200 && if_none_match.is_some_and(|tags| tags.split(',').any(|t| t.trim() == etag))
+    {
+        return Ok(HttpResponse {
+            status: 304,
+            headers,
+            body: Vec::new(),
+        });
+    }
+
+    let body = if accepts_gzip {
+        headers.push(("Content-Encoding", String::from("gzip")));
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(body.as_bytes())?;
+        encoder.finish()?
+    } else {
+        body.into_bytes()
+    };
+    Ok(HttpResponse {
+        status,
+        headers,
+        body,
+    })
+}
+
+fn header<'a>(request: &'a Request, name: &'static str) -> Option<&'a str> {
+    request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv(name))
+        .map(|h| h.value.as_str())
+}
+
+fn respond(data: &ApiData, request: Request) -> anyhow::Result<()> {
+    let (status, body) = if *request.method() == Method::Get {
+        data.handle(request.url())
+    } else {
+        let body = serde_json::json!({ "error": "Only GET requests are supported" });
+        (405, body.to_string())
+    };
+    let accepts_gzip = header(&request, "Accept-Encoding").is_some_and(|v| v.contains("gzip"));
+    let response = finish_response(
+        status,
+        body,
+        header(&request, "If-None-Match"),
+        accepts_gzip,
+    )?;
+
+    let mut http_response = Response::from_data(response.body).with_status_code(response.status);
+    for (name, value) in response.headers {
+        let header = Header::from_bytes(name.as_bytes(), value.as_bytes())
+            .map_err(|_| anyhow!("Invalid header {name}: {value}"))?;
+        http_response.add_header(header);
+    }
+    request.respond(http_response)?;
+    Ok(())
+}
+
+/// Serves the canonical history over HTTP until the process is stopped
+pub fn do_serve(output_dir: &Path, address: &str) -> anyhow::Result<()> {
+    let mut data = ApiData::load(output_dir)?;
+    let server =
+        Server::http(address).map_err(|e| anyhow!("Failed to listen on {address}: {e}"))?;
+    info!("Listening on http://{address}");
+
+    for request in server.incoming_requests() {
+        // Pick up new analysis results without restarting, keep serving the old data if the
+        // new history can't be loaded
+        match history_modified(output_dir) {
+            Ok(modified) if modified != data.modified => match ApiData::load(output_dir) {
+                Ok(new_data) => data = new_data,
+                Err(e) => error!("Failed to reload history: {e:?}"),
+            },
+            Ok(_) => {}
+            Err(e) => error!("Failed to check history for changes: {e:?}"),
+        }
+
+        if let Err(e) = respond(&data, request) {
+            error!("Failed to respond to request: {e:?}");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+
+    use flate2::read::GzDecoder;
+    use serde_json::Value;
+    use time::Month;
+
+    use super::*;
+    use crate::product::ProductSnapshot;
+    use crate::unit::Unit;
+
+    fn product(store: Store, id: i64, name: &str, prices: &[f64]) -> ProductHistory {
+        let info = ProductInfo::new(
+            id,
+            String::from(name),
+            String::new(),
+            None,
+            None,
+            None,
+            Unit::Grams,
+            500.0,
+            store,
+            None,
+        );
+        let prices: Vec<(Date, f64)> = prices
+            .iter()
+            .enumerate()
+            .map(|(i, price)| {
+                let day = 20 - i as u8;
+                let date = Date::from_calendar_date(2024, Month::January, day).unwrap();
+                (date, *price)
+            })
+            .collect();
+        ProductHistory::with_prices(info, &prices)
+    }
+
+    fn data() -> ApiData {
+        ApiData::new(
+            vec![
+                product(Store::Woolies, 2, "Light Milk", &[2.0]),
+                product(Store::Coles, 1, "Full Cream Milk", &[3.0, 4.0]),
+                product(Store::Woolies, 3, "Cheese", &[4.5, 5.0]),
+            ],
+            0,
+        )
+    }
+
+    fn get(url: &str) -> (u16, Value) {
+        let (status, body) = data().handle(url);
+        (status, serde_json::from_str(&body).unwrap())
+    }
+
+    #[test]
+    fn products_pagination() {
+        let (status, body) = get("/products?page=2&per_page=2");
+        assert_eq!(status, 200);
+        assert_eq!(body["total"], 3);
+        assert_eq!(body["page"], 2);
+        assert_eq!(body["items"].as_array().unwrap().len(), 1);
+        assert_eq!(body["items"][0]["id"], 3);
+
+        let (status, _) = get("/products?page=0");
+        assert_eq!(status, 400);
+        let (status, body) = get(&format!("/products?page={}&per_page=2", usize::MAX));
+        assert_eq!(status, 400, "{body}");
+    }
+
+    #[test]
+    fn products_sort() {
+        let date = Date::from_calendar_date(2024, Month::January, 1).unwrap();
+        let snapshot = |id: i64, name: &str, quantity: f64| {
+            let info = ProductInfo::new(
+                id,
+                String::from(name),
+                String::new(),
+                None,
+                None,
+                None,
+                Unit::Grams,
+                quantity,
+                Store::Coles,
+                None,
+            );
+            ProductHistory::from(ProductSnapshot::new(info, 2.0.into(), None, date))
+        };
+        let data = ApiData::new(
+            vec![
+                snapshot(1, "Apple Milk", 500.0),
+                snapshot(2, "Banana Milk", 1000.0),
+            ],
+            0,
+        );
+        let ids = |url: &str| -> Vec<i64> {
+            let (status, body) = data.handle(url);
+            assert_eq!(status, 200, "{body}");
+            let body: Value = serde_json::from_str(&body).unwrap();
+            body["items"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|item| item["id"].as_i64().unwrap())
+                .collect()
+        };
+        assert_eq!(ids("/products"), vec![1, 2]);
+        assert_eq!(ids("/products?sort=unitPrice"), vec![2, 1]);
+        assert_eq!(ids("/search?q=milk"), vec![1, 2]);
+        assert_eq!(ids("/search?q=milk&sort=unitPrice"), vec![2, 1]);
+        assert_eq!(ids("/products?sort=name"), vec![1, 2]);
+        assert_eq!(data.handle("/products?sort=price").0, 400);
+    }
+
+    #[test]
+    fn product_and_history() {
+        let (status, body) = get("/products/coles/1");
+        assert_eq!(status, 200);
+        assert_eq!(body["name"], "Full Cream Milk");
+        assert_eq!(body["price"], 3.0);
+
+        let (status, body) = get("/products/coles/1/history");
+        assert_eq!(status, 200);
+        assert_eq!(body.as_array().unwrap().len(), 2);
+
+        assert_eq!(get("/products/coles/2").0, 404);
+        assert_eq!(get("/products/aldi/1").0, 404);
+        assert_eq!(get("/unknown").0, 404);
+    }
+
+    #[test]
+    fn search() {
+        let (status, body) = get("/search?q=MILK&store=woolies");
+        assert_eq!(status, 200);
+        assert_eq!(body["total"], 1);
+        assert_eq!(body["items"][0]["id"], 2);
+    }
+
+    #[test]
+    fn deals() {
+        let (status, body) = get("/deals");
+        assert_eq!(status, 200);
+        assert_eq!(body["total"], 2);
+        // Biggest discount first
+        assert_eq!(body["items"][0]["id"], 1);
+        assert_eq!(body["items"][0]["previousPrice"], 4.0);
+        assert_eq!(body["items"][0]["change"], -0.25);
+        assert_eq!(body["items"][0]["since"], "2024-01-20");
+    }
+
+    #[test]
+    fn etag_and_gzip() {
+        let response = finish_response(200, String::from("{}"), None, true).unwrap();
+        assert_eq!(response.status, 200);
+        let mut body = String::new();
+        GzDecoder::new(&response.body[..])
+            .read_to_string(&mut body)
+            .unwrap();
+        assert_eq!(body, "{}");
+
+        let (_, etag) = response
+            .headers
+            .iter()
+            .find(|(name, _)| *name == "ETag")
+            .unwrap();
+        let response = finish_response(200, String::from("{}"), Some(etag), true).unwrap();
+        assert_eq!(response.status, 304);
+        assert!(response.body.is_empty());
+    }
+}
@@ -0,0 +1,445 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::read_to_string;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::Serialize;
+
+use crate::{
+    product::{price_serde, Price, ProductHistory},
+    search::{tokenize, OutputFormat},
+    storage::load_history,
+    stores::Store,
+    unit::{normalise_unit, Unit},
+};
+
+lazy_static! {
+    // Amount of an item, e.g. "2l", "1.5 kg" or just "6" in "6 eggs"
+    static ref AMOUNT_REGEX: Regex =
+        Regex::new(r"\b(?P<quantity>[0-9]+(?:\.[0-9]+)?) ?(?P<unit>[a-z]+)?\b").unwrap();
+}
+
+// Pack sizes are compared with this tolerance so float rounding doesn't add a whole pack
+const QUANTITY_EPSILON: f64 = 1e-6;
+
+/// One line of a shopping list, e.g. "2L full cream milk"
+#[derive(Debug, PartialEq)]
+struct ListItem {
+    line: String,
+    quantity: f64,
+    unit: Unit,
+    words: Vec<String>,
+}
+
+impl ListItem {
+    /// Items without an amount are a single piece, an amount without a known unit is a count,
+    /// e.g. "6 eggs"
+    fn parse(line: &str) -> anyhow::Result<Self> {
+        let lower = line.to_lowercase();
+        let (quantity, unit, rest) = match AMOUNT_REGEX.captures(&lower) {
+            Some(captures) => {
+                let quantity: f64 = captures["quantity"].parse()?;
+                let unit = captures
+                    .name("unit")
+                    .and_then(|unit| Some((unit, normalise_unit(unit.as_str()).ok()?)));
+                match unit {
+                    Some((unit_match, (factor, unit))) => {
+                        let start = captures.get(0).expect("whole match").start();
+                        let rest = format!("{} {}", &lower[..start], &lower[unit_match.end()..]);
+                        (quantity * factor, unit, rest)
+                    }
+                    None => {
+                        let amount = captures.name("quantity").expect("quantity is required");
+                        let rest =
+                            format!("{} {}", &lower[..amount.start()], &lower[amount.end()..]);
+                        (quantity, Unit::Each, rest)
+                    }
+                }
+            }
+            None => (1.0, Unit::Each, lower.clone()),
+        };
+        let words: Vec<String> = tokenize(&rest).collect();
+        if words.is_empty() {
+            bail!("Shopping list item {line:?} doesn't name a product");
+        }
+        if quantity <= 0.0 {
+            bail!("Shopping list item {line:?} has no quantity");
+        }
+        Ok(Self {
+            line: line.to_string(),
+            quantity,
+            unit,
+            words,
+        })
+    }
+
+    /// Products sold in the same unit whose name or brand contains every word of the item. Of
+    /// those only the ones in the most common category are kept, so "milk" doesn't pick
+    /// chocolate bars that happen to be milk chocolate.
+    fn candidates<'a>(&self, products: &'a [ProductHistory]) -> Vec<&'a ProductHistory> {
+        let candidates: Vec<&ProductHistory> = products
+            .iter()
+            .filter(|p| {
+                let info = p.product_info();
+                if info.unit() != self.unit || info.quantity() <= 0.0 {
+                    return false;
+                }
+                let tokens: Vec<String> = tokenize(info.name())
+                    .chain(info.brand().into_iter().flat_map(tokenize))
+                    .collect();
+                self.words
+                    .iter()
+                    .all(|word| tokens.iter().any(|token| token.contains(word.as_str())))
+            })
+            .collect();
+
+        let mut counts: BTreeMap<&'static str, usize> = BTreeMap::new();
+        for candidate in candidates.iter() {
+            if let Some(category) = candidate.product_info().category() {
+                *counts.entry(category.code()).or_default() += 1;
+            }
+        }
+        // Ties go to the lowest code so the result doesn't depend on the order of the products
+        let Some(category) = counts
+            .into_iter()
+            .max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(a.0)))
+            .map(|(code, _)| code)
+        else {
+            return candidates;
+        };
+        candidates
+            .into_iter()
+            .filter(|p| {
+                p.product_info()
+                    .category()
+                    .is_some_and(|c| c.code() == category)
+            })
+            .collect()
+    }
+}
+
+/// Product picked for an item and how many packs of it are needed
+#[derive(Debug, Clone, Serialize)]
+struct Choice {
+    store: Store,
+    id: i64,
+    name: String,
+    quantity: f64,
+    unit: Unit,
+    #[serde(with = "price_serde")]
+    price: Price,
+    packs: u32,
+    #[serde(with = "price_serde")]
+    cost: Price,
+}
+
+impl Choice {
+    fn new(item: &ListItem, product: &ProductHistory) -> Self {
+        let info = product.product_info();
+        let price = product.price_history().first().price();
+        let packs = (item.quantity / info.quantity() - QUANTITY_EPSILON)
+            .ceil()
+            .max(1.0) as u32;
+        Self {
+            store: info.store(),
+            id: info.id(),
+            name: info.name().to_string(),
+            quantity: info.quantity(),
+            unit: info.unit(),
+            price,
+            packs,
+            cost: price * packs,
+        }
+    }
+
+    /// Cheapest first, then the one that leaves the least over
+    fn is_better_than(&self, other: &Choice) -> bool {
+        let total = |c: &Choice| c.quantity * c.packs as f64;
+        self.cost
+            .cmp(&other.cost)
+            .then(total(self).total_cmp(&total(other)))
+            .then(self.id.cmp(&other.id))
+            .is_lt()
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ItemPlan {
+    item: String,
+    /// Cheapest choice at every store that has a candidate
+    stores: BTreeMap<Store, Choice>,
+    /// Store with the cheapest choice overall
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cheapest: Option<Store>,
+}
+
+#[derive(Debug, Serialize)]
+struct Basket {
+    #[serde(with = "price_serde")]
+    total: Price,
+    /// Items that couldn't be resolved and aren't part of the total
+    missing: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct ShoppingPlan {
+    items: Vec<ItemPlan>,
+    /// Buying everything at a single store
+    stores: BTreeMap<Store, Basket>,
+    /// Buying every item wherever it is cheapest
+    split: Basket,
+}
+
+impl ShoppingPlan {
+    fn new(items: &[ListItem], products: &[ProductHistory]) -> Self {
+        let all_stores: BTreeSet<Store> = products.iter().map(|p| p.store()).collect();
+        let mut plans = Vec::new();
+        for item in items {
+            let mut stores: BTreeMap<Store, Choice> = BTreeMap::new();
+            for candidate in item.candidates(products) {
+                let choice = Choice::new(item, candidate);
+                match stores.get(&choice.store) {
+                    Some(best) if !choice.is_better_than(best) => {}
+                    _ => {
+                        stores.insert(choice.store, choice);
+                    }
+                }
+            }
+            let cheapest = stores
+                .values()
+                .reduce(|best, choice| {
+                    if choice.is_better_than(best) {
+                        choice
+                    } else {
+                        best
+                    }
+                })
+                .map(|choice| choice.store);
+            plans.push(ItemPlan {
+                item: item.line.clone(),
+                stores,
+                cheapest,
+            });
+        }
+
+        let basket = |pick: &dyn Fn(&ItemPlan) -> Option<&Choice>| {
+            let mut total = Price::from(0.0);
+            let mut missing = Vec::new();
+            for plan in plans.iter() {
+                match pick(plan) {
+                    Some(choice) => total = total + choice.cost,
+                    None => missing.push(plan.item.clone()),
+                }
+            }
+            Basket { total, missing }
+        };
+        let stores = all_stores
+            .into_iter()
+            .map(|store| (store, basket(&|plan| plan.stores.get(&store))))
+            .collect();
+        let split = basket(&|plan| plan.cheapest.and_then(|store| plan.stores.get(&store)));
+        Self {
+            items: plans,
+            stores,
+            split,
+        }
+    }
+
+    fn write_table(&self, mut out: impl Write) -> std::io::Result<()> {
+        writeln!(
+            out,
+            "{:<24} {:<8} {:>5} {:>8}  PRODUCT",
+            "ITEM", "STORE", "PACKS", "COST"
+        )?;
+        for plan in self.items.iter() {
+            if plan.stores.is_empty() {
+                writeln!(out, "{:<24} no matching products", plan.item)?;
+            }
+            for (i, (store, choice)) in plan.stores.iter().enumerate() {
+                let item = if i == 0 { plan.item.as_str() } else { "" };
+                // Marks where the item is cheapest
+                let mark = if plan.cheapest == Some(*store) {
+                    "*"
+                } else {
+                    ""
+                };
+                writeln!(
+                    out,
+                    "{:<24} {:<8} {:>5} {:>8}  {}{mark}",
+                    item,
+                    store.to_string(),
+                    choice.packs,
+                    choice.cost.to_string(),
+                    choice.name
+                )?;
+            }
+        }
+        writeln!(out)?;
+        let mut write_total = |name: String, basket: &Basket| {
+            write!(out, "{:<24} {:>23}", name, basket.total.to_string())?;
+            if !basket.missing.is_empty() {
+                write!(out, "  missing: {}", basket.missing.join(", "))?;
+            }
+            writeln!(out)
+        };
+        for (store, basket) in self.stores.iter() {
+            write_total(format!("Total at {store}"), basket)?;
+        }
+        write_total(String::from("Total split"), &self.split)
+    }
+}
+
+/// Reads a shopping list file with one item per line. Empty lines and lines starting with `#`
+/// are skipped.
+fn read_list(path: &Path) -> anyhow::Result<Vec<String>> {
+    let content = read_to_string(path)
+        .with_context(|| format!("Failed to read shopping list {}", path.to_string_lossy()))?;
+    Ok(content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect())
+}
+
+/// Finds the cheapest way to buy the given items, plus any from the list at `list_path`, at
+/// each store and split across stores
+pub fn do_shopping_list(
+    items: &[String],
+    list_path: Option<&Path>,
+    format: OutputFormat,
+    output_dir: &Path,
+) -> anyhow::Result<()> {
+    let mut lines = items.to_vec();
+    if let Some(path) = list_path {
+        lines.extend(read_list(path)?);
+    }
+    if lines.is_empty() {
+        return Err(anyhow!("The shopping list is empty"));
+    }
+    let items = lines
+        .iter()
+        .map(|line| ListItem::parse(line))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let products = load_history(output_dir)?;
+    let plan = ShoppingPlan::new(&items, &products);
+
+    let mut stdout = std::io::stdout().lock();
+    match format {
+        OutputFormat::Table => plan.write_table(stdout)?,
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut stdout, &plan)?;
+            writeln!(stdout)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::product::ProductInfo;
+
+    fn product(
+        store: Store,
+        id: i64,
+        name: &str,
+        unit: Unit,
+        quantity: f64,
+        price: f64,
+    ) -> ProductHistory {
+        let info = ProductInfo::new(
+            id,
+            String::from(name),
+            String::new(),
+            None,
+            None,
+            None,
+            unit,
+            quantity,
+            store,
+            None,
+        );
+        let day = time::Date::from_calendar_date(2024, time::Month::January, 1).unwrap();
+        ProductHistory::with_prices(info, &[(day, price)])
+    }
+
+    #[test]
+    fn parse_items() {
+        let item = ListItem::parse("2L full cream milk").unwrap();
+        assert_eq!(item.quantity, 2000.0);
+        assert_eq!(item.unit, Unit::Millilitre);
+        assert_eq!(item.words, vec!["full", "cream", "milk"]);
+
+        let item = ListItem::parse("carrots 1.5 kg").unwrap();
+        assert_eq!((item.quantity, item.unit), (1500.0, Unit::Grams));
+        assert_eq!(item.words, vec!["carrots"]);
+
+        let item = ListItem::parse("6 eggs").unwrap();
+        assert_eq!((item.quantity, item.unit), (6.0, Unit::Each));
+        assert_eq!(item.words, vec!["eggs"]);
+
+        let item = ListItem::parse("bananas").unwrap();
+        assert_eq!((item.quantity, item.unit), (1.0, Unit::Each));
+
+        assert!(ListItem::parse("2kg").is_err());
+    }
+
+    #[test]
+    fn cheapest_baskets() {
+        let products = vec![
+            product(
+                Store::Coles,
+                1,
+                "Full Cream Milk 1L",
+                Unit::Millilitre,
+                1000.0,
+                1.6,
+            ),
+            product(
+                Store::Coles,
+                2,
+                "Full Cream Milk 3L",
+                Unit::Millilitre,
+                3000.0,
+                4.5,
+            ),
+            product(Store::Coles, 3, "Carrots 1kg", Unit::Grams, 1000.0, 2.5),
+            product(
+                Store::Woolies,
+                4,
+                "Full Cream Milk 2L",
+                Unit::Millilitre,
+                2000.0,
+                3.5,
+            ),
+        ];
+        let items = vec![
+            ListItem::parse("2L full cream milk").unwrap(),
+            ListItem::parse("1kg carrots").unwrap(),
+        ];
+        let plan = ShoppingPlan::new(&items, &products);
+
+        // Two 1L packs beat one 3L pack
+        let milk = &plan.items[0];
+        let coles = &milk.stores[&Store::Coles];
+        assert_eq!((coles.id, coles.packs, coles.cost), (1, 2, 3.2.into()));
+        assert_eq!(milk.stores[&Store::Woolies].cost, 3.5.into());
+        assert_eq!(milk.cheapest, Some(Store::Coles));
+
+        assert_eq!(plan.stores[&Store::Coles].total, 5.7.into());
+        assert!(plan.stores[&Store::Coles].missing.is_empty());
+        assert_eq!(plan.stores[&Store::Woolies].total, 3.5.into());
+        assert_eq!(plan.stores[&Store::Woolies].missing, vec!["1kg carrots"]);
+        assert_eq!(plan.split.total, 5.7.into());
+
+        let mut out = Vec::new();
+        plan.write_table(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("Full Cream Milk 1L*"));
+        assert!(out.contains("missing: 1kg carrots"));
+    }
+}
@@ -0,0 +1,560 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Write as _;
+use std::fs::{self, create_dir_all, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use log::info;
+use time::Date;
+
+use crate::{
+    category::Category,
+    product::ProductHistory,
+    search::{cmp_unit_price, IndexEntry, SortOrder},
+    storage::load_history,
+    stores::Store,
+};
+
+mod chart;
+
+use chart::price_chart;
+
+// Page for products without a category
+const OTHER_CATEGORY: &str = "other";
+// Most URLs search engines accept in a single sitemap
+const SITEMAP_MAX_URLS: usize = 50_000;
+
+const STYLE: &str =
+    "body { font-family: sans-serif; margin: 0 auto; max-width: 60em; padding: 0 1em; }
+header { display: flex; gap: 1em; padding: 1em 0; border-bottom: 1px solid #ccc; }
+table { border-collapse: collapse; width: 100%; }
+th, td { text-align: left; padding: 0.25em 0.5em; border-bottom: 1px solid #eee; }
+td.price { text-align: right; }
+.chart { width: 100%; max-width: 600px; color: #c00; }
+.chart text { font-size: 12px; fill: #555; }
+";
+
+// Searches the entries of search-index.json in the browser, no build step required
+const SEARCH_BODY: &str = r#"<h1>Search</h1>
+<input id="query" type="search" placeholder="Search products" autofocus>
+<table><tbody id="results"></tbody></table>
+<script>
+fetch("search-index.json").then(response => response.json()).then(entries => {
+  const query = document.getElementById("query");
+  const results = document.getElementById("results");
+  const render = () => {
+    const words = query.value.toLowerCase().split(/\s+/).filter(word => word);
+    results.replaceChildren();
+    if (!words.length) return;
+    entries
+      .filter(entry => {
+        const text = (entry.name + " " + (entry.brand || "")).toLowerCase();
+        return words.every(word => text.includes(word));
+      })
+      .slice(0, 100)
+      .forEach(entry => {
+        const row = results.insertRow();
+        const link = document.createElement("a");
+        link.href = `products/${entry.store}/${entry.id}.html`;
+        link.textContent = entry.name;
+        row.insertCell().append(link);
+        row.insertCell().textContent = entry.store;
+        const price = row.insertCell();
+        price.className = "price";
+        price.textContent = entry.price.toFixed(2);
+      });
+  };
+  query.addEventListener("input", render);
+  render();
+});
+</script>
+"#;
+
+pub(crate) fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+pub(crate) fn product_path(store: Store, id: i64) -> String {
+    format!("products/{store}/{id}.html")
+}
+
+fn category_path(code: &str, sort: SortOrder) -> String {
+    match sort {
+        SortOrder::Name => format!("categories/{code}.html"),
+        SortOrder::UnitPrice => format!("categories/{code}-unit-price.html"),
+    }
+}
+
+/// Wraps `body` in the common layout. `root` is the relative path back to the top of the site,
+/// e.g. "../../" for product pages.
+fn page(title: &str, root: &str, body: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{title} - Hot Prices</title>
+<link rel="stylesheet" href="{root}style.css">
+</head>
+<body>
+<header><a href="{root}index.html">Hot Prices</a><a href="{root}search.html">Search</a></header>
+<main>
+{body}</main>
+</body>
+</html>
+"#,
+        title = escape_html(title)
+    )
+}
+
+fn unit_price_text(product: &ProductHistory) -> String {
+    let info = product.product_info();
+    match info.unit_price() {
+        Some(price) => format!("{price}/{}", info.unit().unit_price_label()),
+        None => String::from("-"),
+    }
+}
+
+/// Table of products linking to their pages
+fn product_table(products: &[&ProductHistory], root: &str) -> String {
+    let mut html = String::from(
+        "<table>\n<tr><th>Product</th><th>Store</th><th>Price</th><th>Unit price</th></tr>\n",
+    );
+    for product in products {
+        let info = product.product_info();
+        let _ = writeln!(
+            html,
+            r#"<tr><td><a href="{root}{}">{}</a></td><td>{}</td><td class="price">{}</td><td class="price">{}</td></tr>"#,
+            product_path(info.store(), info.id()),
+            escape_html(info.name()),
+            info.store(),
+            product.price_history().first().price(),
+            unit_price_text(product)
+        );
+    }
+    html.push_str("</table>\n");
+    html
+}
+
+/// Products grouped by category code, with their category if they have one
+struct CategoryPage<'a> {
+    code: &'static str,
+    category: Option<Category>,
+    products: Vec<&'a ProductHistory>,
+}
+
+impl CategoryPage<'_> {
+    fn name(&self) -> &'static str {
+        self.category.map_or("Other", |c| c.name())
+    }
+}
+
+fn category_pages(products: &[ProductHistory]) -> Vec<CategoryPage<'_>> {
+    let mut pages: BTreeMap<&'static str, CategoryPage> = BTreeMap::new();
+    for product in products {
+        let category = product.product_info().category();
+        let code = category.map_or(OTHER_CATEGORY, |c| c.code());
+        pages
+            .entry(code)
+            .or_insert_with(|| CategoryPage {
+                code,
+                category,
+                products: Vec::new(),
+            })
+            .products
+            .push(product);
+    }
+    let mut pages: Vec<CategoryPage> = pages.into_values().collect();
+    for page in pages.iter_mut() {
+        page.products.sort_by(|a, b| {
+            a.product_info()
+                .name()
+                .cmp(b.product_info().name())
+                .then(a.store().cmp(&b.store()))
+                .then(a.id().cmp(&b.id()))
+        });
+    }
+    pages
+}
+
+fn render_index(pages: &[CategoryPage], products: &[ProductHistory], as_of: Date) -> String {
+    let mut body =
+        format!("<h1>Hot Prices</h1>\n<p>Prices as of {as_of}.</p>\n<h2>Categories</h2>\n<ul>\n");
+    for page in pages {
+        let _ = writeln!(
+            body,
+            r#"<li><a href="{}">{}</a> ({})</li>"#,
+            category_path(page.code, SortOrder::Name),
+            escape_html(page.name()),
+            page.products.len()
+        );
+    }
+    body.push_str("</ul>\n<h2>Stores</h2>\n<ul>\n");
+    let mut stores: BTreeMap<Store, usize> = BTreeMap::new();
+    for product in products {
+        *stores.entry(product.store()).or_default() += 1;
+    }
+    for (store, count) in stores {
+        let _ = writeln!(body, "<li>{store} ({count} products)</li>");
+    }
+    body.push_str("</ul>\n");
+    page("Grocery prices", "", &body)
+}
+
+/// Category page with its products in `sort` order, linking to the page in the other order
+fn render_category(category: &CategoryPage, sort: SortOrder) -> String {
+    let mut products = category.products.clone();
+    if sort == SortOrder::UnitPrice {
+        products.sort_by(|a, b| {
+            cmp_unit_price(a.product_info().unit_price(), b.product_info().unit_price())
+        });
+    }
+    let link = |label: &str, order: SortOrder| match order == sort {
+        true => format!("<strong>{label}</strong>"),
+        false => format!(
+            r#"<a href="../{}">{label}</a>"#,
+            category_path(category.code, order)
+        ),
+    };
+    let body = format!(
+        "<h1>{}</h1>\n<p>Sort by {} | {}</p>\n{}",
+        escape_html(category.name()),
+        link("name", SortOrder::Name),
+        link("unit price", SortOrder::UnitPrice),
+        product_table(&products, "../")
+    );
+    page(category.name(), "../", &body)
+}
+
+fn render_product(product: &ProductHistory, as_of: Date) -> String {
+    let root = "../../";
+    let info = product.product_info();
+    let history = product.price_history();
+    let mut body = format!("<h1>{}</h1>\n<dl>\n", escape_html(info.name()));
+    if let Some(brand) = info.brand() {
+        let _ = writeln!(body, "<dt>Brand</dt><dd>{}</dd>", escape_html(brand));
+    }
+    let _ = writeln!(body, "<dt>Store</dt><dd>{}</dd>", info.store());
+    if let Some(category) = info.category() {
+        let _ = writeln!(
+            body,
+            r#"<dt>Category</dt><dd><a href="{root}{}">{}</a></dd>"#,
+            category_path(category.code(), SortOrder::Name),
+            escape_html(category.name())
+        );
+    }
+    let _ = writeln!(
+        body,
+        "<dt>Price</dt><dd>{}</dd>\n<dt>Unit price</dt><dd>{}</dd>\n<dt>Since</dt><dd>{}</dd>\n</dl>",
+        history.first().price(),
+        unit_price_text(product),
+        history.first().date()
+    );
+    body.push_str(&price_chart(history, as_of));
+    body.push_str(
+        "\n<h2>Price history</h2>\n<table>\n<tr><th>Date</th><th>Price</th><th></th></tr>\n",
+    );
+    for snapshot in history.iter() {
+        let special = if snapshot.promotion() { "Special" } else { "" };
+        let _ = writeln!(
+            body,
+            r#"<tr><td>{}</td><td class="price">{}</td><td>{special}</td></tr>"#,
+            snapshot.date(),
+            snapshot.price()
+        );
+    }
+    body.push_str("</table>\n");
+    page(info.name(), root, &body)
+}
+
+/// Sitemap listing every page with the day of its latest change where known
+fn render_sitemap(base_url: &str, pages: &[(String, Option<Date>)]) -> String {
+    let base_url = base_url.trim_end_matches('/');
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+    );
+    for (path, modified) in pages {
+        let _ = write!(
+            xml,
+            "<url><loc>{}</loc>",
+            escape_html(&format!("{base_url}/{path}"))
+        );
+        if let Some(modified) = modified {
+            let _ = write!(xml, "<lastmod>{modified}</lastmod>");
+        }
+        xml.push_str("</url>\n");
+    }
+    xml.push_str("</urlset>\n");
+    xml
+}
+
+/// Sitemaps of at most `max_urls` pages each, named `sitemap-1.xml` and so on, followed by the
+/// `sitemap_index.xml` listing them
+fn render_sitemaps(
+    base_url: &str,
+    pages: &[(String, Option<Date>)],
+    max_urls: usize,
+) -> Vec<(String, String)> {
+    let mut files: Vec<(String, String)> = Vec::new();
+    let mut index = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<sitemapindex xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+    );
+    for (n, chunk) in pages.chunks(max_urls).enumerate() {
+        let name = format!("sitemap-{}.xml", n + 1);
+        let _ = write!(
+            index,
+            "<sitemap><loc>{}</loc>",
+            escape_html(&format!("{}/{name}", base_url.trim_end_matches('/')))
+        );
+        if let Some(modified) = chunk.iter().filter_map(|(_, modified)| *modified).max() {
+            let _ = write!(index, "<lastmod>{modified}</lastmod>");
+        }
+        index.push_str("</sitemap>\n");
+        files.push((name, render_sitemap(base_url, chunk)));
+    }
+    index.push_str("</sitemapindex>\n");
+    files.push((String::from("sitemap_index.xml"), index));
+    files
+}
+
+/// Removes pages below `dir` that weren't rendered this time, e.g. for products that were
+/// dropped from the history
+fn remove_stale_pages(dir: &Path, rendered: &HashSet<PathBuf>) -> anyhow::Result<usize> {
+    let mut removed = 0;
+    if !dir.exists() {
+        return Ok(removed);
+    }
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            removed += remove_stale_pages(&path, rendered)?;
+        } else if path.extension().is_some_and(|ext| ext == "html") && !rendered.contains(&path) {
+            fs::remove_file(&path)
+                .with_context(|| format!("Failed to remove {}", path.to_string_lossy()))?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// Removes sitemaps left over from a bigger site and the single sitemap used before they were
+/// split up
+fn remove_stale_sitemaps(site_dir: &Path, sitemaps: usize) -> anyhow::Result<()> {
+    let mut stale = vec![site_dir.join("sitemap.xml")];
+    let mut n = sitemaps + 1;
+    while site_dir.join(format!("sitemap-{n}.xml")).exists() {
+        stale.push(site_dir.join(format!("sitemap-{n}.xml")));
+        n += 1;
+    }
+    for path in stale.into_iter().filter(|path| path.exists()) {
+        fs::remove_file(&path)
+            .with_context(|| format!("Failed to remove {}", path.to_string_lossy()))?;
+    }
+    Ok(())
+}
+
+fn write_file(site_dir: &Path, path: &str, content: &[u8]) -> anyhow::Result<()> {
+    let path = site_dir.join(path);
+    if let Some(parent) = path.parent() {
+        create_dir_all(parent)?;
+    }
+    let mut file = BufWriter::new(
+        File::create(&path)
+            .with_context(|| format!("Failed to create {}", path.to_string_lossy()))?,
+    );
+    file.write_all(content)?;
+    file.flush()?;
+    Ok(())
+}
+
+/// Renders the complete site for `products` into `site_dir`
+pub(crate) fn render_site(
+    products: &[ProductHistory],
+    site_dir: &Path,
+    base_url: &str,
+) -> anyhow::Result<()> {
+    // Charts run until the last day any price was recorded
+    let as_of = products
+        .iter()
+        .map(|p| p.price_history().first().date())
+        .max()
+        .unwrap_or(time::OffsetDateTime::now_utc().date());
+    let categories = category_pages(products);
+    let mut sitemap = vec![
+        (String::from("index.html"), Some(as_of)),
+        (String::from("search.html"), None),
+    ];
+
+    write_file(site_dir, "style.css", STYLE.as_bytes())?;
+    write_file(
+        site_dir,
+        "index.html",
+        render_index(&categories, products, as_of).as_bytes(),
+    )?;
+    write_file(
+        site_dir,
+        "search.html",
+        page("Search", "", SEARCH_BODY).as_bytes(),
+    )?;
+    // Pages that are rendered but not listed in the sitemap, as they only differ in order
+    let mut unlisted = Vec::new();
+    for category in categories.iter() {
+        let path = category_path(category.code, SortOrder::UnitPrice);
+        let html = render_category(category, SortOrder::UnitPrice);
+        write_file(site_dir, &path, html.as_bytes())?;
+        unlisted.push(path);
+
+        let path = category_path(category.code, SortOrder::Name);
+        write_file(
+            site_dir,
+            &path,
+            render_category(category, SortOrder::Name).as_bytes(),
+        )?;
+        let modified = category
+            .products
+            .iter()
+            .map(|p| p.price_history().first().date())
+            .max();
+        sitemap.push((path, modified));
+    }
+    for product in products {
+        let path = product_path(product.store(), product.id());
+        write_file(site_dir, &path, render_product(product, as_of).as_bytes())?;
+        sitemap.push((path, Some(product.price_history().first().date())));
+    }
+
+    // Only the current price is needed for search results
+    let index: Vec<IndexEntry> = products
+        .iter()
+        .map(|p| IndexEntry::from(p).with_changes(0))
+        .collect();
+    write_file(site_dir, "search-index.json", &serde_json::to_vec(&index)?)?;
+    let sitemaps = render_sitemaps(base_url, &sitemap, SITEMAP_MAX_URLS);
+    for (name, xml) in sitemaps.iter() {
+        write_file(site_dir, name, xml.as_bytes())?;
+    }
+    // All but the last file are sitemaps, the last one is the index
+    remove_stale_sitemaps(site_dir, sitemaps.len() - 1)?;
+
+    let rendered: HashSet<PathBuf> = sitemap
+        .iter()
+        .map(|(path, _)| path)
+        .chain(unlisted.iter())
+        .map(|path| site_dir.join(path))
+        .collect();
+    let removed = remove_stale_pages(&site_dir.join("products"), &rendered)?
+        + remove_stale_pages(&site_dir.join("categories"), &rendered)?;
+    info!(
+        "Rendered {} product and {} category pages, removed {removed} stale pages",
+        products.len(),
+        categories.len()
+    );
+    Ok(())
+}
+
+/// Renders a static site with index, category and product pages from the canonical history
+pub fn do_site(output_dir: &Path, site_dir: &Path, base_url: &str) -> anyhow::Result<()> {
+    let products = load_history(output_dir)?;
+    render_site(&products, site_dir, base_url)
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::read_to_string;
+
+    use tempfile::tempdir;
+    use time::Month;
+
+    use super::*;
+    use crate::product::ProductInfo;
+
+    #[test]
+    fn escapes_html() {
+        assert_eq!(
+            escape_html(r#"Fish & <Chips> "Large""#),
+            "Fish &amp; &lt;Chips&gt; &quot;Large&quot;"
+        );
+    }
+
+    #[test]
+    fn renders_site() {
+        let day = Date::from_calendar_date(2024, Month::January, 1).unwrap();
+        let products = vec![
+            ProductHistory::with_prices(
+                ProductInfo::with_store(Store::Coles).with_id(1),
+                &[(day, 2.0)],
+            ),
+            ProductHistory::with_prices(
+                ProductInfo::with_store(Store::Woolies).with_id(2),
+                &[(day, 3.0)],
+            ),
+        ];
+        let tmpdir = tempdir().unwrap();
+        let site_dir = tmpdir.path();
+        render_site(&products, site_dir, "https://example.com/").unwrap();
+
+        let product = read_to_string(site_dir.join("products/coles/1.html")).unwrap();
+        assert!(product.contains("<svg"));
+        assert!(product.contains(r#"href="../../style.css""#));
+        assert!(site_dir.join("products/woolies/2.html").exists());
+        assert!(site_dir.join("categories/other.html").exists());
+        let by_unit_price =
+            read_to_string(site_dir.join("categories/other-unit-price.html")).unwrap();
+        assert!(by_unit_price.contains(r#"Sort by <a href="../categories/other.html">name</a>"#));
+
+        let index = read_to_string(site_dir.join("index.html")).unwrap();
+        assert!(index.contains(r#"<a href="categories/other.html">Other</a> (2)"#));
+
+        let search: serde_json::Value =
+            serde_json::from_str(&read_to_string(site_dir.join("search-index.json")).unwrap())
+                .unwrap();
+        assert_eq!(search.as_array().unwrap().len(), 2);
+
+        let sitemap = read_to_string(site_dir.join("sitemap-1.xml")).unwrap();
+        assert!(sitemap.contains(
+            "<url><loc>https://example.com/products/coles/1.html</loc><lastmod>2024-01-01</lastmod></url>"
+        ));
+        let sitemap_index = read_to_string(site_dir.join("sitemap_index.xml")).unwrap();
+        assert!(sitemap_index.contains(
+            "<sitemap><loc>https://example.com/sitemap-1.xml</loc><lastmod>2024-01-01</lastmod></sitemap>"
+        ));
+
+        // Products that are gone lose their page
+        render_site(&products[..1], site_dir, "https://example.com/").unwrap();
+        assert!(site_dir.join("products/coles/1.html").exists());
+        assert!(!site_dir.join("products/woolies/2.html").exists());
+    }
+
+    #[test]
+    fn splits_sitemaps() {
+        let pages = vec![
+            (String::from("index.html"), None),
+            (String::from("search.html"), None),
+            (String::from("products/coles/1.html"), None),
+        ];
+        let files = render_sitemaps("https://example.com", &pages, 2);
+        let names: Vec<&str> = files.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec!["sitemap-1.xml", "sitemap-2.xml", "sitemap_index.xml"]
+        );
+        assert_eq!(files[0].1.matches("<url>").count(), 2);
+        assert_eq!(files[1].1.matches("<url>").count(), 1);
+        assert!(files[2]
+            .1
+            .contains("<sitemap><loc>https://example.com/sitemap-2.xml</loc></sitemap>"));
+    }
+}
@@ -0,0 +1,123 @@
+use std::fmt::Write;
+
+use nonempty::NonEmpty;
+use time::Date;
+
+use crate::product::PriceSnapshot;
+
+const WIDTH: f64 = 600.0;
+const HEIGHT: f64 = 200.0;
+// Space around the plot for the axis labels
+const PADDING: f64 = 40.0;
+
+/// Position of every corner of the step line, from the first price until `end`
+fn step_points(history: &NonEmpty<PriceSnapshot>, end: Date) -> Vec<(f64, f64)> {
+    // History is sorted latest first, the chart goes from left to right
+    let snapshots: Vec<&PriceSnapshot> = history.iter().rev().collect();
+    let start = snapshots[0].date();
+    let end = end.max(history.first().date());
+    let days = (end - start).whole_days().max(1) as f64;
+    let (low, high) = price_range(history);
+
+    let x =
+        |date: Date| PADDING + (date - start).whole_days() as f64 / days * (WIDTH - 2.0 * PADDING);
+    let y = |snapshot: &PriceSnapshot| {
+        let price = f64::from(snapshot.price());
+        HEIGHT - PADDING - (price - low) / (high - low) * (HEIGHT - 2.0 * PADDING)
+    };
+
+    let mut points = Vec::new();
+    for (i, snapshot) in snapshots.iter().enumerate() {
+        // A price lasts until the next change, so the line only moves up or down on that day
+        if i > 0 {
+            points.push((x(snapshot.date()), y(snapshots[i - 1])));
+        }
+        points.push((x(snapshot.date()), y(snapshot)));
+    }
+    points.push((x(end), y(history.first())));
+    points
+}
+
+/// Lowest and highest price shown on the chart. A price that never changed gets some room so
+/// the line ends up in the middle.
+fn price_range(history: &NonEmpty<PriceSnapshot>) -> (f64, f64) {
+    let low = history.iter().map(|s| s.price()).min().expect("non-empty");
+    let high = history.iter().map(|s| s.price()).max().expect("non-empty");
+    let (low, high) = (f64::from(low), f64::from(high));
+    if high - low < 0.01 {
+        ((low - 1.0).max(0.0), high + 1.0)
+    } else {
+        (low, high)
+    }
+}
+
+/// Step chart of the price history as an inline SVG, drawn up to `end`
+pub(crate) fn price_chart(history: &NonEmpty<PriceSnapshot>, end: Date) -> String {
+    let points = step_points(history, end);
+    let (low, high) = price_range(history);
+    let first = history.last().date();
+    let end = end.max(history.first().date());
+
+    let mut svg = String::new();
+    let _ = write!(
+        svg,
+        r#"<svg class="chart" xmlns="http://www.w3.org/2000/svg" viewBox="0 0 {WIDTH} {HEIGHT}" role="img" aria-label="Price history">"#
+    );
+    let polyline: Vec<String> = points
+        .iter()
+        .map(|(x, y)| format!("{x:.1},{y:.1}"))
+        .collect();
+    let _ = write!(
+        svg,
+        r#"<polyline fill="none" stroke="currentColor" stroke-width="2" points="{}"/>"#,
+        polyline.join(" ")
+    );
+    let label_x = PADDING - 4.0;
+    let _ = write!(
+        svg,
+        r#"<text x="{label_x}" y="{}" text-anchor="end">{high:.2}</text>"#,
+        PADDING + 4.0
+    );
+    let _ = write!(
+        svg,
+        r#"<text x="{label_x}" y="{}" text-anchor="end">{low:.2}</text>"#,
+        HEIGHT - PADDING + 4.0
+    );
+    let label_y = HEIGHT - PADDING + 20.0;
+    let _ = write!(
+        svg,
+        r#"<text x="{PADDING}" y="{label_y}">{first}</text><text x="{}" y="{label_y}" text-anchor="end">{end}</text>"#,
+        WIDTH - PADDING
+    );
+    svg.push_str("</svg>");
+    svg
+}
+
+#[cfg(test)]
+mod test {
+    use time::Month;
+
+    use super::*;
+    use crate::product::{ProductHistory, ProductInfo};
+
+    fn day(day: u8) -> Date {
+        Date::from_calendar_date(2024, Month::January, day).unwrap()
+    }
+
+    #[test]
+    fn steps() {
+        let product =
+            ProductHistory::with_prices(ProductInfo::default(), &[(day(3), 1.0), (day(1), 2.0)]);
+        let points = step_points(product.price_history(), day(5));
+        // Four days over 520 pixels, prices from 1.00 at the bottom to 2.00 at the top
+        assert_eq!(
+            points,
+            vec![(40.0, 40.0), (300.0, 40.0), (300.0, 160.0), (560.0, 160.0)]
+        );
+
+        let svg = price_chart(product.price_history(), day(5));
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.contains(">2.00</text>"));
+        assert!(svg.contains(">2024-01-05</text>"));
+    }
+}
@@ -0,0 +1,221 @@
+use serde::Serialize;
+use time::{Date, Duration};
+
+use crate::{
+    product::{price_serde, Price, PriceSnapshot, ProductHistory},
+    stores::Store,
+};
+
+// A price only counts as a real discount if it is at least this much below the usual price
+const REAL_DISCOUNT_MIN: f64 = 0.05;
+// Number of days before the current price that make up the usual price
+const REAL_DISCOUNT_WINDOW: i64 = 90;
+// Number of days up to the stats day that volatility is measured over
+const VOLATILITY_WINDOW: i64 = 90;
+
+/// Statistics over the price history of a product, as of a given day
+#[derive(Debug, Serialize)]
+pub(crate) struct PriceStats {
+    store: Store,
+    id: i64,
+    #[serde(rename = "allTimeLow", with = "price_serde")]
+    all_time_low: Price,
+    #[serde(rename = "allTimeHigh", with = "price_serde")]
+    all_time_high: Price,
+    #[serde(rename = "average30", with = "price_serde::option")]
+    average_30: Option<Price>,
+    #[serde(rename = "average90", with = "price_serde::option")]
+    average_90: Option<Price>,
+    #[serde(rename = "average365", with = "price_serde::option")]
+    average_365: Option<Price>,
+    #[serde(rename = "daysSinceChange")]
+    days_since_change: i64,
+    changes: usize,
+    /// Standard deviation of the relative day-over-day price changes over the last 90 days, 0
+    /// for a price that never changed
+    #[serde(rename = "volatility90")]
+    volatility_90: Option<f64>,
+    /// Whether the current price is clearly below what the product usually cost in the months
+    /// before, rather than a "special" that just undoes a recent increase
+    #[serde(rename = "realDiscount")]
+    real_discount: bool,
+}
+
+impl PriceStats {
+    pub(crate) fn new(product: &ProductHistory, as_of: Date) -> Self {
+        let history: Vec<&PriceSnapshot> = product.price_history().iter().collect();
+        let current = history[0];
+        let average =
+            |days: i64| time_weighted_average(&history, as_of - Duration::days(days - 1), as_of);
+
+        // The usual price is the average over the window before the current price took effect
+        let usual = time_weighted_average(
+            &history,
+            current.date() - Duration::days(REAL_DISCOUNT_WINDOW),
+            current.date() - Duration::days(1),
+        );
+        let real_discount =
+            usual.is_some_and(|usual| current.price().change_from(usual) <= -REAL_DISCOUNT_MIN);
+
+        Self {
+            store: product.store(),
+            id: product.id(),
+            all_time_low: history.iter().map(|s| s.price()).min().expect("non-empty"),
+            all_time_high: history.iter().map(|s| s.price()).max().expect("non-empty"),
+            average_30: average(30),
+            average_90: average(90),
+            average_365: average(365),
+            days_since_change: (as_of - current.date()).whole_days(),
+            changes: history.len() - 1,
+            volatility_90: volatility(
+                &history,
+                as_of - Duration::days(VOLATILITY_WINDOW - 1),
+                as_of,
+            ),
+            real_discount,
+        }
+    }
+}
+
+/// Average of the daily prices from `start` to `end` (inclusive), weighting every price by the
+/// number of days it was in effect. Days before the product's first price are ignored, so
+/// `None` is returned if it wasn't sold at all during that time.
+fn time_weighted_average(history: &[&PriceSnapshot], start: Date, end: Date) -> Option<Price> {
+    let mut total = 0.0;
+    let mut days = 0;
+    // History is sorted latest first, so each price lasts until the day before the next newer one
+    let mut until = end;
+    for snapshot in history {
+        if snapshot.date() <= until {
+            let from = snapshot.date().max(start);
+            if from <= until {
+                let n = (until - from).whole_days() + 1;
+                total += f64::from(snapshot.price()) * n as f64;
+                days += n;
+            }
+        }
+        until = until.min(snapshot.date() - Duration::days(1));
+        if until < start {
+            break;
+        }
+    }
+    (days > 0).then(|| Price::from(total / days as f64))
+}
+
+/// Standard deviation of the relative changes between the prices of consecutive days from
+/// `start` to `end` (inclusive). Like for averages, days before the product's first price are
+/// ignored, so `None` is returned if there are less than two days to compare.
+fn volatility(history: &[&PriceSnapshot], start: Date, end: Date) -> Option<f64> {
+    let mut prices = Vec::new();
+    let mut day = start;
+    while day <= end {
+        // History is sorted latest first, so the first older snapshot is the one in effect
+        if let Some(snapshot) = history.iter().find(|s| s.date() <= day) {
+            prices.push(snapshot.price());
+        }
+        day += Duration::days(1);
+    }
+    let changes: Vec<f64> = prices
+        .windows(2)
+        .map(|pair| pair[1].change_from(pair[0]))
+        .collect();
+    if changes.is_empty() {
+        return None;
+    }
+    let n = changes.len() as f64;
+    let mean = changes.iter().sum::<f64>() / n;
+    let variance = changes.iter().map(|c| (c - mean).powi(2)).sum::<f64>() / n;
+    Some(variance.sqrt())
+}
+
+pub(crate) fn price_stats(products: &[ProductHistory], as_of: Date) -> Vec<PriceStats> {
+    products.iter().map(|p| PriceStats::new(p, as_of)).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use time::Month;
+
+    use super::*;
+    use crate::product::ProductInfo;
+
+    fn day(month: Month, day: u8) -> Date {
+        Date::from_calendar_date(2024, month, day).unwrap()
+    }
+
+    fn product(prices: &[(Date, f64)]) -> ProductHistory {
+        ProductHistory::with_prices(ProductInfo::default(), prices)
+    }
+
+    #[test]
+    fn average_is_time_weighted() {
+        // 4.00 for 9 days, then 1.00 for one day
+        let p = product(&[
+            (day(Month::January, 10), 1.0),
+            (day(Month::January, 1), 4.0),
+        ]);
+        let history: Vec<&PriceSnapshot> = p.price_history().iter().collect();
+        let avg = time_weighted_average(&history, day(Month::January, 1), day(Month::January, 10));
+        assert_eq!(avg, Some(3.7.into()));
+
+        // Days before the first price don't count
+        let avg = time_weighted_average(
+            &history,
+            Date::from_calendar_date(2023, Month::December, 1).unwrap(),
+            day(Month::January, 9),
+        );
+        assert_eq!(avg, Some(4.0.into()));
+
+        let avg = time_weighted_average(
+            &history,
+            Date::from_calendar_date(2023, Month::December, 1).unwrap(),
+            Date::from_calendar_date(2023, Month::December, 31).unwrap(),
+        );
+        assert_eq!(avg, None);
+    }
+
+    #[test]
+    fn stats() {
+        let p = product(&[
+            (day(Month::March, 1), 3.0),
+            (day(Month::February, 1), 5.0),
+            (day(Month::January, 1), 4.0),
+        ]);
+        let stats = PriceStats::new(&p, day(Month::March, 11));
+        assert_eq!(stats.all_time_low, 3.0.into());
+        assert_eq!(stats.all_time_high, 5.0.into());
+        assert_eq!(stats.days_since_change, 10);
+        assert_eq!(stats.changes, 2);
+        assert_eq!(stats.average_30, Some(4.27.into()));
+        assert!(stats.real_discount);
+    }
+
+    #[test]
+    fn special_after_increase_is_not_real_discount() {
+        // Raised to 5.00 for a few days, then "reduced" to 4.25
+        let p = product(&[
+            (day(Month::March, 1), 4.25),
+            (day(Month::February, 25), 5.0),
+            (day(Month::January, 1), 4.0),
+        ]);
+        let stats = PriceStats::new(&p, day(Month::March, 1));
+        assert!(!stats.real_discount);
+    }
+
+    #[test]
+    fn volatility_of_daily_changes() {
+        // 2.00, 2.00, 1.00, 1.00 gives changes of 0%, -50% and 0%
+        let p = product(&[(day(Month::January, 3), 1.0), (day(Month::January, 1), 2.0)]);
+        let stats = PriceStats::new(&p, day(Month::January, 4));
+        let volatility = stats.volatility_90.unwrap();
+        assert!((volatility - (1.0f64 / 18.0).sqrt()).abs() < 1e-9);
+
+        // The window only looks back 90 days
+        let stats = PriceStats::new(&p, day(Month::May, 1));
+        assert_eq!(stats.volatility_90, Some(0.0));
+
+        // A single day has nothing to compare to
+        let stats = PriceStats::new(&p, day(Month::January, 1));
+        assert_eq!(stats.volatility_90, None);
+    }
+}
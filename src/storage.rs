@@ -1,17 +1,31 @@
 use anyhow::Context;
 use flate2::{read::GzDecoder, write::GzEncoder, Compression};
 use log::{debug, info};
+use serde_json::Value;
+use std::collections::{hash_map::Entry, HashMap};
 use std::fs::{self};
 use std::path::PathBuf;
+use std::time::SystemTime;
 use std::{
     fs::{create_dir_all, File},
     io::{BufReader, BufWriter, Write},
     path::Path,
 };
 use strum::IntoEnumIterator;
-use time::Date;
+use time::{macros::format_description, Date};
 
+use crate::config::Config;
+use crate::conversion::{Conversion, ConversionFailure, ConversionMetrics};
+use crate::feeds::FeedEvent;
+use crate::inflation::InflationIndex;
+use crate::manifest::SyncManifest;
+use crate::matching::{MatchGroup, MatchOverrides};
+use crate::price_changes::PriceChangeReport;
 use crate::product::{ProductHistory, ProductSnapshot};
+use crate::run::RunSummary;
+use crate::schema::{migrate, Canonical};
+use crate::search::{IndexEntry, SearchIndex, SHARD_SIZE};
+use crate::stats::PriceStats;
 use crate::stores::{coles, woolies, Store};
 
 pub(crate) fn remove(source: &Path) -> anyhow::Result<()> {
@@ -27,6 +41,82 @@ pub(crate) fn get_snapshot_path(output_dir: &Path, store: Store, day: Date) -> P
     path
 }
 
+/// Ending of sync manifests, which are kept next to the snapshots so they travel with them
+pub(crate) const MANIFEST_SUFFIX: &str = ".manifest.json";
+
+pub(crate) fn get_manifest_path(output_dir: &Path, store: Store, day: Date) -> PathBuf {
+    output_dir
+        .join(store.to_string())
+        .join(format!("{day}{MANIFEST_SUFFIX}"))
+}
+
+/// Loads the sync manifest at `path`, if that sync was started
+pub(crate) fn load_manifest(path: &Path) -> anyhow::Result<Option<SyncManifest>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let fpath = path.to_string_lossy();
+    let file = File::open(path).with_context(|| format!("Failed to open manifest {fpath}"))?;
+    let manifest: SyncManifest = serde_json::from_reader(BufReader::new(file))
+        .with_context(|| format!("Failed to load manifest {fpath}"))?;
+    Ok(Some(manifest.with_path(path.to_path_buf())))
+}
+
+/// Loads the manifest of the latest sync of `store` before `day` that wrote a snapshot
+pub(crate) fn load_previous_manifest(
+    output_dir: &Path,
+    store: Store,
+    day: Date,
+) -> anyhow::Result<Option<SyncManifest>> {
+    let dir = output_dir.join(store.to_string());
+    if !dir.exists() {
+        return Ok(None);
+    }
+    let format = format_description!("[year]-[month]-[day]");
+    let mut days = Vec::new();
+    for entry in fs::read_dir(&dir)? {
+        let file_name = entry?.file_name();
+        let file_name = file_name.to_string_lossy();
+        let Some(basename) = file_name.strip_suffix(MANIFEST_SUFFIX) else {
+            continue;
+        };
+        match Date::parse(basename, &format) {
+            Ok(previous) if previous < day => days.push(previous),
+            _ => {}
+        }
+    }
+    days.sort();
+    for previous in days.into_iter().rev() {
+        let manifest = load_manifest(&get_manifest_path(output_dir, store, previous))?;
+        if let Some(manifest) = manifest.filter(|m| m.is_finished()) {
+            return Ok(Some(manifest));
+        }
+    }
+    Ok(None)
+}
+
+/// Stores whose snapshot of `day` was marked as partial during validation
+pub(crate) fn partial_stores(output_dir: &Path, day: Date) -> anyhow::Result<Vec<Store>> {
+    let mut stores = Vec::new();
+    for store in Store::iter() {
+        let manifest = load_manifest(&get_manifest_path(output_dir, store, day))?;
+        if manifest.is_some_and(|m| m.is_partial()) {
+            stores.push(store);
+        }
+    }
+    Ok(stores)
+}
+
+pub(crate) fn save_manifest(manifest: &SyncManifest) -> anyhow::Result<()> {
+    let path = manifest.path();
+    // Guaranteed to have a parent
+    create_dir_all(path.parent().unwrap())?;
+    let file = File::create(path)
+        .with_context(|| format!("Failed to create {}", path.to_string_lossy()))?;
+    serde_json::to_writer_pretty(BufWriter::new(file), manifest)?;
+    Ok(())
+}
+
 pub(crate) fn save_fetch_data(data: String, snapshot_path: &Path) -> anyhow::Result<()> {
     let snapshot_dir = snapshot_path.parent().with_context(|| {
         format!(
@@ -40,72 +130,457 @@ pub(crate) fn save_fetch_data(data: String, snapshot_path: &Path) -> anyhow::Res
             snapshot_dir.to_string_lossy()
         )
     })?;
-    let file = File::create(snapshot_path).with_context(|| {
+    write_atomic(snapshot_path, |file| {
+        let mut file = GzEncoder::new(file, Compression::default());
+        file.write_all(data.as_bytes())?;
+        file.finish()?;
+        Ok(())
+    })
+    .with_context(|| {
         format!(
             "Failed to save fetched data to {}",
             snapshot_path.to_string_lossy()
         )
-    })?;
-    let mut file = GzEncoder::new(file, Compression::default());
-    file.write_all(data.as_bytes()).with_context(|| {
-        format!(
-            "Failed to write all bytes of fetched data to {}",
-            snapshot_path.to_string_lossy()
-        )
-    })?;
+    })
+}
+
+/// Writes `path` through a temporary file next to it that is synced and renamed over it, so a
+/// crash or a full disk leaves either the previous or the new file but never a truncated one
+pub(crate) fn write_atomic<F>(path: &Path, write: F) -> anyhow::Result<()>
+where
+    F: FnOnce(&mut BufWriter<File>) -> anyhow::Result<()>,
+{
+    let fpath = path.to_string_lossy();
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let file_name = path
+        .file_name()
+        .with_context(|| format!("Path {fpath} has no file name"))?;
+    let tmp = dir.join(format!(".{}.tmp", file_name.to_string_lossy()));
+    let result = (|| -> anyhow::Result<()> {
+        let mut file = BufWriter::new(File::create(&tmp)?);
+        write(&mut file)?;
+        let file = file.into_inner()?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    })();
+    if result.is_err() {
+        // Best effort, the next write replaces it anyway
+        let _ = fs::remove_file(&tmp);
+    }
+    result.with_context(|| format!("Failed to write {fpath}"))?;
+    // The rename only survives a crash once the directory is synced as well
+    #[cfg(unix)]
+    File::open(dir)
+        .and_then(|dir| dir.sync_all())
+        .with_context(|| format!("Failed to sync directory of {fpath}"))?;
     Ok(())
 }
 
+pub(crate) fn get_history_path(output_dir: &Path) -> PathBuf {
+    output_dir.join("latest-canonical.json.gz")
+}
+
+/// Path of the `n`th most recent backup of the canonical history, starting at 1
+pub(crate) fn get_history_backup_path(output_dir: &Path, n: usize) -> PathBuf {
+    output_dir.join(format!("latest-canonical.json.gz.{n}"))
+}
+
 pub(crate) fn load_history(output_dir: &Path) -> anyhow::Result<Vec<ProductHistory>> {
-    let file = output_dir.join("latest-canonical.json.gz");
+    read_history(&get_history_path(output_dir))
+}
+
+/// Reads a canonical history file, which might also be one of its backups
+pub(crate) fn read_history(file: &Path) -> anyhow::Result<Vec<ProductHistory>> {
     let fpath = file.to_string_lossy();
-    let file = File::open(&file).with_context(|| format!("Failed to open history file {fpath}"))?;
+    let file = File::open(file).with_context(|| format!("Failed to open history file {fpath}"))?;
     let file = GzDecoder::new(file);
     let file = BufReader::new(file);
-    let products: Vec<ProductHistory> = serde_json::from_reader(file)
+    let history: Value = serde_json::from_reader(file)
         .with_context(|| format!("Failed to load history from {fpath}"))?;
+    let products = migrate(history).with_context(|| format!("Failed to read history {fpath}"))?;
     debug!("Loaded {} products from history", products.len());
     Ok(products)
 }
 
+/// Converts the snapshot of `store` on `day` without saving the failures or checking them
+/// against the conversion threshold, for looking at the data rather than analysing it
+pub(crate) fn read_daily_snapshot(
+    config: &Config,
+    store: Store,
+    day: Date,
+) -> anyhow::Result<Conversion> {
+    let file = get_snapshot_path(&config.output_dir, store, day);
+    debug!("Loading {}", file.to_string_lossy());
+    let file = File::open(&file).context(format!(
+        "Failed to open daily snapshot {}",
+        file.to_string_lossy()
+    ))?;
+    let file = GzDecoder::new(file);
+    let file = BufReader::new(file);
+    let filter = config.category_filter(store);
+    let conversion = match store {
+        Store::Coles => coles::load_snapshot(file, day, &filter)
+            .context("Failed to load coles data from snapshot")?,
+        Store::Woolies => woolies::load_snapshot(file, day, &filter)
+            .context("Failed to load woolies data from snapshot")?,
+    };
+    Ok(conversion)
+}
+
 pub(crate) fn load_daily_snapshot(
-    output_dir: &Path,
+    config: &Config,
     day: Date,
     store_filter: Option<Store>,
-) -> anyhow::Result<Vec<ProductSnapshot>> {
+) -> anyhow::Result<(Vec<ProductSnapshot>, Vec<ConversionMetrics>)> {
+    let output_dir = config.output_dir.as_path();
+    let thresholds = config.conversion_thresholds();
     let mut products = Vec::new();
+    let mut metrics = Vec::new();
     for store in Store::iter() {
         if store_filter.is_some_and(|s| s != store) {
             continue;
         }
-        let file = output_dir
-            .join(store.to_string())
-            .join(format!("{day}.json.gz"));
-        debug!("Loading {}", file.to_string_lossy());
-        let file = File::open(&file).context(format!(
-            "Failed to open daily snapshot {}",
-            file.to_string_lossy()
-        ))?;
-        let file = GzDecoder::new(file);
-        let file = BufReader::new(file);
-        let store_products = match store {
-            Store::Coles => coles::load_snapshot(file, day)
-                .context("Failed to load coles data from snapshot")?,
-            Store::Woolies => woolies::load_snapshot(file, day)
-                .context("Failed to load woolies data from snapshot")?,
-        };
+        let conversion = read_daily_snapshot(config, store, day)?;
+        save_conversion_failures(conversion.failures(), output_dir, store, day)?;
+        let store_metrics = conversion.metrics();
+        save_conversion_summary(&store_metrics, output_dir, store, day)?;
+        metrics.push(store_metrics);
+        let store_products = conversion
+            .into_products(thresholds.for_store(store))
+            .with_context(|| format!("Failed to convert {store} products for {day}"))?;
         products.extend(store_products);
     }
     debug!("Loaded {} products for date {:?}", products.len(), day);
-    Ok(products)
+    Ok((products, metrics))
+}
+
+pub(crate) fn get_conversion_failures_path(output_dir: &Path, store: Store, day: Date) -> PathBuf {
+    let mut path = PathBuf::from(output_dir);
+    path.push("conversion-failures");
+    path.push(store.to_string());
+    path.push(format!("{day}.jsonl"));
+    path
+}
+
+pub(crate) fn get_conversion_summary_path(output_dir: &Path, store: Store, day: Date) -> PathBuf {
+    get_conversion_failures_path(output_dir, store, day).with_extension("summary.json")
+}
+
+/// Writes success and failure counts of a conversion, with failures broken down by kind
+pub(crate) fn save_conversion_summary(
+    metrics: &ConversionMetrics,
+    output_dir: &Path,
+    store: Store,
+    day: Date,
+) -> anyhow::Result<()> {
+    let path = get_conversion_summary_path(output_dir, store, day);
+    // Guaranteed to have a parent
+    create_dir_all(path.parent().unwrap())?;
+    let file = File::create(&path)
+        .with_context(|| format!("Failed to create {}", path.to_string_lossy()))?;
+    serde_json::to_writer(BufWriter::new(file), metrics)?;
+    Ok(())
+}
+
+/// Writes one JSON line per failed product so parser rules can be fixed without re-running the
+/// scrape. The report is rewritten on every conversion, so an empty file means no failures.
+pub(crate) fn save_conversion_failures(
+    failures: &[ConversionFailure],
+    output_dir: &Path,
+    store: Store,
+    day: Date,
+) -> anyhow::Result<()> {
+    let path = get_conversion_failures_path(output_dir, store, day);
+    let fpath = path.to_string_lossy();
+    // Guaranteed to have a parent
+    create_dir_all(path.parent().unwrap())
+        .with_context(|| format!("Failed to create directory for {fpath}"))?;
+    let file = File::create(&path).with_context(|| format!("Failed to create {fpath}"))?;
+    let mut file = BufWriter::new(file);
+    for failure in failures {
+        serde_json::to_writer(&mut file, failure)?;
+        file.write_all(b"\n")?;
+    }
+    file.flush()
+        .with_context(|| format!("Failed to write conversion failures to {fpath}"))?;
+    if !failures.is_empty() {
+        info!("Wrote {} conversion failures to {fpath}", failures.len());
+    }
+    Ok(())
 }
 
-pub(crate) fn save_result(products: &Vec<ProductHistory>, output_dir: &Path) -> anyhow::Result<()> {
-    let file = output_dir.join("latest-canonical.json.gz");
+/// Loads manual match overrides from `match-overrides.json` in the output directory, if present
+pub(crate) fn load_match_overrides(output_dir: &Path) -> anyhow::Result<MatchOverrides> {
+    let file = output_dir.join("match-overrides.json");
+    if !file.exists() {
+        return Ok(MatchOverrides::default());
+    }
+    let fpath = file.to_string_lossy();
+    let file = File::open(&file).with_context(|| format!("Failed to open {fpath}"))?;
+    let overrides = serde_json::from_reader(BufReader::new(file))
+        .with_context(|| format!("Failed to load match overrides from {fpath}"))?;
+    Ok(overrides)
+}
+
+pub(crate) fn save_matches(groups: &[MatchGroup], output_dir: &Path) -> anyhow::Result<()> {
+    let file = output_dir.join("matches.json");
+    let file = File::create(file)?;
+    let file = BufWriter::new(file);
+    serde_json::to_writer(file, groups)?;
+    Ok(())
+}
+
+/// Writes the price changes of the current run next to the canonical history
+pub(crate) fn save_price_changes(
+    report: &PriceChangeReport,
+    output_dir: &Path,
+) -> anyhow::Result<()> {
+    let file = output_dir.join("price-changes.json");
+    let file = File::create(file)?;
+    let file = BufWriter::new(file);
+    serde_json::to_writer(file, report)?;
+    Ok(())
+}
+
+/// Writes price statistics of every product next to the canonical history
+pub(crate) fn save_price_stats(stats: &[PriceStats], output_dir: &Path) -> anyhow::Result<()> {
+    let file = output_dir.join("price-stats.json.gz");
     let file = File::create(file)?;
     let file = GzEncoder::new(file, Compression::default());
     let file = BufWriter::new(file);
-    serde_json::to_writer(file, products)?;
+    serde_json::to_writer(file, stats)?;
+    Ok(())
+}
+
+fn get_inflation_index_path(output_dir: &Path) -> PathBuf {
+    output_dir.join("inflation-index.json")
+}
+
+/// Loads the published inflation index, returning `None` if there is none yet
+pub(crate) fn load_inflation_index(output_dir: &Path) -> anyhow::Result<Option<InflationIndex>> {
+    let file = get_inflation_index_path(output_dir);
+    if !file.exists() {
+        return Ok(None);
+    }
+    let fpath = file.to_string_lossy();
+    let file = File::open(&file).with_context(|| format!("Failed to open {fpath}"))?;
+    let index = serde_json::from_reader(BufReader::new(file))
+        .with_context(|| format!("Failed to load inflation index from {fpath}"))?;
+    Ok(Some(index))
+}
+
+pub(crate) fn save_inflation_index(
+    index: &InflationIndex,
+    output_dir: &Path,
+) -> anyhow::Result<()> {
+    let file = get_inflation_index_path(output_dir);
+    let file = File::create(file)?;
+    let file = BufWriter::new(file);
+    serde_json::to_writer(file, index)?;
+    Ok(())
+}
+
+/// Saves the canonical history, keeping the previous `backups` versions of it
+pub(crate) fn save_result(
+    products: &[ProductHistory],
+    output_dir: &Path,
+    backups: usize,
+) -> anyhow::Result<()> {
+    rotate_history_backups(output_dir, backups)?;
+    write_atomic(&get_history_path(output_dir), |file| {
+        let mut file = GzEncoder::new(file, Compression::default());
+        serde_json::to_writer(&mut file, &Canonical::new(products))?;
+        file.finish()?;
+        Ok(())
+    })
+}
+
+/// Shifts the backups of the canonical history by one and makes the current history the most
+/// recent backup, dropping the oldest one beyond `backups`
+pub(crate) fn rotate_history_backups(output_dir: &Path, backups: usize) -> anyhow::Result<()> {
+    let history = get_history_path(output_dir);
+    if backups == 0 || !history.exists() {
+        return Ok(());
+    }
+    for n in (1..backups).rev() {
+        let backup = get_history_backup_path(output_dir, n);
+        if backup.exists() {
+            fs::rename(&backup, get_history_backup_path(output_dir, n + 1))?;
+        }
+    }
+    let latest = get_history_backup_path(output_dir, 1);
+    if latest.exists() {
+        fs::remove_file(&latest)?;
+    }
+    // A link keeps the current history in place until the new one is renamed over it
+    fs::hard_link(&history, &latest)
+        .or_else(|_| fs::copy(&history, &latest).map(|_| ()))
+        .with_context(|| format!("Failed to back up history to {}", latest.to_string_lossy()))?;
+    Ok(())
+}
+
+/// Modification time of the canonical history in milliseconds since the epoch, used to tell whether
+/// derived files are out of date
+pub(crate) fn history_modified(output_dir: &Path) -> anyhow::Result<u64> {
+    let file = get_history_path(output_dir);
+    let fpath = file.to_string_lossy();
+    let modified = fs::metadata(&file)
+        .and_then(|m| m.modified())
+        .with_context(|| format!("Failed to read modification time of {fpath}"))?;
+    let modified = modified
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .with_context(|| format!("Invalid modification time of {fpath}"))?;
+    Ok(modified.as_millis() as u64)
+}
+
+pub(crate) fn get_search_index_path(output_dir: &Path) -> PathBuf {
+    output_dir.join("search-index.json.gz")
+}
+
+/// Loads the search index, returning `None` if it hasn't been built yet
+pub(crate) fn load_search_index(output_dir: &Path) -> anyhow::Result<Option<SearchIndex>> {
+    let file = get_search_index_path(output_dir);
+    if !file.exists() {
+        return Ok(None);
+    }
+    let fpath = file.to_string_lossy();
+    let file = File::open(&file).with_context(|| format!("Failed to open {fpath}"))?;
+    let file = BufReader::new(GzDecoder::new(file));
+    let index = serde_json::from_reader(file)
+        .with_context(|| format!("Failed to load search index from {fpath}"))?;
+    Ok(Some(index))
+}
+
+fn get_search_entries_dir(output_dir: &Path) -> PathBuf {
+    output_dir.join("search-index")
+}
+
+fn get_search_entries_path(output_dir: &Path, shard: usize) -> PathBuf {
+    get_search_entries_dir(output_dir).join(format!("{shard}.json.gz"))
+}
+
+/// Saves the index along with its entries split into shards. The index is written last so it
+/// never refers to shards that aren't there yet.
+pub(crate) fn save_search_index(
+    index: &SearchIndex,
+    entries: &[IndexEntry],
+    output_dir: &Path,
+) -> anyhow::Result<()> {
+    let dir = get_search_entries_dir(output_dir);
+    create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.to_string_lossy()))?;
+    let mut shards = 0;
+    for (shard, chunk) in entries.chunks(SHARD_SIZE).enumerate() {
+        write_atomic(&get_search_entries_path(output_dir, shard), |file| {
+            let mut file = GzEncoder::new(file, Compression::default());
+            serde_json::to_writer(&mut file, chunk)?;
+            file.finish()?;
+            Ok(())
+        })?;
+        shards = shard + 1;
+    }
+    // Shards past the end are left over from a bigger catalogue
+    for entry in fs::read_dir(&dir)? {
+        let path = entry?.path();
+        let shard = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_suffix(".json.gz"))
+            .and_then(|shard| shard.parse::<usize>().ok());
+        if shard.is_some_and(|shard| shard >= shards) {
+            fs::remove_file(&path)
+                .with_context(|| format!("Failed to remove {}", path.to_string_lossy()))?;
+        }
+    }
+    write_atomic(&get_search_index_path(output_dir), |file| {
+        let mut file = GzEncoder::new(file, Compression::default());
+        serde_json::to_writer(&mut file, index)?;
+        file.finish()?;
+        Ok(())
+    })
+}
+
+fn load_search_shard(output_dir: &Path, shard: usize) -> anyhow::Result<Vec<IndexEntry>> {
+    let path = get_search_entries_path(output_dir, shard);
+    let fpath = path.to_string_lossy();
+    let file = File::open(&path).with_context(|| format!("Failed to open {fpath}"))?;
+    let file = BufReader::new(GzDecoder::new(file));
+    serde_json::from_reader(file)
+        .with_context(|| format!("Failed to load search entries from {fpath}"))
+}
+
+/// Loads the entries at `positions`, reading each shard that holds one of them once
+pub(crate) fn load_search_entries(
+    output_dir: &Path,
+    positions: &[usize],
+) -> anyhow::Result<Vec<IndexEntry>> {
+    let mut shards: HashMap<usize, Vec<IndexEntry>> = HashMap::new();
+    let mut entries = Vec::with_capacity(positions.len());
+    for &pos in positions {
+        let shard = pos / SHARD_SIZE;
+        let shard_entries = match shards.entry(shard) {
+            Entry::Occupied(loaded) => loaded.into_mut(),
+            Entry::Vacant(vacant) => vacant.insert(load_search_shard(output_dir, shard)?),
+        };
+        let entry = shard_entries
+            .get(pos % SHARD_SIZE)
+            .with_context(|| format!("Search index has no entry at {pos}, rebuild it"))?;
+        entries.push(entry.clone());
+    }
+    Ok(entries)
+}
+
+fn get_feed_events_path(output_dir: &Path) -> PathBuf {
+    output_dir.join("feeds").join("events.json")
+}
+
+/// Loads the events the feeds are built from, which are empty before the first run with feeds
+pub(crate) fn load_feed_events(output_dir: &Path) -> anyhow::Result<Vec<FeedEvent>> {
+    let file = get_feed_events_path(output_dir);
+    if !file.exists() {
+        return Ok(Vec::new());
+    }
+    let fpath = file.to_string_lossy();
+    let file = File::open(&file).with_context(|| format!("Failed to open {fpath}"))?;
+    serde_json::from_reader(BufReader::new(file))
+        .with_context(|| format!("Failed to load feed events from {fpath}"))
+}
+
+pub(crate) fn save_feed_events(events: &[FeedEvent], output_dir: &Path) -> anyhow::Result<()> {
+    let file = get_feed_events_path(output_dir);
+    create_dir_all(output_dir.join("feeds"))?;
+    let file = File::create(file)?;
+    let file = BufWriter::new(file);
+    serde_json::to_writer(file, events)?;
+    Ok(())
+}
+
+/// Writes the summary of a sync and analysis run to `runs/{day}.json`
+pub(crate) fn save_run_summary(
+    summary: &RunSummary,
+    day: Date,
+    output_dir: &Path,
+) -> anyhow::Result<()> {
+    let dir = output_dir.join("runs");
+    create_dir_all(&dir)?;
+    let file = File::create(dir.join(format!("{day}.json")))?;
+    let file = BufWriter::new(file);
+    serde_json::to_writer_pretty(file, summary)?;
+    Ok(())
+}
+
+/// Writes a feed to `path` below the feeds directory, e.g. "coles/drops.xml"
+pub(crate) fn save_feed(feed: &str, path: &str, output_dir: &Path) -> anyhow::Result<()> {
+    let file = output_dir.join("feeds").join(path);
+    if let Some(parent) = file.parent() {
+        create_dir_all(parent)?;
+    }
+    let mut file = BufWriter::new(File::create(file)?);
+    file.write_all(feed.as_bytes())?;
+    file.flush()?;
     Ok(())
 }
 
@@ -123,32 +598,117 @@ pub(crate) fn save_to_site(
         let file = data_dir.join(format!(
             "latest-canonical.{store}.compressed.json{filename_suffix}"
         ));
-        let file = File::create(file)?;
-        let file: Box<dyn Write> = if compress {
-            Box::new(GzEncoder::new(file, Compression::default()))
-        } else {
-            Box::new(file)
-        };
-        let file = BufWriter::new(file);
         let store_products: Vec<&ProductHistory> =
             products.iter().filter(|p| p.store() == store).collect();
-        serde_json::to_writer(file, &store_products)?;
+        write_atomic(&file, |file| {
+            if compress {
+                let mut file = GzEncoder::new(file, Compression::default());
+                serde_json::to_writer(&mut file, &store_products)?;
+                file.finish()?;
+            } else {
+                serde_json::to_writer(file, &store_products)?;
+            }
+            Ok(())
+        })?;
     }
     Ok(())
 }
 
 #[cfg(test)]
 mod test {
-    use std::fs::File;
+    use std::fs::{read_to_string, File};
+    use std::io::Write;
+    use std::path::Path;
 
     use tempfile::tempdir;
+    use time::{Date, Month};
 
-    use super::save_to_site;
+    use super::{
+        get_conversion_failures_path, get_conversion_summary_path, get_history_backup_path,
+        get_snapshot_path, load_history, read_daily_snapshot, read_history,
+        save_conversion_failures, save_fetch_data, save_result, save_to_site, write_atomic,
+    };
     use crate::{
+        config::Config,
         product::{ProductHistory, ProductInfo},
         stores::Store,
     };
 
+    #[test]
+    fn test_read_daily_snapshot_has_no_side_effects() {
+        let tmpdir = tempdir().unwrap();
+        let day = Date::from_calendar_date(2024, Month::January, 1).unwrap();
+        let config = Config {
+            output_dir: tmpdir.path().to_path_buf(),
+            ..Config::default()
+        };
+        // A bundle without products can't be converted, so every product fails
+        let snapshot = serde_json::json!([{
+            "NodeId": "1-E5BEE36E",
+            "Description": "Fruit & Veg",
+            "IsSpecial": false,
+            "Children": [],
+            "Products": [{"Products": []}],
+        }]);
+        let path = get_snapshot_path(tmpdir.path(), Store::Woolies, day);
+        save_fetch_data(snapshot.to_string(), &path).unwrap();
+
+        let conversion = read_daily_snapshot(&config, Store::Woolies, day).unwrap();
+        assert_eq!(conversion.metrics().failure(), 1);
+        assert!(!get_conversion_failures_path(tmpdir.path(), Store::Woolies, day).exists());
+        assert!(!get_conversion_summary_path(tmpdir.path(), Store::Woolies, day).exists());
+    }
+
+    #[test]
+    fn test_save_conversion_failures_empty() {
+        let tmpdir = tempdir().unwrap();
+        let day = Date::from_calendar_date(2024, Month::January, 1).unwrap();
+        save_conversion_failures(&[], tmpdir.path(), Store::Coles, day).unwrap();
+
+        let path = get_conversion_failures_path(tmpdir.path(), Store::Coles, day);
+        assert!(path.ends_with("conversion-failures/coles/2024-01-01.jsonl"));
+        assert_eq!(read_to_string(path).unwrap(), "");
+    }
+
+    #[test]
+    fn test_conversion_summary_path() {
+        let day = Date::from_calendar_date(2024, Month::January, 1).unwrap();
+        let path = get_conversion_summary_path(Path::new("output"), Store::Woolies, day);
+        assert_eq!(
+            path,
+            Path::new("output/conversion-failures/woolies/2024-01-01.summary.json")
+        );
+    }
+
+    #[test]
+    fn test_write_atomic_keeps_file_on_error() {
+        let tmpdir = tempdir().unwrap();
+        let path = tmpdir.path().join("file.json");
+        write_atomic(&path, |file| Ok(file.write_all(b"old")?)).unwrap();
+        let result = write_atomic(&path, |file| {
+            file.write_all(b"partial")?;
+            anyhow::bail!("disk full")
+        });
+        assert!(result.is_err());
+        assert_eq!(read_to_string(&path).unwrap(), "old");
+        assert!(!tmpdir.path().join(".file.json.tmp").exists());
+    }
+
+    #[test]
+    fn test_save_result_rotates_backups() {
+        let tmpdir = tempdir().unwrap();
+        let tmppath = tmpdir.path();
+        for count in 1..=4 {
+            let products: Vec<_> = (0..count).map(|_| ProductHistory::default()).collect();
+            save_result(&products, tmppath, 2).unwrap();
+        }
+        assert_eq!(load_history(tmppath).unwrap().len(), 4);
+        let backup = |n| read_history(&get_history_backup_path(tmppath, n)).unwrap();
+        assert_eq!(backup(1).len(), 3);
+        assert_eq!(backup(2).len(), 2);
+        assert!(!get_history_backup_path(tmppath, 3).exists());
+    }
+
     #[test]
     fn test_save_to_site_compressed() {
         let products = vec![ProductHistory::default()];
@@ -1,11 +1,26 @@
 use clap::ValueEnum;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::fmt::Display;
 use strum::EnumIter;
 pub mod coles;
 pub mod woolies;
 
-#[derive(ValueEnum, Clone, Debug, Serialize, Deserialize, Eq, Hash, PartialEq, Copy, EnumIter)]
+#[derive(
+    ValueEnum,
+    Clone,
+    Debug,
+    Serialize,
+    Deserialize,
+    JsonSchema,
+    Eq,
+    Hash,
+    PartialEq,
+    PartialOrd,
+    Ord,
+    Copy,
+    EnumIter,
+)]
 pub enum Store {
     #[serde(rename = "coles")]
     Coles,
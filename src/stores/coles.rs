@@ -5,7 +5,9 @@ mod product;
 pub(crate) use product::load_snapshot;
 
 use crate::cache::FsCache;
+use crate::config::ColesConfig;
 use crate::conversion::Category as CategoryTrait;
+use crate::manifest::{CategoryProgress, SyncManifest};
 use crate::stores::coles::category::Category;
 
 use anyhow::bail;
@@ -53,9 +55,12 @@ fn get_setup_data(client: &ColesHttpClient) -> anyhow::Result<(String, String)>
     Ok((api_key, version))
 }
 
-fn get_versioned_client(client: &ColesHttpClient) -> anyhow::Result<ColesHttpClient> {
+fn get_versioned_client(
+    client: &ColesHttpClient,
+    config: &ColesConfig,
+) -> anyhow::Result<ColesHttpClient> {
     let (api_key, version) = get_setup_data(client)?;
-    let client = ColesHttpClient::new_with_setup(&api_key, version)?;
+    let client = ColesHttpClient::new_with_setup(config.clone(), &api_key, version)?;
     Ok(client)
 }
 
@@ -65,30 +70,49 @@ fn get_categories(client: &ColesHttpClient) -> anyhow::Result<CategoriesResponse
     Ok(categories)
 }
 
-pub(crate) fn fetch(cache: &FsCache, quick: bool) -> anyhow::Result<String> {
+/// Scrapes all categories, returning them as JSON together with the number of products
+pub(crate) fn fetch(
+    cache: &FsCache,
+    quick: bool,
+    config: &ColesConfig,
+    manifest: &mut SyncManifest,
+) -> anyhow::Result<(String, usize)> {
     log::info!("Starting fetch for coles");
-    let client = ColesHttpClient::new()?;
-    let client = get_versioned_client(&client)?;
+    let client = ColesHttpClient::new(config.clone())?;
+    let client = get_versioned_client(&client, config)?;
     let categories = get_categories(&client)?;
+    let filter = config.category_filter();
     let mut categories: Vec<_> = categories
         .catalog_group_view
         .into_iter()
-        .filter(|c| !c.is_filtered())
+        .filter(|c| !c.is_filtered(&filter))
         .collect();
     debug!("Loaded categories for Coles, have {}", categories.len());
+    let mut total = 0;
     for category in categories.iter_mut() {
-        let product_count = category.fetch_products(&client, cache, quick)?;
+        let mut progress = CategoryProgress::default();
+        let result = category.fetch_products(&client, cache, quick, config, &mut progress);
+        if let Err(e) = &result {
+            progress.error = Some(format!("{e:#}"));
+        }
+        manifest.record(category.to_string(), progress)?;
+        let product_count = result?;
         debug!("Got category {} with {} products", category, product_count);
+        if config.fetch_barcodes {
+            category.fetch_barcodes(&client, cache);
+        }
+        total += product_count;
         if quick {
             break;
         }
     }
-    Ok(serde_json::to_string(&categories)?)
+    Ok((serde_json::to_string(&categories)?, total))
 }
 
 #[cfg(test)]
 mod test {
     use crate::cache::test::get_cache;
+    use crate::stores::Store;
 
     use super::*;
     use serde_json::json;
@@ -140,7 +164,7 @@ mod test {
     fn test_fetch() {
         // prepare mock client
         let new_with_setup_ctx = ColesHttpClient::new_with_setup_context();
-        new_with_setup_ctx.expect().returning(|_a, _v| {
+        new_with_setup_ctx.expect().returning(|_c, _a, _v| {
             let mut client = ColesHttpClient::default();
             client.expect_get_categories().times(1).returning(|| {
                 let json_data = json!({
@@ -168,7 +192,7 @@ mod test {
             Ok(client)
         });
         let new_ctx = ColesHttpClient::new_context();
-        new_ctx.expect().returning(|| {
+        new_ctx.expect().returning(|_| {
             let mut client = ColesHttpClient::default();
             client.expect_get_setup_data().times(1).returning(|| {
                 let response = r#"
@@ -189,7 +213,14 @@ mod test {
         });
 
         let cache = get_cache();
-        let categories = fetch(&cache, false).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let day = time::OffsetDateTime::now_utc().date();
+        let mut manifest = SyncManifest::new(dir.path().join("manifest.json"), Store::Coles, day);
+        let (categories, product_count) =
+            fetch(&cache, false, &ColesConfig::default(), &mut manifest).unwrap();
+        assert_eq!(product_count, 1);
+        assert_eq!(manifest.categories().len(), 1);
+        assert!(manifest.categories().values().all(|c| c.is_complete()));
         let categories: serde_json::Value = serde_json::from_str(&categories).unwrap();
         assert_eq!(
             categories,
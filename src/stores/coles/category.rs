@@ -3,16 +3,20 @@ use std::{collections::HashMap, fmt::Display};
 #[double]
 use super::http::ColesHttpClient;
 use super::product::SearchResult;
-use crate::{cache::FsCache, category::CategoryCode, conversion, errors::Error};
+use crate::{
+    cache::FsCache,
+    category::CategoryCode,
+    config::ColesConfig,
+    conversion,
+    conversion::{CategoryFilter, ProductResult},
+    errors::Error,
+    manifest::CategoryProgress,
+};
 use anyhow::Context;
-use log::{debug, error};
+use log::{debug, error, warn};
 use mockall_double::double;
 use serde::{Deserialize, Serialize};
-
-const SKIP_CATEGORIES: [&str; 2] = ["down-down", "back-to-school"];
-// How many errors are acceptable for a category before failing?
-// This means it's okay if *some* categories don't return 100% of products
-const ERROR_COUNT_MAX: i32 = 2;
+use serde_json::Value;
 
 #[derive(Deserialize, Serialize)]
 pub(crate) struct Category {
@@ -48,6 +52,8 @@ impl Category {
         client: &ColesHttpClient,
         cache: &FsCache,
         quick: bool,
+        config: &ColesConfig,
+        progress: &mut CategoryProgress,
     ) -> anyhow::Result<usize> {
         let mut products = Vec::new();
         let mut page = 1;
@@ -61,7 +67,8 @@ impl Category {
                         self.seo_token, page, &e
                     );
                     err_count += 1;
-                    if err_count > ERROR_COUNT_MAX {
+                    progress.failed_pages.push(page);
+                    if err_count > config.error_count_max {
                         return Err(e);
                     }
                     // Advance page without doing any of the other stuff, essentially skipping it
@@ -70,6 +77,8 @@ impl Category {
                 }
             };
             let new_products = category_response.results;
+            progress.pages += 1;
+            progress.expected_products = Some(category_response.no_of_results);
             page += 1;
             debug!(
                 "New page with results loaded. Product count: {}, products on this page: {}, expected total: {}",
@@ -84,24 +93,64 @@ impl Category {
             }
         }
         self.products = products;
+        progress.products = self.products.len();
         Ok(self.products.len())
     }
+
+    /// Adds the GTIN from the product details to every product of this category that has none
+    /// in its search result. Products whose details fail to load are kept without a barcode.
+    pub(crate) fn fetch_barcodes(&mut self, client: &ColesHttpClient, cache: &FsCache) -> usize {
+        let mut found = 0;
+        for product in self.products.iter_mut() {
+            let Some(product) = product.as_object_mut() else {
+                continue;
+            };
+            if product.get("_type").and_then(Value::as_str) != Some("PRODUCT")
+                || product.get("barcode").is_some_and(|b| !b.is_null())
+            {
+                continue;
+            }
+            let Some(id) = product.get("id").and_then(Value::as_i64) else {
+                continue;
+            };
+            match get_product_details(client, cache, id) {
+                Ok(details) => {
+                    if let Some(gtin) = details.gtin {
+                        product.insert(String::from("barcode"), Value::String(gtin));
+                        found += 1;
+                    }
+                }
+                Err(e) => warn!("Failed to load details of product {id}: {e:?}"),
+            }
+        }
+        debug!("Found {found} barcodes in category {}", self.seo_token);
+        found
+    }
+}
+
+fn get_product_details(
+    client: &ColesHttpClient,
+    cache: &FsCache,
+    id: i64,
+) -> anyhow::Result<ProductDetails> {
+    let path = format!("products/{id}.json");
+    let fetch = &|| client.get_product(id);
+    let resp = cache.get_or_fetch(path, fetch)?;
+    let json_data: ProductJson = serde_json::from_str(&resp)?;
+    Ok(json_data.page_props.product)
 }
 
 impl conversion::Category for Category {
     type Product = SearchResult;
-    fn is_filtered(&self) -> bool {
-        if SKIP_CATEGORIES.contains(&self.seo_token.as_str()) {
-            return true;
-        }
-        false
+    fn is_filtered(&self, filter: &CategoryFilter) -> bool {
+        filter.filters(&[&self.seo_token])
     }
 
-    fn into_products(self) -> anyhow::Result<Vec<SearchResult>> {
+    fn into_products(self) -> anyhow::Result<Vec<ProductResult<SearchResult>>> {
         self.products
             .into_iter()
             .filter_map(|v| match SearchResult::from_json_value(v) {
-                Ok(v) => Some(Ok(v)),
+                Ok(v) => Some(Ok(Ok(v))),
                 Err(err) => match err {
                     Error::AdResult => None,
                     _ => Some(
@@ -120,6 +169,23 @@ impl Display for Category {
     }
 }
 
+#[derive(Deserialize)]
+struct ProductDetails {
+    #[serde(default)]
+    gtin: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ProductProps {
+    product: ProductDetails,
+}
+
+#[derive(Deserialize)]
+struct ProductJson {
+    #[serde(rename = "pageProps")]
+    page_props: ProductProps,
+}
+
 #[derive(Deserialize)]
 struct SearchResults {
     #[serde(rename = "results")]
@@ -168,6 +234,7 @@ pub(crate) fn get_category_from_names(names: Vec<&str>) -> Option<CategoryCode>
 
 #[cfg(test)]
 mod test {
+    use crate::cache::test::get_cache;
     use crate::conversion::Category as CategoryTrait;
     use crate::stores::coles::get_categories;
 
@@ -229,4 +296,37 @@ mod test {
         let search_results = category.into_products().unwrap();
         assert!(search_results.is_empty());
     }
+
+    #[test]
+    fn test_fetch_barcodes() {
+        let mut client = ColesHttpClient::default();
+        client
+            .expect_get_product()
+            .withf(|id| *id == 1)
+            .times(1)
+            .returning(|_| {
+                Ok(
+                    json!({"pageProps": {"product": {"id": 1, "gtin": "9300633603601"}}})
+                        .to_string(),
+                )
+            });
+        let mut category = Category {
+            seo_token: String::from("slug"),
+            products: vec![
+                json!({"_type": "PRODUCT", "id": 1}),
+                json!({"_type": "PRODUCT", "id": 2, "barcode": "9300633603618"}),
+                json!({"_type": "SINGLE_TILE", "adId": "ad"}),
+            ],
+            extra: HashMap::new(),
+        };
+        let cache = get_cache();
+        assert_eq!(category.fetch_barcodes(&client, &cache), 1);
+        assert_eq!(category.products[0]["barcode"], "9300633603601");
+        assert_eq!(category.products[1]["barcode"], "9300633603618");
+
+        // Details are only requested once
+        category.products[0] = json!({"_type": "PRODUCT", "id": 1});
+        assert_eq!(category.fetch_barcodes(&client, &cache), 1);
+        assert_eq!(cache.cached(), 1);
+    }
 }
@@ -5,35 +5,41 @@ use cookie_store::CookieStore;
 #[cfg(test)]
 use mockall::automock;
 
+use crate::config::ColesConfig;
 use crate::retry::RetryPolicy;
 
-const BASE_URL: &str = "https://www.coles.com.au";
-const USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/87.0.4280.88 Safari/537.36";
-const STORE_ID: &str = "0584";
-
 pub(crate) struct ColesHttpClient {
     client: ureq::Agent,
     version: Option<String>,
     api_key: Option<String>,
     retry_policy: RetryPolicy,
+    config: ColesConfig,
 }
 
 #[cfg_attr(test, automock)]
 #[allow(dead_code)]
 impl ColesHttpClient {
-    pub(crate) fn new() -> anyhow::Result<Self> {
-        Self::new_client(None, None)
+    pub(crate) fn new(config: ColesConfig) -> anyhow::Result<Self> {
+        Self::new_client(config, None, None)
     }
 
-    pub(crate) fn new_with_setup(api_key: &str, version: String) -> anyhow::Result<Self> {
-        Self::new_client(Some(String::from(api_key)), Some(version))
+    pub(crate) fn new_with_setup(
+        config: ColesConfig,
+        api_key: &str,
+        version: String,
+    ) -> anyhow::Result<Self> {
+        Self::new_client(config, Some(String::from(api_key)), Some(version))
     }
 
-    fn new_client(api_key: Option<String>, version: Option<String>) -> anyhow::Result<Self> {
+    fn new_client(
+        config: ColesConfig,
+        api_key: Option<String>,
+        version: Option<String>,
+    ) -> anyhow::Result<Self> {
         let cookie_store = CookieStore::new(None);
         let client = ureq::builder()
             .cookie_store(cookie_store)
-            .user_agent(USER_AGENT)
+            .user_agent(&config.user_agent)
             .timeout(Duration::from_secs(30))
             .build();
         Ok(ColesHttpClient {
@@ -41,6 +47,7 @@ impl ColesHttpClient {
             version,
             api_key,
             retry_policy: RetryPolicy::default(),
+            config,
         })
     }
 
@@ -50,8 +57,8 @@ impl ColesHttpClient {
             let request = self
                 .client
                 .get(url)
-                .set("Origin", BASE_URL)
-                .set("Referer", BASE_URL);
+                .set("Origin", &self.config.base_url)
+                .set("Referer", &self.config.base_url);
             let request = match &self.api_key {
                 Some(api_key) => request.set("ocp-apim-subscription-key", api_key),
                 None => request,
@@ -63,11 +70,14 @@ impl ColesHttpClient {
     }
 
     pub(crate) fn get_setup_data(&self) -> anyhow::Result<String> {
-        self.get(BASE_URL)
+        self.get(&self.config.base_url)
     }
 
     pub(crate) fn get_categories(&self) -> anyhow::Result<String> {
-        let cat_url = format!("{BASE_URL}/api/bff/products/categories?storeId={STORE_ID}");
+        let cat_url = format!(
+            "{}/api/bff/products/categories?storeId={}",
+            self.config.base_url, self.config.store_id
+        );
         self.get(&cat_url)
     }
 
@@ -77,7 +87,20 @@ impl ColesHttpClient {
             .as_ref()
             .ok_or_else(|| anyhow!("Must set version"))?;
         let url = format!(
-            "{BASE_URL}/_next/data/{version}/en/browse/{slug}.json?page={page}&slug={slug}"
+            "{}/_next/data/{version}/en/browse/{slug}.json?page={page}&slug={slug}",
+            self.config.base_url
+        );
+        self.get(&url)
+    }
+
+    pub(crate) fn get_product(&self, id: i64) -> anyhow::Result<String> {
+        let version = &self
+            .version
+            .as_ref()
+            .ok_or_else(|| anyhow!("Must set version"))?;
+        let url = format!(
+            "{}/_next/data/{version}/en/product/{id}.json?slug={id}",
+            self.config.base_url
         );
         self.get(&url)
     }
@@ -89,13 +112,13 @@ mod test {
 
     #[test]
     fn new_unconfigured_fails_get_category() {
-        let client = ColesHttpClient::new().unwrap();
+        let client = ColesHttpClient::new(ColesConfig::default()).unwrap();
         let res = client.get_category("", 0).unwrap_err();
         assert_eq!(res.to_string(), "Must set version");
     }
 
     #[test]
     fn new_with_setup() {
-        ColesHttpClient::new_with_setup("", String::new()).unwrap();
+        ColesHttpClient::new_with_setup(ColesConfig::default(), "", String::new()).unwrap();
     }
 }
@@ -1,6 +1,6 @@
 use crate::category::CategoryCode;
-use crate::conversion::{self, Product};
-use crate::errors::{Error, Result};
+use crate::conversion::{self, CategoryFilter, Conversion, Product};
+use crate::errors::{ConversionError, Error, Result};
 use crate::product::{price_serde, Price};
 use crate::product::{ProductInfo, ProductSnapshot};
 use crate::stores::coles::category::Category;
@@ -26,6 +26,9 @@ struct PricingUnit {
 struct Pricing {
     #[serde(with = "price_serde")]
     now: Price,
+    // Regular price while on special, zero otherwise
+    #[serde(default, with = "price_serde::option")]
+    was: Option<Price>,
     unit: PricingUnit,
 }
 
@@ -41,9 +44,17 @@ pub(crate) struct SearchResult {
     brand: String,
     description: String,
     size: String,
+    // Coles usually only has the GTIN in the product details, which are added to the search
+    // result when the sync fetches barcodes
+    #[serde(default)]
+    barcode: Option<String>,
     pricing: Option<Pricing>,
     #[serde(rename = "onlineHeirs")]
     online_heirs: Option<Vec<OnlineHeir>>,
+
+    // Original JSON of this result, kept for reporting failed conversions
+    #[serde(skip)]
+    raw: serde_json::Value,
 }
 
 impl SearchResult {
@@ -69,7 +80,8 @@ impl SearchResult {
             return Err(Error::AdResult);
         }
 
-        let search_result: SearchResult = serde_json::from_value(value)?;
+        let mut search_result = SearchResult::deserialize(&value)?;
+        search_result.raw = value;
 
         Ok(search_result)
     }
@@ -91,14 +103,17 @@ impl Product for SearchResult {
         Store::Coles
     }
 
+    fn take_raw(&mut self) -> serde_json::Value {
+        std::mem::take(&mut self.raw)
+    }
+
     fn try_into_snapshot_and_date(self, date: Date) -> Result<ProductSnapshot> {
-        let pricing = self.pricing.as_ref().ok_or(Error::ProductConversion(
-            "missing field pricing".to_string(),
-        ))?;
+        let pricing = self.pricing.as_ref().ok_or(ConversionError::MissingPrice)?;
         let mut name = self.name.clone();
         if !self.brand.is_empty() {
             name = format!("{} {}", self.brand, name);
         }
+        let brand = Some(self.brand.clone());
 
         let category = self.category()?;
 
@@ -107,28 +122,38 @@ impl Product for SearchResult {
             self.id,
             name,
             self.description,
+            brand,
+            self.barcode,
             pricing.unit.is_weighted,
             unit,
             quantity,
             Store::Coles,
             category,
         );
-        Ok(ProductSnapshot::new(product_info, pricing.now, date))
+        Ok(ProductSnapshot::new(
+            product_info,
+            pricing.now,
+            pricing.was,
+            date,
+        ))
     }
 }
 
 fn get_quantity_and_unit(item: &SearchResult) -> Result<(f64, Unit)> {
     let size = &item.size;
     if size.is_empty() {
-        return Err(Error::ProductConversion(String::from("empty field size")));
+        return Err(ConversionError::UnparseableSize { raw: size.clone() }.into());
     }
     let (parsed_quantity, unit) = parse_str_unit(size)?;
     Ok((parsed_quantity, unit))
 }
 
-pub(crate) fn load_snapshot(file: impl Read, date: Date) -> anyhow::Result<Vec<ProductSnapshot>> {
-    let success = conversion::from_reader::<Category>(file, date)?;
-    Ok(success)
+pub(crate) fn load_snapshot(
+    file: impl Read,
+    date: Date,
+    filter: &CategoryFilter,
+) -> anyhow::Result<Conversion> {
+    conversion::from_reader::<Category>(file, date, filter)
 }
 
 #[cfg(test)]
@@ -182,6 +207,25 @@ mod test {
         assert!(!unit.is_weighted.unwrap());
     }
 
+    #[test]
+    fn test_take_raw() {
+        let json_data = json!(
+            {
+              "_type": "PRODUCT",
+              "id": 42,
+              "adId": null,
+              "name": "Product name",
+              "brand": "Brand name",
+              "description": "BRAND NAME PRODUCT NAME 150G",
+              "size": "150g",
+              "pricing": null,
+            }
+        );
+        let mut product = SearchResult::from_json_value(json_data.clone())
+            .expect("Returned error instead of result");
+        assert_eq!(product.take_raw(), json_data);
+    }
+
     #[test]
     fn test_load_ad() {
         let ad = json!(
@@ -262,7 +306,7 @@ mod test {
         let date = Date::from_calendar_date(2024, Month::January, 1).unwrap();
         let err = product.try_into_snapshot_and_date(date).unwrap_err();
         match err {
-            Error::ProductConversion(msg) => assert_eq!(msg, "missing field pricing"),
+            Error::ProductConversion(err) => assert_eq!(err, ConversionError::MissingPrice),
             _ => panic!("unexpected type err type"),
         }
     }
@@ -1,5 +1,7 @@
 use crate::cache::FsCache;
+use crate::config::WooliesConfig;
 use crate::conversion::Category as CategoryTrait;
+use crate::manifest::{CategoryProgress, SyncManifest};
 #[double]
 use crate::stores::woolies::http::WooliesHttpClient;
 use log::{debug, info};
@@ -26,24 +28,39 @@ fn get_categories(client: &WooliesHttpClient) -> anyhow::Result<CategoriesRespon
     Ok(categories)
 }
 
-pub(crate) fn fetch(cache: &FsCache, quick: bool) -> anyhow::Result<String> {
+/// Scrapes all categories, returning them as JSON together with the number of products
+pub(crate) fn fetch(
+    cache: &FsCache,
+    quick: bool,
+    config: &WooliesConfig,
+    manifest: &mut SyncManifest,
+) -> anyhow::Result<(String, usize)> {
     info!("Starting fetch for woolies");
-    let client = WooliesHttpClient::new();
+    let client = WooliesHttpClient::new(config.clone());
     let categories = get_categories(&client)?;
+    let filter = config.category_filter();
     let mut categories: Vec<_> = categories
         .categories
         .into_iter()
-        .filter(|c| !c.is_filtered())
+        .filter(|c| !c.is_filtered(&filter))
         .collect();
     debug!("Loaded categories for Woolies, have {}", categories.len());
+    let mut total = 0;
     for category in categories.iter_mut() {
-        let product_count = category.fetch_products(&client, cache, quick)?;
+        let mut progress = CategoryProgress::default();
+        let result = category.fetch_products(&client, cache, quick, &mut progress);
+        if let Err(e) = &result {
+            progress.error = Some(format!("{e:#}"));
+        }
+        manifest.record(category.to_string(), progress)?;
+        let product_count = result?;
         debug!("Got category {} with {} products", category, product_count);
+        total += product_count;
         if quick {
             break;
         }
     }
-    Ok(serde_json::to_string(&categories)?)
+    Ok((serde_json::to_string(&categories)?, total))
 }
 
 #[cfg(test)]
@@ -51,6 +68,7 @@ mod test {
     use serde_json::json;
 
     use crate::cache::test::get_cache;
+    use crate::stores::Store;
 
     use super::*;
 
@@ -81,7 +99,7 @@ mod test {
     fn test_fetch() {
         // prepare mock client
         let new_ctx = WooliesHttpClient::new_context();
-        new_ctx.expect().returning(|| {
+        new_ctx.expect().returning(|_| {
             let mut client = WooliesHttpClient::default();
             client.expect_get_categories().times(1).returning(|| {
                 let json_data = json!({
@@ -109,7 +127,14 @@ mod test {
         });
 
         let cache = get_cache();
-        let categories = fetch(&cache, false).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let day = time::OffsetDateTime::now_utc().date();
+        let mut manifest = SyncManifest::new(dir.path().join("manifest.json"), Store::Woolies, day);
+        let (categories, product_count) =
+            fetch(&cache, false, &WooliesConfig::default(), &mut manifest).unwrap();
+        assert_eq!(product_count, 1);
+        assert_eq!(manifest.categories().len(), 1);
+        assert!(manifest.categories().values().all(|c| c.is_complete()));
         let categories: serde_json::Value = serde_json::from_str(&categories).unwrap();
         assert_eq!(
             categories,
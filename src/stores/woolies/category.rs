@@ -5,8 +5,9 @@ use crate::cache::FsCache;
 use crate::category::CategoryCode;
 use crate::category::FruitAndVeg;
 use crate::conversion;
-use crate::errors::Result;
-use anyhow::bail;
+use crate::conversion::{CategoryFilter, ConversionFailure, ProductResult};
+use crate::errors::{ConversionError, Error, Result};
+use crate::manifest::CategoryProgress;
 use anyhow::Context;
 use log::debug;
 use mockall_double::double;
@@ -14,15 +15,6 @@ use serde::{Deserialize, Serialize};
 use std::rc::Rc;
 use std::{collections::HashMap, fmt::Display};
 
-const IGNORED_CATEGORY_IDS: [&str; 2] = [
-    "specialsgroup", // Ads
-    "1_8E4DA6F",     // Beer, Wine & Spirits
-];
-const IGNORED_CATEGORY_DESCRIPTIONS: [&str; 2] = [
-    "Front of Store",       // Expect duplicates
-    "Beer, Wine & Spirits", // skip alcohol because it has weird sizing and isn't that important
-];
-
 #[derive(Deserialize, Serialize, Debug, Default)]
 pub(crate) struct CategoryInfo {
     #[serde(rename = "NodeId")]
@@ -77,6 +69,7 @@ impl Category {
         client: &WooliesHttpClient,
         cache: &FsCache,
         quick: bool,
+        progress: &mut CategoryProgress,
     ) -> anyhow::Result<usize> {
         let mut products = Vec::new();
         let mut page = 1;
@@ -84,6 +77,8 @@ impl Category {
             let category_response = self.get_category(client, cache, page)?;
             let new_products = category_response.bundles;
             let new_product_count = new_products.len();
+            progress.pages += 1;
+            progress.expected_products = Some(category_response.total_record_count);
             page += 1;
             debug!(
                 "New page with results loaded. Product count: {}, products on this page: {}, expected total: {}",
@@ -101,6 +96,7 @@ impl Category {
             }
         }
         self.products = products;
+        progress.products = self.products.len();
         Ok(self.products.len())
     }
 
@@ -123,7 +119,17 @@ impl Category {
 
                 _ => return Ok(None),
             },
-            None => return Ok(None),
+            None => {
+                // Products are listed in one of the subcategories, unless the category has none.
+                // The product is still kept, just without a category.
+                if self.children.iter().any(|c| !c.category_info.is_special) {
+                    let error = ConversionError::MissingCategory {
+                        names: subcategory_names,
+                    };
+                    debug!("No category in {}: {error}", self.category_info.node_id);
+                }
+                return Ok(None);
+            }
         };
 
         Ok(Some(CategoryCode { category }))
@@ -132,32 +138,28 @@ impl Category {
 
 impl conversion::Category for Category {
     type Product = BundleProduct;
-    fn is_filtered(&self) -> bool {
-        if IGNORED_CATEGORY_IDS.contains(&self.category_info.node_id.as_str()) {
-            return true;
-        }
-
-        if IGNORED_CATEGORY_DESCRIPTIONS.contains(&self.category_info.description.as_str()) {
-            return true;
-        }
-
-        false
+    fn is_filtered(&self, filter: &CategoryFilter) -> bool {
+        filter.filters(&[&self.category_info.node_id, &self.category_info.description])
     }
 
-    fn into_products(mut self) -> anyhow::Result<Vec<BundleProduct>> {
+    fn into_products(mut self) -> anyhow::Result<Vec<ProductResult<BundleProduct>>> {
         let products: Vec<_> = self.products.drain(..).collect();
         let category = Rc::new(self);
 
         products
             .into_iter()
-            .map(|v| match serde_json::from_value::<Bundle>(v) {
-                Ok(v) => match v.products.len() {
+            .map(|v| match Bundle::deserialize(&v) {
+                Ok(bundle) => match bundle.products.len() {
                     1 => {
-                        let mut product = v.products.into_iter().next().unwrap();
+                        let mut product = bundle.products.into_iter().next().unwrap();
                         product.set_category(Rc::clone(&category));
-                        Ok(product)
+                        product.set_raw(v);
+                        Ok(Ok(product))
+                    }
+                    n => {
+                        let error = Error::from(ConversionError::BundleSize { n });
+                        Ok(Err(ConversionFailure::new(&error, v)))
                     }
-                    _ => bail!("Invalid number of products in bundle: {}", v.products.len()),
                 },
                 Err(err) => {
                     Err(err).context("Failed to convert product to BundleProduct from JSON")
@@ -184,6 +186,7 @@ pub(crate) struct CategoryResponse {
 #[cfg(test)]
 mod test {
     use crate::cache::test::get_cache;
+    use crate::config::WooliesConfig;
     use crate::conversion::Category as CategoryTrait;
     use crate::conversion::Product as ProductTrait;
     use crate::stores::woolies::get_categories;
@@ -232,7 +235,9 @@ mod test {
         });
         let cache = get_cache();
         let mut category = Category::default();
-        category.fetch_products(&client, &cache, false).unwrap();
+        category
+            .fetch_products(&client, &cache, false, &mut CategoryProgress::default())
+            .unwrap();
         assert_eq!(category.products.len(), 2);
     }
 
@@ -249,7 +254,9 @@ mod test {
         });
         let cache = get_cache();
         let mut category = Category::default();
-        category.fetch_products(&client, &cache, false).unwrap();
+        category
+            .fetch_products(&client, &cache, false, &mut CategoryProgress::default())
+            .unwrap();
         assert_eq!(category.products.len(), 4);
     }
 
@@ -266,7 +273,9 @@ mod test {
         });
         let cache = get_cache();
         let mut category = Category::default();
-        category.fetch_products(&client, &cache, false).unwrap();
+        category
+            .fetch_products(&client, &cache, false, &mut CategoryProgress::default())
+            .unwrap();
         assert_eq!(category.products.len(), 0);
     }
 
@@ -274,12 +283,22 @@ mod test {
     fn test_is_filtered() {
         let category = Category {
             category_info: CategoryInfo {
-                description: String::from(IGNORED_CATEGORY_DESCRIPTIONS[0]),
+                description: String::from("Front of Store"),
                 ..Default::default()
             },
             ..Default::default()
         };
-        assert!(category.is_filtered());
+        assert!(category.is_filtered(&WooliesConfig::default().category_filter()));
+        assert!(!category.is_filtered(&CategoryFilter::default()));
+        // Naming a category explicitly scrapes it even though it's excluded by default
+        let mut config = WooliesConfig::default();
+        config
+            .include_categories
+            .push(String::from("Front of Store"));
+        assert!(!category.is_filtered(&config.category_filter()));
+        config.include_categories = Vec::new();
+        config.only_categories = vec![String::from("Fruit & Veg")];
+        assert!(category.is_filtered(&config.category_filter()));
     }
 
     #[test]
@@ -325,7 +344,7 @@ mod test {
         let category: Category = serde_json::from_value(json_data).unwrap();
         let mut products = category.into_products().unwrap();
         assert_eq!(products.len(), 1);
-        let product = products.pop().unwrap();
+        let product = products.pop().unwrap().unwrap();
         let date = Date::from_calendar_date(2024, Month::January, 1).unwrap();
         let product = product
             .try_into_snapshot_and_date(date)
@@ -379,7 +398,7 @@ mod test {
         let category: Category = serde_json::from_value(json_data).unwrap();
         let mut products = category.into_products().unwrap();
         assert_eq!(products.len(), 1);
-        let product = products.pop().unwrap();
+        let product = products.pop().unwrap().unwrap();
         let date = Date::from_calendar_date(2024, Month::January, 1).unwrap();
         let product = product
             .try_into_snapshot_and_date(date)
@@ -433,7 +452,7 @@ mod test {
         let category: Category = serde_json::from_value(json_data).unwrap();
         let mut products = category.into_products().unwrap();
         assert_eq!(products.len(), 1);
-        let product = products.pop().unwrap();
+        let product = products.pop().unwrap().unwrap();
         let date = Date::from_calendar_date(2024, Month::January, 1).unwrap();
         let product = product
             .try_into_snapshot_and_date(date)
@@ -487,7 +506,7 @@ mod test {
         let category: Category = serde_json::from_value(json_data).unwrap();
         let mut products = category.into_products().unwrap();
         assert_eq!(products.len(), 1);
-        let product = products.pop().unwrap();
+        let product = products.pop().unwrap().unwrap();
         let date = Date::from_calendar_date(2024, Month::January, 1).unwrap();
         let product = product
             .try_into_snapshot_and_date(date)
@@ -497,4 +516,45 @@ mod test {
             crate::category::Category::FruitAndVeg(FruitAndVeg::Fruit)
         );
     }
+
+    #[test]
+    fn test_bundle_size_is_a_product_failure() {
+        let json_data = json!(
+            {
+              "NodeId": "1-E5BEE36E",
+              "Description": "Fruit & Veg",
+              "IsSpecial": false,
+              "Children": [],
+              "Products": [{"Products": []}]
+            }
+        );
+        let category: Category = serde_json::from_value(json_data).unwrap();
+        let mut products = category.into_products().unwrap();
+        assert_eq!(products.len(), 1);
+        let failure = products.pop().unwrap().unwrap_err();
+        let failure = serde_json::to_value(failure).unwrap();
+        assert_eq!(failure["kind"], "bundle_size");
+        assert_eq!(failure["product"], json!({"Products": []}));
+    }
+
+    #[test]
+    fn test_missing_category() {
+        let json_data = json!(
+            {
+              "NodeId": "1-E5BEE36E",
+              "Description": "Fruit & Veg",
+              "IsSpecial": false,
+              "Children": [{
+                  "NodeId": "1-5931EE89",
+                  "Description": "Fruit",
+                  "IsSpecial": false,
+              }],
+            }
+        );
+        let category: Category = serde_json::from_value(json_data).unwrap();
+        // Products that aren't in any of the subcategories are kept without a category
+        let names = vec![String::from("Bakery")];
+        assert!(category.code(names.clone()).unwrap().is_none());
+        assert!(Category::default().code(names).unwrap().is_none());
+    }
 }
@@ -4,68 +4,78 @@ use cookie_store::CookieStore;
 #[cfg(test)]
 use mockall::automock;
 
+use crate::config::WooliesConfig;
 use crate::retry::RetryPolicy;
 
-const BASE_URL: &str = "https://www.woolworths.com.au";
-const REFERER: &str = "https://www.woolworths.com.au/shop/browse/fruit-veg";
-const USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/87.0.4280.88 Safari/537.36";
+const REFERER_PATH: &str = "/shop/browse/fruit-veg";
 
 pub(crate) struct WooliesHttpClient {
     client: ureq::Agent,
     retry_policy: RetryPolicy,
+    config: WooliesConfig,
 }
 
 #[cfg_attr(test, automock)]
 #[allow(dead_code)]
 impl WooliesHttpClient {
-    pub(crate) fn new() -> Self {
+    pub(crate) fn new(config: WooliesConfig) -> Self {
         let cookie_store = CookieStore::new(None);
         let client = ureq::builder()
             .cookie_store(cookie_store)
-            .user_agent(USER_AGENT)
+            .user_agent(&config.user_agent)
             .timeout(Duration::from_secs(30))
             .build();
         WooliesHttpClient {
             client,
             retry_policy: RetryPolicy::default(),
+            config,
         }
     }
 
     pub(crate) fn start(&self) -> anyhow::Result<()> {
-        self.get(BASE_URL)?;
+        self.get(&self.config.base_url)?;
         Ok(())
     }
 
+    fn referer(&self) -> String {
+        format!("{}{REFERER_PATH}", self.config.base_url)
+    }
+
     fn get(&self, url: &str) -> anyhow::Result<String> {
         log::info!("Loading url '{url}'");
+        let referer = self.referer();
         let response = self.retry_policy.retry(|| {
             let request = self
                 .client
                 .get(url)
-                .set("Origin", BASE_URL)
-                .set("Referer", REFERER);
+                .set("Origin", &self.config.base_url)
+                .set("Referer", &referer);
             request.call()
         })?;
         Ok(response.into_string()?)
     }
 
     pub(crate) fn get_categories(&self) -> anyhow::Result<String> {
-        let cat_url = format!("{BASE_URL}/apis/ui/PiesCategoriesWithSpecials");
+        let cat_url = format!(
+            "{}/apis/ui/PiesCategoriesWithSpecials",
+            self.config.base_url
+        );
         self.get(&cat_url)
     }
 
     pub(crate) fn get_category(&self, id: &str, page: i32) -> anyhow::Result<String> {
-        let url = format!("{BASE_URL}/apis/ui/browse/category");
+        let url = format!("{}/apis/ui/browse/category", self.config.base_url);
         log::info!("Loading url '{url}' with page {page} and category id {id}");
+        let referer = self.referer();
         let response = self.retry_policy.retry(|| {
             self.client
                 .post(&url)
-                .set("Origin", BASE_URL)
-                .set("Referer", REFERER)
+                .set("Origin", &self.config.base_url)
+                .set("Referer", &referer)
                 .send_json(ureq::json!({
                     "categoryId": id,
                     "pageNumber": page,
-                    "pageSize": 36,
+                    "pageSize": self.config.page_size,
                     "sortType": "Name",
                     "url": "/shop/browse/fruit-veg",
                     "location": "/shop/browse/fruit-veg",
@@ -102,6 +112,6 @@ mod test {
 
     #[test]
     fn test_new() {
-        WooliesHttpClient::new();
+        WooliesHttpClient::new(WooliesConfig::default());
     }
 }
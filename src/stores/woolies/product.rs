@@ -1,12 +1,11 @@
-use anyhow::{anyhow, Context};
 use log::{debug, warn};
 use serde::Deserialize;
 use std::io::Read;
 use std::rc::Rc;
 use time::Date;
 
-use crate::conversion::{self, Product};
-use crate::errors::{Error, Result};
+use crate::conversion::{self, CategoryFilter, Conversion, Product};
+use crate::errors::{ConversionError, Result};
 use crate::product::{Price, ProductInfo, ProductSnapshot};
 use crate::stores::Store;
 use crate::unit::{parse_str_unit, Unit};
@@ -20,12 +19,13 @@ struct AdditionalAttributes {
 }
 
 impl AdditionalAttributes {
-    pub(crate) fn subcategory_names(&self) -> Result<Vec<String>> {
+    pub(crate) fn subcategory_names(&self) -> std::result::Result<Vec<String>, ConversionError> {
         let mut categories = Vec::new();
-        categories.extend(serde_json::from_str::<Vec<_>>(
-            &self.piessubcategorynamesjson,
-        )?);
-        categories.extend(serde_json::from_str::<Vec<_>>(&self.piescategorynamesjson)?);
+        for raw in [&self.piessubcategorynamesjson, &self.piescategorynamesjson] {
+            let names = serde_json::from_str::<Vec<_>>(raw)
+                .map_err(|_| ConversionError::UnparseableCategories { raw: raw.clone() })?;
+            categories.extend(names);
+        }
 
         Ok(categories)
     }
@@ -39,6 +39,10 @@ pub(crate) struct BundleProduct {
     name: String,
     #[serde(rename = "Description")]
     description: String,
+    #[serde(rename = "Brand", default)]
+    brand: Option<String>,
+    #[serde(rename = "Barcode", default)]
+    barcode: Option<String>,
     #[serde(rename = "Price")]
     price: Option<f64>,
     #[serde(rename = "WasPrice")]
@@ -59,6 +63,10 @@ pub(crate) struct BundleProduct {
 
     #[serde(skip)]
     category: Rc<Category>,
+
+    // Original JSON of the bundle, kept for reporting failed conversions
+    #[serde(skip)]
+    raw: serde_json::Value,
 }
 
 impl BundleProduct {
@@ -81,24 +89,20 @@ impl BundleProduct {
                 }
             },
             None => {
-                return Err(anyhow!("Missing CupMeasure, ran out of options to convert").into());
+                return Err(ConversionError::MissingCupMeasure.into());
             }
         };
 
         let cup_price = match self.cup_price {
             Some(v) => v,
             None => {
-                return Err(Error::ProductConversion(String::from(
-                    "Missing cup price, unable to calculate quantity",
-                )));
+                return Err(ConversionError::MissingCupPrice.into());
             }
         };
         let quantity = (price / cup_price * std_quantity).round();
         if quantity < 10.0 {
             warn!("Low quantity of {quantity} during conversion of {self:?}");
-            return Err(Error::ProductConversion(String::from(
-                "Low quantity for conversion",
-            )));
+            return Err(ConversionError::LowQuantity { quantity }.into());
         }
         Ok((quantity, unit))
     }
@@ -106,6 +110,10 @@ impl BundleProduct {
     pub(crate) fn set_category(&mut self, category: Rc<Category>) {
         self.category = category;
     }
+
+    pub(crate) fn set_raw(&mut self, raw: serde_json::Value) {
+        self.raw = raw;
+    }
 }
 
 impl Product for BundleProduct {
@@ -113,6 +121,10 @@ impl Product for BundleProduct {
         Store::Woolies
     }
 
+    fn take_raw(&mut self) -> serde_json::Value {
+        std::mem::take(&mut self.raw)
+    }
+
     fn try_into_snapshot_and_date(self, date: Date) -> Result<ProductSnapshot> {
         let price = match self.price {
             Some(price) => price,
@@ -120,18 +132,12 @@ impl Product for BundleProduct {
                 if !self.is_in_stock && self.was_price > 0.0 {
                     self.was_price
                 } else {
-                    return Err(Error::ProductConversion(format!(
-                        "Missing price on {}",
-                        self.name
-                    )));
+                    return Err(ConversionError::MissingPrice.into());
                 }
             }
         };
 
-        let subcategory_names = self
-            .addtional_attributes
-            .subcategory_names()
-            .context("Failed to parse nested subcategory names")?;
+        let subcategory_names = self.addtional_attributes.subcategory_names()?;
 
         let category = self.category.code(subcategory_names)?;
 
@@ -145,13 +151,20 @@ impl Product for BundleProduct {
             self.stockcode,
             self.name,
             self.description,
+            self.brand,
+            self.barcode,
             is_weighted,
             unit,
             quantity,
             Store::Woolies,
             category,
         );
-        Ok(ProductSnapshot::new(product_info, Price::from(price), date))
+        Ok(ProductSnapshot::new(
+            product_info,
+            Price::from(price),
+            Some(Price::from(self.was_price)),
+            date,
+        ))
     }
 }
 
@@ -161,9 +174,13 @@ pub(crate) struct Bundle {
     pub(crate) products: Vec<BundleProduct>,
 }
 
-pub(crate) fn load_snapshot(file: impl Read, date: Date) -> Result<Vec<ProductSnapshot>> {
-    let success = conversion::from_reader::<Category>(file, date)?;
-    Ok(success)
+pub(crate) fn load_snapshot(
+    file: impl Read,
+    date: Date,
+    filter: &CategoryFilter,
+) -> Result<Conversion> {
+    let conversion = conversion::from_reader::<Category>(file, date, filter)?;
+    Ok(conversion)
 }
 
 #[cfg(test)]
@@ -174,6 +191,7 @@ mod test {
     use time::Month;
 
     use super::*;
+    use crate::errors::Error;
 
     #[test]
     fn test_load_product() {
@@ -289,7 +307,7 @@ mod test {
         let date = Date::from_calendar_date(2024, Month::January, 1).unwrap();
         let err = product.try_into_snapshot_and_date(date).unwrap_err();
         match err {
-            Error::ProductConversion(msg) => assert_eq!(msg, "Missing price on product name"),
+            Error::ProductConversion(err) => assert_eq!(err, ConversionError::MissingPrice),
             _ => panic!("unexpected type err type"),
         }
     }
@@ -346,6 +364,8 @@ mod test {
                 stockcode: 1,
                 name: String::from("product name"),
                 description: String::from("product description"),
+                brand: None,
+                barcode: None,
                 price: Some(1.0),
                 was_price: 1.0,
                 is_in_stock: true,
@@ -355,6 +375,7 @@ mod test {
                 unit: String::from("Each"),
                 category: Rc::new(Category::default()),
                 addtional_attributes: AdditionalAttributes::default(),
+                raw: serde_json::Value::Null,
             }
         }
     }
@@ -370,7 +391,7 @@ mod test {
         let err = product.get_quantity_and_unit(1.0).unwrap_err();
         assert_eq!(
             err.to_string(),
-            "Conversion error: Low quantity for conversion"
+            "Conversion error: low quantity 1 for conversion"
         );
     }
 
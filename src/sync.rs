@@ -1,14 +1,71 @@
 use crate::cache::FsCache;
-use crate::storage::{get_snapshot_path, remove, save_fetch_data};
+use crate::config::{Config, ValidationAction};
+use crate::date::date_serde;
+use crate::manifest::SyncManifest;
+use crate::retry::retry_count;
+use crate::storage::{
+    get_manifest_path, get_snapshot_path, load_manifest, load_previous_manifest, remove,
+    save_fetch_data, save_manifest,
+};
 use crate::stores::{coles, woolies, Store};
+use crate::validation::{is_partial, validate};
+use anyhow::bail;
+use log::warn;
+use serde::Serialize;
 use std::fs::create_dir_all;
-use std::path::{Path, PathBuf};
-use time::OffsetDateTime;
+use std::io::Write;
+use std::path::Path;
+use std::time::Instant;
+use strum::IntoEnumIterator;
+use time::Date;
+
+/// What syncing a single store did
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncSummary {
+    pub store: Store,
+    #[serde(with = "date_serde")]
+    pub day: Date,
+    /// Whether the snapshot already existed and nothing was fetched
+    pub skipped: bool,
+    pub products: usize,
+    /// Pages requested from the store, not counting the ones left in the cache by an earlier try
+    pub pages_fetched: usize,
+    pub pages_cached: usize,
+    pub retries: usize,
+    /// Problems found when validating the snapshot
+    pub issues: usize,
+    /// Whether the snapshot was saved but marked as missing products
+    pub partial: bool,
+    pub duration_secs: f64,
+}
+
+impl SyncSummary {
+    fn new(store: Store, day: Date) -> Self {
+        Self {
+            store,
+            day,
+            skipped: false,
+            products: 0,
+            pages_fetched: 0,
+            pages_cached: 0,
+            retries: 0,
+            issues: 0,
+            partial: false,
+            duration_secs: 0.0,
+        }
+    }
+}
 
 /// Scrapes stores and saves results to a local folder. Individual results will be cached until all
-/// pages of products have been scraped. Calling this function again after failure will fetch any
-/// cached results straight from the cache and only fetch missing items from stores, as long as it
-/// is the same day (in UTC timestamp).
+/// pages of products have been scraped, and the progress of every category is tracked in a
+/// manifest. Calling this function again after failure, or if some pages had to be skipped, will
+/// fetch any cached results straight from the cache and only fetch missing pages from stores, as
+/// long as it is the same day. With `resume` a store whose manifest shows a complete sync of the
+/// day isn't synced again.
+///
+/// The snapshot is saved for `day`, which defaults to the current date in the configured
+/// timezone.
 ///
 /// # Examples
 ///
@@ -17,29 +74,43 @@ use time::OffsetDateTime;
 /// ```
 /// let output_dir = tempfile::tempdir().unwrap();
 /// let cache_path = tempfile::tempdir().unwrap();
+/// let config = hotprices_au_rs::config::Config {
+///   output_dir: output_dir.path().to_path_buf(),
+///   cache_path: cache_path.path().to_path_buf(),
+///   ..Default::default()
+/// };
 /// hotprices_au_rs::sync::do_sync(
 ///   hotprices_au_rs::stores::Store::Woolies,
+///   None,  // day
 ///   true,  // quick
 ///   true,  // print_save_path
 ///   false,  // skip_existing
-///   output_dir.path().to_path_buf(),
-///   cache_path.path().to_path_buf(),
+///   false,  // resume
+///   &config,
 /// ).unwrap();
 /// ```
 ///
 pub fn do_sync(
     store: Store,
+    day: Option<Date>,
     quick: bool,
     print_save_path: bool,
     skip_existing: bool,
-    output_dir: PathBuf,
-    cache_path: PathBuf,
-) -> anyhow::Result<()> {
-    let day = OffsetDateTime::now_utc().date();
-    let snapshot_path = get_snapshot_path(&output_dir, store, day);
+    resume: bool,
+    config: &Config,
+) -> anyhow::Result<SyncSummary> {
+    let start = Instant::now();
+    let retries = retry_count();
+    let day = match day {
+        Some(day) => day,
+        None => config.today()?,
+    };
+    let mut summary = SyncSummary::new(store, day);
+    let snapshot_path = get_snapshot_path(&config.output_dir, store, day);
     if print_save_path {
-        print!("{}", get_save_path(&snapshot_path, &output_dir));
-        return Ok(());
+        print!("{}", get_save_path(&snapshot_path, &config.output_dir));
+        summary.skipped = true;
+        return Ok(summary);
     }
 
     if skip_existing && snapshot_path.exists() {
@@ -47,18 +118,124 @@ pub fn do_sync(
             "Skipping because outputfile {} already exists and requested to skip if output file exists.",
             snapshot_path.to_string_lossy(),
         );
-        return Ok(());
+        summary.skipped = true;
+        return Ok(summary);
     }
 
-    let cache_path = cache_path.join(store.to_string()).join(day.to_string());
+    let cache_path = config
+        .cache_path
+        .join(store.to_string())
+        .join(day.to_string());
+    let manifest_path = get_manifest_path(&config.output_dir, store, day);
+    if resume {
+        let previous = load_manifest(&manifest_path)?;
+        if previous.is_some_and(|m| m.is_complete()) && snapshot_path.exists() {
+            println!("Nothing to resume, {store} was fully synced for {day}");
+            summary.skipped = true;
+            return Ok(summary);
+        }
+    }
     create_dir_all(&cache_path)?;
     let cache: FsCache = FsCache::new(cache_path.clone());
-    let fetch_data = match store {
-        Store::Coles => coles::fetch(&cache, quick)?,
-        Store::Woolies => woolies::fetch(&cache, quick)?,
+    let mut manifest = SyncManifest::new(manifest_path, store, day);
+    let (fetch_data, products) = match store {
+        Store::Coles => coles::fetch(&cache, quick, &config.coles, &mut manifest)?,
+        Store::Woolies => woolies::fetch(&cache, quick, &config.woolies, &mut manifest)?,
+    };
+    // A quick sync only fetches a single page, so there is nothing to validate
+    let issues = if quick {
+        Vec::new()
+    } else {
+        let previous = load_previous_manifest(&config.output_dir, store, day)?;
+        validate(&manifest, previous.as_ref(), &config.validation)
     };
+    for issue in issues.iter() {
+        warn!("Validation of {store} snapshot: {issue}");
+    }
+    let action = config.validation.action;
+    summary.issues = issues.len();
+    summary.partial = is_partial(&issues, action);
+    let blocked = !issues.is_empty() && action == ValidationAction::Block;
+    manifest.set_validation(issues, summary.partial);
+    if blocked {
+        save_manifest(&manifest)?;
+        bail!(
+            "Snapshot of {store} failed validation and was not saved, see {}",
+            manifest.path().to_string_lossy()
+        );
+    }
+
     save_fetch_data(fetch_data, &snapshot_path)?;
-    remove(&cache_path)?;
+    manifest.finish()?;
+    if manifest.is_complete() {
+        remove(&cache_path)?;
+    } else {
+        warn!("Some pages of {store} could not be fetched, run sync with --resume to retry them");
+    }
+    summary.products = products;
+    summary.pages_fetched = cache.fetched();
+    summary.pages_cached = cache.cached();
+    summary.retries = retry_count() - retries;
+    summary.duration_secs = start.elapsed().as_secs_f64();
+    Ok(summary)
+}
+
+/// Prints how far the sync of every store got on `day`, according to the manifests
+pub fn do_status(output_dir: &Path, day: Date) -> anyhow::Result<()> {
+    let mut manifests = Vec::new();
+    for store in Store::iter() {
+        let manifest = load_manifest(&get_manifest_path(output_dir, store, day))?;
+        manifests.push((store, manifest));
+    }
+    write_status(&manifests, std::io::stdout().lock())?;
+    Ok(())
+}
+
+fn write_status(
+    manifests: &[(Store, Option<SyncManifest>)],
+    mut out: impl Write,
+) -> std::io::Result<()> {
+    writeln!(
+        out,
+        "{:<8} {:<11} {:>10} {:>9} {:>9} {:>6}",
+        "STORE", "STATUS", "CATEGORIES", "PRODUCTS", "EXPECTED", "PAGES"
+    )?;
+    for (store, manifest) in manifests {
+        let Some(manifest) = manifest else {
+            writeln!(out, "{:<8} not started", store.to_string())?;
+            continue;
+        };
+        let categories = manifest.categories();
+        let done = categories.values().filter(|c| c.is_complete()).count();
+        let products: usize = categories.values().map(|c| c.products).sum();
+        let expected: i64 = categories
+            .values()
+            .filter_map(|c| c.expected_products)
+            .sum();
+        let pages: usize = categories.values().map(|c| c.pages).sum();
+        writeln!(
+            out,
+            "{:<8} {:<11} {:>10} {:>9} {:>9} {:>6}",
+            store.to_string(),
+            manifest.status(),
+            format!("{done}/{}", categories.len()),
+            products,
+            expected,
+            pages
+        )?;
+        for issue in manifest.issues() {
+            writeln!(out, "  {issue}")?;
+        }
+        for (name, category) in categories.iter() {
+            if let Some(error) = &category.error {
+                writeln!(out, "  {name}: {error}")?;
+            } else if !category.failed_pages.is_empty() {
+                let pages = category.failed_pages.iter().map(|p| p.to_string());
+                let pages: Vec<String> = pages.collect();
+                writeln!(out, "  {name}: failed pages {}", pages.join(", "))?;
+            }
+        }
+    }
     Ok(())
 }
 
@@ -71,9 +248,12 @@ fn get_save_path<'a>(snapshot_path: &'a Path, base_dir: &Path) -> std::borrow::C
 
 #[cfg(test)]
 mod test {
+    use std::path::PathBuf;
+
     use time::macros::datetime;
 
     use super::*;
+    use crate::manifest::CategoryProgress;
 
     #[test]
     fn save_path() {
@@ -84,4 +264,40 @@ mod test {
         let res = get_save_path(&snapshot_path, &output_dir);
         assert_eq!(res, "coles/2020-12-01.json.gz");
     }
+
+    #[test]
+    fn status() {
+        let dir = tempfile::tempdir().unwrap();
+        let day = datetime!(2024-01-01 0:00 UTC).date();
+        let mut manifest = SyncManifest::new(dir.path().join("coles.json"), Store::Coles, day);
+        let progress = |failed_pages| CategoryProgress {
+            expected_products: Some(96),
+            products: 96,
+            pages: 2,
+            failed_pages,
+            error: None,
+        };
+        manifest
+            .record(String::from("bakery"), progress(vec![]))
+            .unwrap();
+        manifest
+            .record(String::from("dairy"), progress(vec![3]))
+            .unwrap();
+        manifest.finish().unwrap();
+
+        let mut out = Vec::new();
+        write_status(
+            &[(Store::Coles, Some(manifest)), (Store::Woolies, None)],
+            &mut out,
+        )
+        .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(
+            lines[1],
+            "coles    partial            1/2       192       192      4"
+        );
+        assert_eq!(lines[2], "  dairy: failed pages 3");
+        assert_eq!(lines[3], "woolies  not started");
+    }
 }
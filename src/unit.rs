@@ -1,10 +1,11 @@
 use lazy_static::lazy_static;
 use regex::Regex;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use crate::errors::Error;
+use crate::errors::ConversionError;
 
-#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema, Clone, Copy)]
 pub(crate) enum Unit {
     #[serde(rename = "ea")]
     Each,
@@ -14,17 +15,71 @@ pub(crate) enum Unit {
     Millilitre,
     #[serde(rename = "cm")]
     Centimetre,
+    #[serde(rename = "m2")]
+    SquareMetre,
+    #[serde(rename = "loads")]
+    Loads,
+    #[serde(rename = "sheets")]
+    Sheets,
+    #[serde(rename = "servings")]
+    Servings,
+}
+
+impl Unit {
+    /// Quantity of this unit that a unit price refers to, e.g. 1000g for a price per kg
+    pub(crate) fn unit_price_quantity(&self) -> f64 {
+        match self {
+            Self::Each => 1.0,
+            Self::Grams => 1000.0,
+            Self::Millilitre => 1000.0,
+            Self::Centimetre => 100.0,
+            Self::SquareMetre => 1.0,
+            Self::Loads => 1.0,
+            // Shelf labels quote paper products per 100 sheets
+            Self::Sheets => 100.0,
+            Self::Servings => 1.0,
+        }
+    }
+
+    /// Short name of the unit as used in the canonical output, e.g. "g"
+    pub(crate) fn symbol(&self) -> &'static str {
+        match self {
+            Self::Each => "ea",
+            Self::Grams => "g",
+            Self::Millilitre => "ml",
+            Self::Centimetre => "cm",
+            Self::SquareMetre => "m2",
+            Self::Loads => "loads",
+            Self::Sheets => "sheets",
+            Self::Servings => "servings",
+        }
+    }
+
+    /// What a unit price refers to, e.g. "kg" for a price per kg
+    pub(crate) fn unit_price_label(&self) -> &'static str {
+        match self {
+            Self::Each => "ea",
+            Self::Grams => "kg",
+            Self::Millilitre => "L",
+            Self::Centimetre => "m",
+            Self::SquareMetre => "m²",
+            Self::Loads => "load",
+            Self::Sheets => "100 sheets",
+            Self::Servings => "serve",
+        }
+    }
 }
 
 lazy_static! {
-    static ref UNIT_REGEX: Regex = Regex::new(r"(?P<quantity>[0-9]+) ?(?P<unit>[a-z]+)").unwrap();
+    static ref UNIT_REGEX: Regex =
+        Regex::new(r"(?P<quantity>[0-9]+) ?(?P<unit>sq ?m|square metres?|m2|[a-z]+)").unwrap();
     static ref EACH_WORDS: Vec<&'static str> = vec![
-        "ea", "each", "pk", "pack", "bunch", "sheets", "sachets", "capsules", "ss", "set", "pair",
-        "pairs", "piece", "tablets", "rolls",
+        "ea", "each", "pk", "pack", "bunch", "sachets", "capsules", "ss", "set", "pair", "pairs",
+        "piece", "tablets", "rolls",
     ];
 }
 
-fn normalise_unit(unit: &str) -> anyhow::Result<(f64, Unit)> {
+pub(crate) fn normalise_unit(unit: &str) -> Result<(f64, Unit), ConversionError> {
     let (factor, unit) = match unit {
         // Grams
         "g" => (1.0, Unit::Grams),
@@ -39,41 +94,44 @@ fn normalise_unit(unit: &str) -> anyhow::Result<(f64, Unit)> {
         "cm" => (1.0, Unit::Centimetre),
         "m" | "metre" => (100.0, Unit::Centimetre),
 
+        // Square metre
+        "m2" | "sqm" | "sq m" | "square metre" | "square metres" => (1.0, Unit::SquareMetre),
+
+        // Loads, e.g. laundry powder
+        "load" | "loads" | "wash" | "washes" => (1.0, Unit::Loads),
+
+        // Sheets, e.g. paper towels
+        "sheet" | "sheets" => (1.0, Unit::Sheets),
+
+        // Servings, e.g. supplements
+        "serve" | "serves" | "serving" | "servings" => (1.0, Unit::Servings),
+
         // Each
         "dozen" => (12.0, Unit::Each),
         x if EACH_WORDS.contains(&x) => (1.0, Unit::Each),
 
         _ => {
-            let err = Error::ProductConversion(format!("unknown unit: {unit}"));
-            return Err(anyhow::Error::from(err));
+            return Err(ConversionError::UnknownUnit {
+                unit: unit.to_string(),
+            })
         }
     };
     Ok((factor, unit))
 }
 
-pub(crate) fn parse_str_unit(size: &str) -> anyhow::Result<(f64, Unit)> {
+pub(crate) fn parse_str_unit(size: &str) -> Result<(f64, Unit), ConversionError> {
     let size = size.to_lowercase();
-    let captures = UNIT_REGEX
-        .captures(&size)
-        .ok_or(Error::ProductConversion(format!(
-            "regex didn't match for {size}"
-        )))?;
+    let unparseable = || ConversionError::UnparseableSize { raw: size.clone() };
+    let captures = UNIT_REGEX.captures(&size).ok_or_else(unparseable)?;
 
     let quantity: f64 = captures
         .name("quantity")
-        .ok_or(Error::ProductConversion(format!(
-            "missing field quantity in {size}"
-        )))?
+        .ok_or_else(unparseable)?
         .as_str()
         .parse()
-        .map_err(|e| Error::ProductConversion(format!("can't parse quantity as f64: {e}")))?;
-
-    let unit = captures
-        .name("unit")
-        .ok_or(Error::ProductConversion(format!(
-            "missing field unit for {size}"
-        )))?
-        .as_str();
+        .map_err(|_| unparseable())?;
+
+    let unit = captures.name("unit").ok_or_else(unparseable)?.as_str();
     let (factor, unit) = normalise_unit(unit)?;
     let quantity = quantity * factor;
 
@@ -84,6 +142,7 @@ pub(crate) fn parse_str_unit(size: &str) -> anyhow::Result<(f64, Unit)> {
 mod test {
 
     use super::{parse_str_unit, Unit};
+    use crate::errors::ConversionError;
 
     #[test]
     fn test_unit_from_size() {
@@ -110,7 +169,6 @@ mod test {
         assert_eq!(parse_str_unit("10 pack").unwrap(), (10.0, Unit::Each));
         assert_eq!(parse_str_unit("10pk").unwrap(), (10.0, Unit::Each));
         assert_eq!(parse_str_unit("10 bunch").unwrap(), (10.0, Unit::Each));
-        assert_eq!(parse_str_unit("10 sheets").unwrap(), (10.0, Unit::Each));
         assert_eq!(parse_str_unit("10 sachets").unwrap(), (10.0, Unit::Each));
         assert_eq!(parse_str_unit("10 capsules").unwrap(), (10.0, Unit::Each));
         assert_eq!(parse_str_unit("10 ss").unwrap(), (10.0, Unit::Each));
@@ -121,5 +179,69 @@ mod test {
         assert_eq!(parse_str_unit("500 tablets").unwrap(), (500.0, Unit::Each));
         assert_eq!(parse_str_unit("12 rolls").unwrap(), (12.0, Unit::Each));
         assert_eq!(parse_str_unit("2 dozen").unwrap(), (24.0, Unit::Each));
+
+        // Square metre
+        assert_eq!(parse_str_unit("5m2").unwrap(), (5.0, Unit::SquareMetre));
+        assert_eq!(parse_str_unit("5 sqm").unwrap(), (5.0, Unit::SquareMetre));
+        assert_eq!(parse_str_unit("5 sq m").unwrap(), (5.0, Unit::SquareMetre));
+        assert_eq!(
+            parse_str_unit("5 square metres").unwrap(),
+            (5.0, Unit::SquareMetre)
+        );
+
+        // Loads
+        assert_eq!(parse_str_unit("40 washes").unwrap(), (40.0, Unit::Loads));
+        assert_eq!(parse_str_unit("1 wash").unwrap(), (1.0, Unit::Loads));
+        assert_eq!(parse_str_unit("20 loads").unwrap(), (20.0, Unit::Loads));
+
+        // Sheets
+        assert_eq!(parse_str_unit("10 sheets").unwrap(), (10.0, Unit::Sheets));
+        assert_eq!(parse_str_unit("1 sheet").unwrap(), (1.0, Unit::Sheets));
+
+        // Servings
+        assert_eq!(
+            parse_str_unit("30 servings").unwrap(),
+            (30.0, Unit::Servings)
+        );
+        assert_eq!(parse_str_unit("12 serves").unwrap(), (12.0, Unit::Servings));
+    }
+
+    #[test]
+    fn test_unit_errors() {
+        assert_eq!(
+            parse_str_unit("big").unwrap_err(),
+            ConversionError::UnparseableSize {
+                raw: String::from("big")
+            }
+        );
+        assert_eq!(
+            parse_str_unit("5 bananas").unwrap_err(),
+            ConversionError::UnknownUnit {
+                unit: String::from("bananas")
+            }
+        );
+    }
+
+    #[test]
+    fn test_unit_serde() {
+        let units =
+            serde_json::to_value([Unit::SquareMetre, Unit::Loads, Unit::Sheets, Unit::Servings])
+                .unwrap();
+        assert_eq!(
+            units,
+            serde_json::json!(["m2", "loads", "sheets", "servings"])
+        );
+    }
+
+    #[test]
+    fn test_unit_price_quantity() {
+        assert_eq!(Unit::Each.unit_price_quantity(), 1.0);
+        assert_eq!(Unit::Grams.unit_price_quantity(), 1000.0);
+        assert_eq!(Unit::Millilitre.unit_price_quantity(), 1000.0);
+        assert_eq!(Unit::Centimetre.unit_price_quantity(), 100.0);
+        assert_eq!(Unit::SquareMetre.unit_price_quantity(), 1.0);
+        assert_eq!(Unit::Loads.unit_price_quantity(), 1.0);
+        assert_eq!(Unit::Sheets.unit_price_quantity(), 100.0);
+        assert_eq!(Unit::Servings.unit_price_quantity(), 1.0);
     }
 }
@@ -0,0 +1,239 @@
+//! Checks that a fetched snapshot isn't missing products before it's saved, by comparing the
+//! product count of every category with the count the store reported and with the previous
+//! snapshot
+use std::fmt::Display;
+
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+use crate::config::{ValidationAction, ValidationConfig};
+use crate::manifest::SyncManifest;
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub(crate) enum Issue {
+    /// Fewer products than the store reported for the category
+    #[serde(rename_all = "camelCase")]
+    Missing {
+        category: String,
+        expected: i64,
+        products: usize,
+    },
+    /// Fewer products than in the previous snapshot, or none if the category wasn't fetched
+    #[serde(rename_all = "camelCase")]
+    Drop {
+        category: String,
+        previous: usize,
+        products: usize,
+    },
+    /// Pages or the whole category couldn't be fetched, so products are missing no matter how few
+    #[serde(rename_all = "camelCase")]
+    Incomplete {
+        category: String,
+        failed_pages: Vec<i32>,
+        error: Option<String>,
+    },
+}
+
+impl Display for Issue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Issue::Missing {
+                category,
+                expected,
+                products,
+            } => write!(
+                f,
+                "{category} has {products} of {expected} products reported by the store"
+            ),
+            Issue::Drop {
+                category,
+                previous,
+                products,
+            } => write!(
+                f,
+                "{category} has {products} products, down from {previous} in the previous snapshot"
+            ),
+            Issue::Incomplete {
+                category,
+                error: Some(error),
+                ..
+            } => write!(f, "{category} failed: {error}"),
+            Issue::Incomplete {
+                category,
+                failed_pages,
+                error: None,
+            } => write!(
+                f,
+                "{category} is missing pages {}",
+                failed_pages.iter().join(", ")
+            ),
+        }
+    }
+}
+
+/// Whether a snapshot with `issues` is saved but marked as partial. Snapshots with failed pages
+/// are partial even if the action only warns, as their missing products are certain.
+pub(crate) fn is_partial(issues: &[Issue], action: ValidationAction) -> bool {
+    match action {
+        ValidationAction::Block => false,
+        ValidationAction::Partial => !issues.is_empty(),
+        ValidationAction::Warn => issues
+            .iter()
+            .any(|issue| matches!(issue, Issue::Incomplete { .. })),
+    }
+}
+
+/// Whether `products` is less than `reference` by more than the share `max`
+fn below(products: usize, reference: f64, max: f64) -> bool {
+    (products as f64) < reference * (1.0 - max)
+}
+
+/// Compares the fetched categories with the totals reported by the store and with the `previous`
+/// snapshot of the store
+pub(crate) fn validate(
+    manifest: &SyncManifest,
+    previous: Option<&SyncManifest>,
+    config: &ValidationConfig,
+) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    for (category, progress) in manifest.categories() {
+        if !progress.is_complete() {
+            issues.push(Issue::Incomplete {
+                category: category.clone(),
+                failed_pages: progress.failed_pages.clone(),
+                error: progress.error.clone(),
+            });
+        }
+        if let Some(expected) = progress.expected_products {
+            if below(progress.products, expected as f64, config.max_missing) {
+                issues.push(Issue::Missing {
+                    category: category.clone(),
+                    expected,
+                    products: progress.products,
+                });
+            }
+        }
+    }
+    let Some(previous) = previous else {
+        return issues;
+    };
+    for (category, before) in previous.categories() {
+        let products = manifest
+            .categories()
+            .get(category)
+            .map_or(0, |progress| progress.products);
+        if below(products, before.products as f64, config.max_drop) {
+            issues.push(Issue::Drop {
+                category: category.clone(),
+                previous: before.products,
+                products,
+            });
+        }
+    }
+    issues
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use time::{Date, Month};
+
+    use super::*;
+    use crate::manifest::CategoryProgress;
+    use crate::stores::Store;
+
+    fn manifest(categories: &[(&str, Option<i64>, usize)]) -> SyncManifest {
+        let day = Date::from_calendar_date(2024, Month::January, 1).unwrap();
+        let mut manifest = SyncManifest::new(PathBuf::new(), Store::Coles, day);
+        for (category, expected_products, products) in categories {
+            manifest.insert(
+                category.to_string(),
+                CategoryProgress {
+                    expected_products: *expected_products,
+                    products: *products,
+                    ..Default::default()
+                },
+            );
+        }
+        manifest
+    }
+
+    #[test]
+    fn missing() {
+        let config = ValidationConfig::default();
+        // 5% of 100 products may be missing
+        let fetched = manifest(&[("bakery", Some(100), 95), ("dairy", Some(100), 94)]);
+        assert_eq!(
+            validate(&fetched, None, &config),
+            vec![Issue::Missing {
+                category: String::from("dairy"),
+                expected: 100,
+                products: 94
+            }]
+        );
+    }
+
+    #[test]
+    fn failed_page_within_tolerance() {
+        let config = ValidationConfig::default();
+        let mut fetched = manifest(&[("bakery", Some(100), 99)]);
+        fetched.insert(
+            String::from("dairy"),
+            CategoryProgress {
+                expected_products: Some(100),
+                products: 98,
+                failed_pages: vec![3],
+                ..Default::default()
+            },
+        );
+        let issues = validate(&fetched, None, &config);
+        assert_eq!(
+            issues,
+            vec![Issue::Incomplete {
+                category: String::from("dairy"),
+                failed_pages: vec![3],
+                error: None
+            }]
+        );
+        assert_eq!(issues[0].to_string(), "dairy is missing pages 3");
+        assert!(is_partial(&issues, ValidationAction::Warn));
+        assert!(is_partial(&issues, ValidationAction::Partial));
+        assert!(!is_partial(&issues, ValidationAction::Block));
+
+        let missing = validate(&manifest(&[("dairy", Some(100), 90)]), None, &config);
+        assert!(!is_partial(&missing, ValidationAction::Warn));
+    }
+
+    #[test]
+    fn drop() {
+        let config = ValidationConfig::default();
+        let previous = manifest(&[
+            ("bakery", None, 100),
+            ("dairy", None, 100),
+            ("meat", None, 50),
+        ]);
+        let fetched = manifest(&[("bakery", None, 80), ("dairy", None, 79)]);
+        let issues = validate(&fetched, Some(&previous), &config);
+        assert_eq!(
+            issues,
+            vec![
+                Issue::Drop {
+                    category: String::from("dairy"),
+                    previous: 100,
+                    products: 79
+                },
+                Issue::Drop {
+                    category: String::from("meat"),
+                    previous: 50,
+                    products: 0
+                }
+            ]
+        );
+        assert_eq!(
+            issues[1].to_string(),
+            "meat has 0 products, down from 50 in the previous snapshot"
+        );
+    }
+}